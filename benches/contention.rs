@@ -0,0 +1,69 @@
+//! Readers racing a writer that inserts sessions whose handles are costly to clone
+//!
+//! Run with `cargo bench --bench contention`.
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use session::SessionLayer;
+
+const READERS: usize = 4;
+const INSERTS: u32 = 2_000;
+
+/// Spends a while in every clone, like a handle deep-copying its state
+#[derive(Debug)]
+struct CostlyClone(u32);
+impl Clone for CostlyClone {
+    fn clone(&self) -> Self {
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_micros(20) {
+            std::hint::spin_loop();
+        }
+        Self(self.0)
+    }
+}
+
+fn main() {
+    let layer = SessionLayer::<u32, CostlyClone>::new_unbounded();
+    let done = Arc::new(AtomicBool::new(false));
+    let readers = (0..READERS)
+        .map(|_| {
+            let layer = Arc::clone(&layer);
+            let done = Arc::clone(&done);
+            std::thread::spawn(move || {
+                let mut reads = 0_u64;
+                let mut waited = Duration::ZERO;
+                while !done.load(Ordering::Relaxed) {
+                    let start = Instant::now();
+                    std::hint::black_box(layer.contains_and_touch(&(reads as u32 % INSERTS)));
+                    waited += start.elapsed();
+                    reads += 1;
+                }
+                (reads, waited)
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let start = Instant::now();
+    for key in 0..INSERTS {
+        std::hint::black_box(layer.get_or_insert_with(key, || CostlyClone(key)));
+    }
+    let inserting = start.elapsed();
+    done.store(true, Ordering::Relaxed);
+
+    let (reads, waited) = readers
+        .into_iter()
+        .map(|reader| reader.join().unwrap())
+        .fold((0, Duration::ZERO), |(reads, waited), (r, w)| {
+            (reads + r, waited + w)
+        });
+    println!(
+        "{INSERTS} get_or_insert_with in {inserting:?}, {reads} reads by {READERS} readers at {:?} each",
+        waited / reads.max(1) as u32,
+    );
+}
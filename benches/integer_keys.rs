@@ -0,0 +1,30 @@
+//! Lookups by integer keys hashed with SipHash against [`SessionLayerBuilder::integer_keys`]
+//!
+//! Run with `cargo bench --bench integer_keys`.
+
+use std::time::{Duration, Instant};
+
+use session::SessionLayerBuilder;
+
+const SESSIONS: u64 = 100_000;
+const ROUNDS: u32 = 20;
+
+fn time(builder: SessionLayerBuilder<u64, u64>) -> Duration {
+    let layer = builder.build().unwrap();
+    layer.insert_many_lenient((0..SESSIONS).map(|key| (key, key)));
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        for key in 0..SESSIONS {
+            std::hint::black_box(layer.contains_key(&key));
+        }
+    }
+    start.elapsed() / (ROUNDS * SESSIONS as u32)
+}
+
+fn main() {
+    let sip = time(SessionLayerBuilder::unbounded());
+    let integer = time(SessionLayerBuilder::unbounded().integer_keys());
+    println!(
+        "lookup among {SESSIONS} sessions: {sip:?} with SipHash, {integer:?} with integer keys"
+    );
+}
@@ -0,0 +1,36 @@
+//! One [`SessionLayer::remove_many`] against a loop of [`SessionLayer::remove`]
+//!
+//! Run with `cargo bench --bench remove_many`.
+
+use std::time::{Duration, Instant};
+
+use session::SessionLayer;
+
+const SESSIONS: u32 = 100_000;
+const ROUNDS: u32 = 10;
+
+fn time(remove: impl Fn(&SessionLayer<u32, u32>, &[u32])) -> Duration {
+    let keys = (0..SESSIONS).collect::<Vec<_>>();
+    let mut total = Duration::ZERO;
+    for _ in 0..ROUNDS {
+        let layer = SessionLayer::new_unbounded();
+        layer.insert_many_lenient(keys.iter().map(|&key| (key, key)));
+        let start = Instant::now();
+        remove(&layer, &keys);
+        total += start.elapsed();
+        assert!(layer.is_empty());
+    }
+    total / ROUNDS
+}
+
+fn main() {
+    let single = time(|layer, keys| {
+        for key in keys {
+            std::hint::black_box(layer.remove(key));
+        }
+    });
+    let batch = time(|layer, keys| {
+        std::hint::black_box(layer.remove_many(keys));
+    });
+    println!("removing {SESSIONS} sessions: {single:?} one by one, {batch:?} in a batch");
+}
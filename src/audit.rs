@@ -0,0 +1,77 @@
+use std::{collections::VecDeque, time::Instant};
+
+use crate::{event::SessionEvent, lock::Mutex};
+
+/// One operation remembered by the audit log, see [`crate::SessionLayerBuilder::audit_capacity`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditRecord {
+    pub at: Instant,
+    pub op: AuditOp,
+    /// See [`crate::SessionLayer::key_label`]
+    pub key_label: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuditOp {
+    Insert,
+    /// An insertion rejected since the key was taken
+    Collision,
+    /// Only recorded if [`crate::SessionLayerBuilder::access_events`] is enabled, since hits are the bulk of all operations
+    Hit,
+    Remove,
+    Expire,
+    Replace,
+    /// `key_label` is the new key
+    Rekey {
+        from_label: String,
+    },
+}
+
+/// A ring buffer of the latest operations
+#[derive(Debug)]
+pub(crate) struct AuditLog {
+    records: Mutex<VecDeque<AuditRecord>>,
+    capacity: usize,
+}
+impl AuditLog {
+    /// `capacity` must be positive
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            records: Mutex::new(VecDeque::with_capacity(capacity)),
+            capacity,
+        }
+    }
+
+    pub fn record(&self, at: Instant, op: AuditOp, key_label: String) {
+        let record = AuditRecord { at, op, key_label };
+        let mut records = self.records.lock();
+        if records.len() == self.capacity {
+            records.pop_front();
+        }
+        records.push_back(record);
+    }
+
+    /// Keys are only labeled once recorded, so layers without a log pay nothing
+    pub fn record_event<SessionKey>(
+        &self,
+        event: &SessionEvent<SessionKey>,
+        label: impl Fn(&SessionKey) -> String,
+    ) {
+        let op = match event {
+            SessionEvent::Inserted { .. } => AuditOp::Insert,
+            SessionEvent::Accessed { .. } => AuditOp::Hit,
+            SessionEvent::Expired { .. } => AuditOp::Expire,
+            SessionEvent::Removed { .. } => AuditOp::Remove,
+            SessionEvent::Replaced { .. } => AuditOp::Replace,
+            SessionEvent::Rekeyed { from, .. } => AuditOp::Rekey {
+                from_label: label(from),
+            },
+        };
+        self.record(event.at(), op, label(event.key()));
+    }
+
+    /// Oldest first
+    pub fn records(&self) -> Vec<AuditRecord> {
+        self.records.lock().iter().cloned().collect()
+    }
+}
@@ -0,0 +1,333 @@
+use std::{
+    borrow::Borrow,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use crate::{
+    builder::{NewSessionLayerError, SessionLayerBuilder},
+    mut_session::{MutSessionCollision, SessionBusy},
+    runtime::Runtime,
+    scheduler::SweepScheduler,
+    session::SessionLayer,
+    stats::SweepReport,
+    time::TimeSource,
+};
+
+/// Store sessions that can be mutated synchronously
+///
+/// Mirrors [`crate::MutSessionLayer`] but locks a [`std::sync::Mutex`] instead of an async one, so sessions can be mutated from `spawn_blocking` or plain threads.
+/// The layer itself still sweeps on the tokio runtime it is created in, so only [`Self::new_unbounded`] works without one.
+/// Pass a [`Runtime`] or opt out of the background task through [`BlockingMutSessionLayerBuilder`] to use it entirely outside of tokio.
+#[derive(Debug)]
+pub struct BlockingMutSessionLayer<SessionKey, MutSession> {
+    session: Arc<SessionLayer<SessionKey, BlockingSession<MutSession>>>,
+}
+impl<SessionKey, MutSession> BlockingMutSessionLayer<SessionKey, MutSession>
+where
+    SessionKey: Eq + std::hash::Hash + Clone + Sync + Send + 'static,
+    MutSession: Sync + Send + 'static,
+{
+    /// # Panics
+    ///
+    /// Must be called within a tokio runtime, which runs the sweeper.
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            session: SessionLayer::new(timeout),
+        }
+    }
+
+    pub fn try_new(timeout: Duration) -> Result<Self, NewSessionLayerError> {
+        Ok(Self {
+            session: SessionLayer::try_new(timeout)?,
+        })
+    }
+
+    /// Sessions never expire and no background task is spawned
+    pub fn new_unbounded() -> Self {
+        Self {
+            session: SessionLayer::new_unbounded(),
+        }
+    }
+
+    pub fn builder(timeout: Duration) -> BlockingMutSessionLayerBuilder<SessionKey, MutSession> {
+        BlockingMutSessionLayerBuilder::new(timeout)
+    }
+
+    /// Remove idle sessions now instead of waiting for the background task
+    pub fn sweep(&self) -> SweepReport {
+        self.session.sweep()
+    }
+
+    pub fn len(&self) -> usize {
+        self.session.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.session.is_empty()
+    }
+}
+impl<SessionKey, MutSession> BlockingMutSessionLayer<SessionKey, MutSession>
+where
+    SessionKey: std::fmt::Debug + Clone + Eq + std::hash::Hash + Sync + Send + 'static,
+    MutSession: std::fmt::Debug + Sync + Send + 'static,
+{
+    /// Block the current thread until the session is locked, then run `f` on it
+    ///
+    /// The lock is released when `f` returns.
+    pub fn with_mut<Q, R>(&self, key: &Q, f: impl FnOnce(&mut MutSession) -> R) -> Option<R>
+    where
+        SessionKey: Borrow<Q>,
+        Q: ?Sized + Eq + std::hash::Hash,
+    {
+        let session = self.session.get(key)?;
+        let mut mut_session = session.0.lock().unwrap();
+        Some(f(&mut mut_session))
+    }
+
+    pub fn insert(
+        &self,
+        key: SessionKey,
+        mut_session: MutSession,
+    ) -> Result<(), MutSessionCollision> {
+        let session = BlockingSession(Arc::new(Mutex::new(mut_session)));
+        self.session
+            .insert(key, session)
+            .map_err(|_| MutSessionCollision)
+    }
+
+    /// Whether a session is stored under the key, without refreshing it
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        SessionKey: Borrow<Q>,
+        Q: ?Sized + Eq + std::hash::Hash,
+    {
+        self.session.contains_key(key)
+    }
+
+    /// Remove the session and hand back its lock, which threads still in [`Self::with_mut`] hold until they are done
+    pub fn remove<Q>(&self, key: &Q) -> Option<Arc<Mutex<MutSession>>>
+    where
+        SessionKey: Borrow<Q>,
+        Q: ?Sized + Eq + std::hash::Hash,
+    {
+        Some(self.session.remove(key)?.0)
+    }
+
+    /// Remove the session and take back its value, see [`crate::MutSessionLayer::take`]
+    ///
+    /// Fail with [`SessionBusy`] and keep the session if a thread is still in [`Self::with_mut`] on it.
+    /// Return `Ok(None)` if the key is not found.
+    pub fn take<Q>(&self, key: &Q) -> Result<Option<MutSession>, SessionBusy>
+    where
+        SessionKey: Borrow<Q>,
+        Q: ?Sized + Eq + std::hash::Hash,
+    {
+        // Other references can only be made from the map, which is write-locked during the check
+        let Some(session) = self
+            .session
+            .remove_if(key, |session| Arc::strong_count(&session.0) == 1)
+        else {
+            return Ok(None);
+        };
+        let session = session.ok_or(SessionBusy)?;
+        let mutex = Arc::try_unwrap(session.0).map_err(|_| SessionBusy)?;
+        Ok(Some(mutex.into_inner().unwrap()))
+    }
+}
+
+/// Options of a [`BlockingMutSessionLayer`], forwarded to a [`SessionLayerBuilder`]
+#[derive(Debug)]
+pub struct BlockingMutSessionLayerBuilder<SessionKey, MutSession> {
+    session: SessionLayerBuilder<SessionKey, BlockingSession<MutSession>>,
+}
+impl<SessionKey, MutSession> BlockingMutSessionLayerBuilder<SessionKey, MutSession> {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            session: SessionLayerBuilder::new(timeout),
+        }
+    }
+
+    /// Sessions never expire and no background task is spawned
+    pub fn unbounded() -> Self {
+        Self {
+            session: SessionLayerBuilder::unbounded(),
+        }
+    }
+
+    /// See [`SessionLayerBuilder::time_source`]
+    pub fn time_source(mut self, time_source: TimeSource) -> Self {
+        self.session = self.session.time_source(time_source);
+        self
+    }
+
+    /// See [`SessionLayerBuilder::runtime`]
+    pub fn runtime(mut self, runtime: impl Runtime) -> Self {
+        self.session = self.session.runtime(runtime);
+        self
+    }
+
+    /// Sweep only through [`BlockingMutSessionLayer::sweep`], so no runtime is needed
+    pub fn manual_sweep(mut self) -> Self {
+        self.session = self.session.manual_sweep();
+        self
+    }
+
+    /// Sweep on the task of `scheduler`, see [`SessionLayerBuilder::scheduler`]
+    pub fn scheduler(mut self, scheduler: &SweepScheduler) -> Self {
+        self.session = self.session.scheduler(scheduler);
+        self
+    }
+}
+impl<SessionKey, MutSession> BlockingMutSessionLayerBuilder<SessionKey, MutSession>
+where
+    SessionKey: Eq + std::hash::Hash + Clone + Sync + Send + 'static,
+    MutSession: Sync + Send + 'static,
+{
+    pub fn build(
+        self,
+    ) -> Result<BlockingMutSessionLayer<SessionKey, MutSession>, NewSessionLayerError> {
+        Ok(BlockingMutSessionLayer {
+            session: self.session.build()?,
+        })
+    }
+}
+
+/// Satisfy any bounds that [`SessionLayer`] requires
+#[derive(Debug)]
+struct BlockingSession<MutSession>(Arc<Mutex<MutSession>>);
+impl<MutSession> Clone for BlockingSession<MutSession> {
+    fn clone(&self) -> Self {
+        Self(Arc::clone(&self.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Barrier;
+
+    use super::*;
+    use crate::time::ManualClock;
+
+    fn runtime() -> tokio::runtime::Runtime {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn mutations_persist_across_locks() {
+        let runtime = runtime();
+        let layer =
+            runtime.block_on(async { BlockingMutSessionLayer::new(Duration::from_secs(10)) });
+        layer.insert(1, 0).unwrap();
+        layer.with_mut(&1, |n| *n += 1).unwrap();
+        layer.with_mut(&1, |n| *n += 1).unwrap();
+        assert_eq!(layer.with_mut(&1, |n| *n), Some(2));
+        assert!(layer.with_mut(&2, |n| *n).is_none());
+    }
+
+    #[test]
+    fn insert_rejects_collisions() {
+        let runtime = runtime();
+        let layer =
+            runtime.block_on(async { BlockingMutSessionLayer::new(Duration::from_secs(10)) });
+        layer.insert(1, 0).unwrap();
+        assert!(layer.insert(1, 1).is_err());
+        assert_eq!(layer.with_mut(&1, |n| *n), Some(0));
+    }
+
+    #[test]
+    fn concurrent_mutations_are_serialized() {
+        const THREADS: usize = 4;
+        const ROUNDS: usize = 1000;
+        let runtime = runtime();
+        let layer = Arc::new(
+            runtime.block_on(async { BlockingMutSessionLayer::new(Duration::from_secs(10)) }),
+        );
+        layer.insert(1, 0).unwrap();
+        let start = Arc::new(Barrier::new(THREADS));
+        let threads = (0..THREADS)
+            .map(|_| {
+                let layer = Arc::clone(&layer);
+                let start = Arc::clone(&start);
+                std::thread::spawn(move || {
+                    start.wait();
+                    for _ in 0..ROUNDS {
+                        layer
+                            .with_mut(&1, |n| {
+                                // Split the increment so an unserialized write would lose updates
+                                let read = *n;
+                                std::thread::yield_now();
+                                *n = read + 1;
+                            })
+                            .unwrap();
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(layer.with_mut(&1, |n| *n), Some(THREADS * ROUNDS));
+    }
+
+    #[test]
+    fn sessions_expire_without_a_runtime() {
+        let clock = ManualClock::new();
+        let layer = BlockingMutSessionLayer::builder(Duration::from_secs(10))
+            .manual_sweep()
+            .time_source(TimeSource::Manual(clock.clone()))
+            .build()
+            .unwrap();
+        layer.insert(1, 0).unwrap();
+        clock.advance(Duration::from_secs(5));
+        layer.with_mut(&1, |n| *n += 1).unwrap();
+        clock.advance(Duration::from_secs(5));
+        layer.sweep();
+        assert_eq!(layer.with_mut(&1, |n| *n), Some(1));
+        clock.advance(Duration::from_secs(10));
+        layer.sweep();
+        assert!(layer.with_mut(&1, |n| *n).is_none());
+    }
+
+    #[test]
+    fn sessions_can_be_removed_and_taken_back() {
+        let layer = BlockingMutSessionLayer::builder(Duration::from_secs(10))
+            .manual_sweep()
+            .build()
+            .unwrap();
+        assert!(layer.is_empty());
+        layer.insert(1, 10).unwrap();
+        layer.insert(2, 20).unwrap();
+        layer.insert(3, 30).unwrap();
+        assert_eq!(layer.len(), 3);
+        assert!(layer.contains_key(&1));
+
+        let removed = layer.remove(&1).unwrap();
+        assert_eq!(*removed.lock().unwrap(), 10);
+        assert!(!layer.contains_key(&1));
+        assert!(layer.remove(&1).is_none());
+
+        assert_eq!(layer.take(&2), Ok(Some(20)));
+        assert_eq!(layer.take(&2), Ok(None));
+
+        // A thread still mutating the session keeps it in the layer
+        let entered = Barrier::new(2);
+        let release = Barrier::new(2);
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                layer.with_mut(&3, |_| {
+                    entered.wait();
+                    release.wait();
+                })
+            });
+            entered.wait();
+            assert_eq!(layer.take(&3), Err(SessionBusy));
+            release.wait();
+        });
+        assert_eq!(layer.take(&3), Ok(Some(30)));
+        assert!(layer.is_empty());
+    }
+}
@@ -0,0 +1,508 @@
+use std::{marker::PhantomData, sync::Arc, time::Duration};
+
+use crate::{
+    audit::AuditLog,
+    eviction::FullQueuePolicy,
+    hook::Hook,
+    map::{IntKey, KeyHashing},
+    miss::MissTracker,
+    replication::{Replication, ReplicationSink},
+    runtime::Runtime,
+    scheduler::SweepScheduler,
+    session::{
+        AdmissionDenied, CollisionPolicy, Expiry, ResurrectPolicy, SessionLayer, TimeoutClass,
+    },
+    stats::{SweepReport, Threshold},
+    time::TimeSource,
+    tombstone::Tombstones,
+};
+
+pub(crate) type Admission<SessionKey, SessionHandle> =
+    dyn Fn(&SessionKey, &SessionHandle) -> Result<(), AdmissionDenied> + Send + Sync;
+pub(crate) type KeyLabel<SessionKey> = dyn Fn(&SessionKey) -> String + Send + Sync;
+pub(crate) type KeyNormalizer<SessionKey> = dyn Fn(&SessionKey) -> SessionKey + Send + Sync;
+pub(crate) type LivenessCheck<SessionHandle> = dyn Fn(&SessionHandle) -> bool + Send + Sync;
+pub(crate) type HandleExpiry<SessionHandle> =
+    dyn Fn(&SessionHandle) -> Option<Duration> + Send + Sync;
+pub(crate) type SweepHook = dyn Fn(SweepReport) + Send + Sync;
+pub(crate) type Weigher<SessionHandle> = dyn Fn(&SessionHandle) -> usize + Send + Sync;
+pub(crate) type ExpiringHook<SessionKey, SessionHandle> =
+    dyn Fn(&SessionKey, &SessionHandle) + Send + Sync;
+
+const DEFAULT_EVENT_CAPACITY: usize = 1024;
+
+/// Configure a [`SessionLayer`] before spawning its background task
+#[derive(Debug)]
+pub struct SessionLayerBuilder<SessionKey, SessionHandle, Metadata = ()> {
+    /// [`None`] if sessions never expire
+    pub(crate) timeout: Option<Duration>,
+    pub(crate) event_capacity: usize,
+    pub(crate) access_events: bool,
+    pub(crate) resurrect_policy: ResurrectPolicy,
+    pub(crate) collision_policy: CollisionPolicy,
+    pub(crate) normalize_key: Option<Hook<KeyNormalizer<SessionKey>>>,
+    pub(crate) is_alive: Option<Hook<LivenessCheck<SessionHandle>>>,
+    pub(crate) handle_expiry: Option<Hook<HandleExpiry<SessionHandle>>>,
+    pub(crate) on_expiring: Option<(Duration, Hook<ExpiringHook<SessionKey, SessionHandle>>)>,
+    pub(crate) on_sweep: Option<Hook<SweepHook>>,
+    pub(crate) on_overrun: Option<Hook<SweepHook>>,
+    pub(crate) replication: Option<Replication<SessionKey, SessionHandle>>,
+    pub(crate) audit: Option<AuditLog>,
+    pub(crate) key_label: Option<Hook<KeyLabel<SessionKey>>>,
+    pub(crate) tombstones: Option<Tombstones>,
+    pub(crate) misses: Option<MissTracker>,
+    pub(crate) reservation_timeout: Option<Duration>,
+    pub(crate) admission: Option<Hook<Admission<SessionKey, SessionHandle>>>,
+    pub(crate) insert_rate_limit: Option<(u32, Duration)>,
+    pub(crate) timeout_classes: Vec<(TimeoutClass, Duration)>,
+    pub(crate) eviction_worker: Option<(usize, FullQueuePolicy)>,
+    pub(crate) negative_lookup_filter: usize,
+    pub(crate) expiry_wheel: Option<Duration>,
+    pub(crate) scheduler: Option<SweepScheduler>,
+    pub(crate) thresholds: Vec<Threshold>,
+    pub(crate) key_hashing: KeyHashing,
+    pub(crate) weight_budget: Option<(usize, Hook<Weigher<SessionHandle>>)>,
+    pub(crate) time_source: TimeSource,
+    pub(crate) runtime: Option<Box<dyn Runtime>>,
+    pub(crate) manual_sweep: bool,
+    pub(crate) sweep_after_mutations: u64,
+    _marker: PhantomData<fn() -> (SessionKey, SessionHandle)>,
+    _metadata: PhantomData<fn() -> Metadata>,
+}
+impl<SessionKey, SessionHandle> SessionLayerBuilder<SessionKey, SessionHandle> {
+    pub fn new(timeout: Duration) -> Self {
+        Self::with_timeout(Some(timeout))
+    }
+
+    /// Sessions never expire and no background task is spawned
+    pub fn unbounded() -> Self {
+        Self::with_timeout(None)
+    }
+
+    fn with_timeout(timeout: Option<Duration>) -> Self {
+        Self {
+            timeout,
+            event_capacity: DEFAULT_EVENT_CAPACITY,
+            access_events: false,
+            resurrect_policy: ResurrectPolicy::default(),
+            collision_policy: CollisionPolicy::default(),
+            normalize_key: None,
+            is_alive: None,
+            handle_expiry: None,
+            on_expiring: None,
+            on_sweep: None,
+            on_overrun: None,
+            replication: None,
+            audit: None,
+            key_label: None,
+            tombstones: None,
+            misses: None,
+            reservation_timeout: None,
+            admission: None,
+            insert_rate_limit: None,
+            timeout_classes: vec![],
+            eviction_worker: None,
+            negative_lookup_filter: 0,
+            expiry_wheel: None,
+            scheduler: None,
+            thresholds: vec![],
+            key_hashing: KeyHashing::Random,
+            weight_budget: None,
+            time_source: TimeSource::default(),
+            runtime: None,
+            manual_sweep: false,
+            sweep_after_mutations: 0,
+            _marker: PhantomData,
+            _metadata: PhantomData,
+        }
+    }
+}
+impl<SessionKey, SessionHandle, Metadata> SessionLayerBuilder<SessionKey, SessionHandle, Metadata> {
+    /// Store metadata of type `NewMetadata` alongside every session, see [`SessionLayer::insert_with_metadata`]
+    pub fn metadata<NewMetadata>(
+        self,
+    ) -> SessionLayerBuilder<SessionKey, SessionHandle, NewMetadata> {
+        SessionLayerBuilder {
+            timeout: self.timeout,
+            event_capacity: self.event_capacity,
+            access_events: self.access_events,
+            resurrect_policy: self.resurrect_policy,
+            collision_policy: self.collision_policy,
+            normalize_key: self.normalize_key,
+            is_alive: self.is_alive,
+            handle_expiry: self.handle_expiry,
+            on_expiring: self.on_expiring,
+            on_sweep: self.on_sweep,
+            on_overrun: self.on_overrun,
+            replication: self.replication,
+            audit: self.audit,
+            key_label: self.key_label,
+            tombstones: self.tombstones,
+            misses: self.misses,
+            reservation_timeout: self.reservation_timeout,
+            admission: self.admission,
+            insert_rate_limit: self.insert_rate_limit,
+            timeout_classes: self.timeout_classes,
+            eviction_worker: self.eviction_worker,
+            negative_lookup_filter: self.negative_lookup_filter,
+            expiry_wheel: self.expiry_wheel,
+            scheduler: self.scheduler,
+            thresholds: self.thresholds,
+            key_hashing: self.key_hashing,
+            weight_budget: self.weight_budget,
+            time_source: self.time_source,
+            runtime: self.runtime,
+            manual_sweep: self.manual_sweep,
+            sweep_after_mutations: self.sweep_after_mutations,
+            _marker: PhantomData,
+            _metadata: PhantomData,
+        }
+    }
+
+    /// How many events a subscriber can fall behind before it starts missing them
+    ///
+    /// Must be positive.
+    pub fn event_capacity(mut self, capacity: usize) -> Self {
+        self.event_capacity = capacity;
+        self
+    }
+
+    /// Also publish [`crate::SessionEvent::Accessed`] on every hit
+    ///
+    /// Off by default since it is chatty.
+    pub fn access_events(mut self, enabled: bool) -> Self {
+        self.access_events = enabled;
+        self
+    }
+
+    /// How inserting under a taken key is resolved
+    ///
+    /// [`CollisionPolicy::Reject`] by default.
+    pub fn collision_policy(mut self, policy: CollisionPolicy) -> Self {
+        self.collision_policy = policy;
+        self
+    }
+
+    /// Canonicalize every owned key passed into the layer, e.g. to make keys case-insensitive
+    ///
+    /// Lookups by a borrowed key (`get`, `remove`, and the like) cannot be normalized without an owned round trip, so they are left as is.
+    /// Use their owned counterparts such as [`SessionLayer::get_normalized`] instead, or pass such keys through [`SessionLayer::normalize_key`] first.
+    pub fn normalize_key(
+        mut self,
+        normalize: impl Fn(&SessionKey) -> SessionKey + Send + Sync + 'static,
+    ) -> Self {
+        self.normalize_key = Some(Hook::new(Box::new(normalize)));
+        self
+    }
+
+    /// Treat sessions for which `is_alive` returns `false` as gone
+    ///
+    /// Such sessions are removed when accessed or swept, even if they are not idle yet.
+    /// The check runs on every access, so it must be cheap, e.g. `Sender::is_closed`.
+    pub fn liveness_check(
+        mut self,
+        is_alive: impl Fn(&SessionHandle) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.is_alive = Some(Hook::new(Box::new(is_alive)));
+        self
+    }
+
+    /// Also expire each session once the lifetime its handle declares through [`Expiry`] runs out, e.g. a token at its `exp` claim
+    ///
+    /// The lifetime is read when the session is inserted or its handle replaced, and accesses never extend it, whatever the [`ResurrectPolicy`].
+    /// The timeout still applies, so a session expires at whichever comes first.
+    /// Sweeps run every half of the shortest timeout, so lookups miss a session past its lifetime until the next sweep removes it.
+    /// Without a timeout there is no interval to sweep at, so building fails with [`NewSessionLayerError::UnsweptHandleExpiry`] unless the layer is swept through [`Self::manual_sweep`].
+    pub fn respect_handle_expiry(mut self, respect: bool) -> Self
+    where
+        SessionHandle: Expiry + 'static,
+    {
+        self.handle_expiry = match respect {
+            true => Some(Hook::new(Box::new(Expiry::expires_in))),
+            false => None,
+        };
+        self
+    }
+
+    /// Warn of sessions whose remaining idle time has fallen below `window`, e.g. to ping the peer before the session is lost
+    ///
+    /// Checked by the sweeps, so `window` should be longer than the sweep interval of half the timeout to be caught in time.
+    /// Fires once per session until it is accessed again.
+    /// Runs while the map is read-locked, so it must not call back into the layer.
+    pub fn on_expiring(
+        mut self,
+        window: Duration,
+        on_expiring: impl Fn(&SessionKey, &SessionHandle) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_expiring = Some((window, Hook::new(Box::new(on_expiring))));
+        self
+    }
+
+    /// Call `on_threshold` with the number of sessions once it reaches `level`, e.g. to page when approaching the capacity
+    ///
+    /// Fires once per excursion: only after the number drops below 90% of `level` can it fire again.
+    /// Runs while the map is write-locked, so it must not call back into the layer.
+    /// Can be called several times for several levels.
+    pub fn on_threshold(
+        mut self,
+        level: usize,
+        on_threshold: impl Fn(usize) + Send + Sync + 'static,
+    ) -> Self {
+        self.thresholds
+            .push(Threshold::new(level, Hook::new(Box::new(on_threshold))));
+        self
+    }
+
+    /// Observe every sweep, e.g. to alert on huge batches of removals or on slow sweeps
+    ///
+    /// Called outside the lock after both background and manual sweeps.
+    pub fn on_sweep(mut self, on_sweep: impl Fn(SweepReport) + Send + Sync + 'static) -> Self {
+        self.on_sweep = Some(Hook::new(Box::new(on_sweep)));
+        self
+    }
+
+    /// Observe background sweeps that took longer than their interval, e.g. to alert or to raise the timeout
+    ///
+    /// Called outside the lock right after [`Self::on_sweep`], and also counted in [`SessionLayer::sweep_overruns`].
+    pub fn on_overrun(mut self, on_overrun: impl Fn(SweepReport) + Send + Sync + 'static) -> Self {
+        self.on_overrun = Some(Hook::new(Box::new(on_overrun)));
+        self
+    }
+
+    /// Mirror every insertion, removal, and refresh to `sink`, see [`ReplicationSink`]
+    pub fn replication_sink(mut self, sink: impl ReplicationSink<SessionKey, SessionHandle>) -> Self
+    where
+        SessionHandle: Clone,
+    {
+        self.replication = Some(Replication {
+            sink: Hook::new(Box::new(sink)),
+            clone_session: SessionHandle::clone,
+        });
+        self
+    }
+
+    /// Remember the latest `capacity` operations for [`SessionLayer::audit_log`], e.g. to find out who removed a session and when
+    ///
+    /// Off by default, and a `capacity` of zero turns it off again.
+    /// Keys are recorded by [`SessionLayer::key_label`] only while it is on.
+    pub fn audit_capacity(mut self, capacity: usize) -> Self {
+        self.audit = (capacity != 0).then(|| AuditLog::new(capacity));
+        self
+    }
+
+    /// Turn a key into the identifier shown wherever the layer exposes keys as text, such as the audit log
+    ///
+    /// Defaults to a short hash of the key, so that secret keys such as tokens stay out of logs and metric labels while still correlating.
+    /// Override it to show keys that are safe to disclose.
+    pub fn key_label(
+        mut self,
+        label: impl Fn(&SessionKey) -> String + Send + Sync + 'static,
+    ) -> Self {
+        self.key_label = Some(Hook::new(Box::new(label)));
+        self
+    }
+
+    /// Evict the least recently used sessions whenever their summed `weight` exceeds `max_total_weight`, e.g. to bound memory when handles differ in size
+    ///
+    /// `weight` runs once per insertion under the write lock, so it must be cheap.
+    /// The session being inserted is never evicted for itself, so one heavier than the whole budget stays alone.
+    /// Eviction sorts all sessions by recency under the write lock, which costs O(n log n) but only when over budget.
+    pub fn weight_budget(
+        mut self,
+        max_total_weight: usize,
+        weight: impl Fn(&SessionHandle) -> usize + Send + Sync + 'static,
+    ) -> Self {
+        self.weight_budget = Some((max_total_weight, Hook::new(Box::new(weight))));
+        self
+    }
+
+    /// Hash integer keys with a single multiplication instead of SipHash, see [`crate::SessionLayer::new_integer_keyed`]
+    ///
+    /// Unlike the default, the hash is not randomized, so keys that clients can pick freely may be chosen to collide and degrade lookups.
+    /// Keep it to keys assigned by the server, e.g. sequential connection IDs.
+    pub fn integer_keys(mut self) -> Self
+    where
+        SessionKey: IntKey,
+    {
+        self.key_hashing = KeyHashing::Integer;
+        self
+    }
+
+    /// Run the `on_evict` callbacks of [`SessionLayer::insert_with_on_evict`] on a dedicated thread, queueing up to `capacity` of them
+    ///
+    /// Callbacks doing real work, e.g. network calls, then no longer hold up the sweep or the caller that removed the session.
+    /// They still run one at a time and in order of eviction.
+    /// `policy` decides what happens when the queue is full.
+    pub fn eviction_worker(mut self, capacity: usize, policy: FullQueuePolicy) -> Self {
+        self.eviction_worker = Some((capacity, policy));
+        self
+    }
+
+    /// Vet every session before it enters the layer, e.g. to refuse handles that target a draining backend
+    ///
+    /// Consulted by [`SessionLayer::insert`] and its variants, [`SessionLayer::insert_or_replace`], and [`SessionLayer::try_get_or_insert_with`], which fail with [`crate::InsertError::Denied`] to hand the session back.
+    /// It runs outside the write lock, so it may be slow.
+    pub fn admission(
+        mut self,
+        admission: impl Fn(&SessionKey, &SessionHandle) -> Result<(), AdmissionDenied>
+            + Send
+            + Sync
+            + 'static,
+    ) -> Self {
+        self.admission = Some(Hook::new(Box::new(admission)));
+        self
+    }
+
+    /// Reject [`SessionLayer::insert`], its variants and [`SessionLayer::insert_or_replace`] with [`crate::InsertError::RateLimited`] once they outpace a token bucket of `capacity` tokens refilled one every `refill_interval`
+    ///
+    /// Resists a client opening sessions faster than they expire, which a weight budget alone would answer by evicting legitimate sessions.
+    /// Every attempt takes a token, including the ones that then fail for another reason.
+    /// The bucket starts full and follows [`Self::time_source`].
+    /// `capacity` must be positive.
+    pub fn insert_rate_limit(mut self, capacity: u32, refill_interval: Duration) -> Self {
+        self.insert_rate_limit = Some((capacity, refill_interval));
+        self
+    }
+
+    /// Release keys held by [`SessionLayer::reserve`] but not fulfilled within `timeout`, e.g. those of abandoned handshakes
+    ///
+    /// Defaults to the session timeout.
+    /// Expired reservations are forgotten by the sweeps.
+    pub fn reservation_timeout(mut self, timeout: Duration) -> Self {
+        self.reservation_timeout = Some(timeout);
+        self
+    }
+
+    /// Remember up to `capacity` keys removed within `retention` so that [`SessionLayer::lookup`] can report them as expired
+    ///
+    /// Off by default, and a `capacity` of zero turns it off again.
+    /// Each tombstone costs a few dozen bytes, and sweeps forget the ones past `retention`.
+    pub fn tombstones(mut self, capacity: usize, retention: Duration) -> Self {
+        self.tombstones = (capacity != 0).then(|| Tombstones::new(capacity, retention));
+        self
+    }
+
+    /// Count lookups of keys missing from the map, by key hash over the last `window`, for [`SessionLayer::top_missed`] and [`SessionLayer::misses_for`]
+    ///
+    /// A flood of misses is the trace of a client guessing keys.
+    /// At most about `capacity` hashes are tracked, forgetting the least recently missed first.
+    /// Off by default, and a `capacity` of zero turns it off again.
+    pub fn track_misses(mut self, capacity: usize, window: Duration) -> Self {
+        self.misses = (capacity != 0).then(|| MissTracker::new(capacity, window));
+        self
+    }
+
+    /// Let lookups rule out absent keys through a Bloom filter of `bits` bits before taking the lock, e.g. to shrug off a flood of random keys
+    ///
+    /// A present key is never ruled out, but removed keys linger in the filter until the next sweep rebuilds it under the write lock.
+    /// Size it to several bits per session, since a filter of too few bits rules out little and still costs a rebuild per sweep.
+    /// Off by default, and zero `bits` turns it off again.
+    pub fn negative_lookup_filter(mut self, bits: usize) -> Self {
+        self.negative_lookup_filter = bits;
+        self
+    }
+
+    /// Find idle sessions by bucketing their deadlines into ticks of `granularity` instead of visiting every session on each sweep, e.g. for maps of millions of sessions
+    ///
+    /// A sweep then costs in proportion to the sessions due rather than to all of them, and sessions expire up to one tick late.
+    /// The [`Self::on_expiring`] hook is driven by the wheel too and may run up to one tick late as well.
+    /// Ticks follow [`Self::time_source`].
+    /// [`Self::liveness_check`] and [`Self::negative_lookup_filter`] still visit every session on each sweep.
+    pub fn expiry_wheel(mut self, granularity: Duration) -> Self {
+        self.expiry_wheel = Some(granularity);
+        self
+    }
+
+    /// Measure idle times against this clock
+    ///
+    /// [`TimeSource::Monotonic`] by default.
+    pub fn time_source(mut self, time_source: TimeSource) -> Self {
+        self.time_source = time_source;
+        self
+    }
+
+    /// Host the background task on `runtime`
+    ///
+    /// Defaults to the current tokio runtime if the `tokio` feature is enabled.
+    pub fn runtime(mut self, runtime: impl Runtime) -> Self {
+        self.runtime = Some(Box::new(runtime));
+        self
+    }
+
+    /// Expire sessions of `class` after `timeout` of idleness, see [`SessionLayer::insert_in_class`]
+    ///
+    /// Defining [`TimeoutClass::DEFAULT`] replaces the timeout the builder was created with.
+    /// The background task sweeps every half of the shortest timeout of all classes.
+    pub fn timeout_class(mut self, class: TimeoutClass, timeout: Duration) -> Self {
+        self.timeout_classes.push((class, timeout));
+        self
+    }
+
+    /// How lookups treat a session idle past the timeout that no sweep has removed yet
+    ///
+    /// Defaults to [`ResurrectPolicy::Lenient`], which is how lookups always behaved, so that upgrading does not start dropping sessions of clients that come back between the timeout and the next sweep.
+    /// Pick [`ResurrectPolicy::Strict`] for an exact timeout.
+    pub fn resurrect_policy(mut self, policy: ResurrectPolicy) -> Self {
+        self.resurrect_policy = policy;
+        self
+    }
+
+    /// Also wake the background task to sweep after every `count` writes to the map, so that bursts of churn are reclaimed before the next timed sweep
+    ///
+    /// The writer only signals the background task, so the sweep adds no latency to the write itself.
+    /// Off by default, and a `count` of zero turns it off again.
+    pub fn sweep_after_mutations(mut self, count: u64) -> Self {
+        self.sweep_after_mutations = count;
+        self
+    }
+
+    /// Do not spawn the background task
+    ///
+    /// Spawn [`SessionLayer::purge_loop`] on your own terms or call [`SessionLayer::sweep`] instead, or else idle sessions are never removed.
+    /// No runtime is required then.
+    pub fn manual_sweep(mut self) -> Self {
+        self.manual_sweep = true;
+        self
+    }
+
+    /// Sweep on the task of `scheduler` instead of spawning one, e.g. to keep the wakeups of dozens of layers down to one task
+    ///
+    /// The layer keeps its own sweep interval, and no runtime is required of it.
+    pub fn scheduler(mut self, scheduler: &SweepScheduler) -> Self {
+        self.scheduler = Some(scheduler.clone());
+        self
+    }
+}
+impl<SessionKey, SessionHandle, Metadata> SessionLayerBuilder<SessionKey, SessionHandle, Metadata>
+where
+    SessionKey: Eq + std::hash::Hash + Clone + Sync + Send + 'static,
+    SessionHandle: Sync + Send + 'static,
+    Metadata: Sync + Send + 'static,
+{
+    pub fn build(
+        self,
+    ) -> Result<Arc<SessionLayer<SessionKey, SessionHandle, Metadata>>, NewSessionLayerError> {
+        SessionLayer::from_builder(self)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+#[non_exhaustive]
+pub enum NewSessionLayerError {
+    #[error("session timeout must be positive")]
+    ZeroTimeout,
+    #[error("session layer with a timeout needs a runtime to spawn its cleanup task")]
+    NoRuntime,
+    #[error("event capacity must be positive")]
+    ZeroEventCapacity,
+    #[error("max concurrent loads must be positive")]
+    ZeroConcurrentLoads,
+    #[error("insert rate limit capacity must be positive")]
+    ZeroRateLimitCapacity,
+    #[error("expiry wheel granularity must be positive")]
+    ZeroExpiryGranularity,
+    #[error("failed to spawn the eviction worker thread")]
+    EvictionWorker,
+    #[error("session layer respecting handle expiry needs a timeout or a manual sweep to remove expired handles")]
+    UnsweptHandleExpiry,
+}
@@ -0,0 +1,60 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use crate::runtime::BoxFuture;
+
+/// Run `f` over all `items` with at most `limit` futures in flight, without spawning
+///
+/// A `limit` of zero is treated as one.
+pub(crate) async fn for_each_concurrent<T, Fut>(
+    items: impl IntoIterator<Item = T>,
+    limit: usize,
+    f: impl Fn(T) -> Fut,
+) where
+    Fut: Future<Output = ()>,
+{
+    let mut items = items.into_iter();
+    let mut running = items
+        .by_ref()
+        .take(limit.max(1))
+        .map(|item| Box::pin(f(item)))
+        .collect::<Vec<Pin<Box<Fut>>>>();
+    std::future::poll_fn(|cx: &mut Context<'_>| {
+        let mut i = 0;
+        while i < running.len() {
+            if running[i].as_mut().poll(cx).is_pending() {
+                i += 1;
+                continue;
+            }
+            // The slot is polled again in the next iteration if refilled
+            match items.next() {
+                Some(item) => running[i] = Box::pin(f(item)),
+                None => drop(running.swap_remove(i)),
+            }
+        }
+        match running.is_empty() {
+            true => Poll::Ready(()),
+            false => Poll::Pending,
+        }
+    })
+    .await
+}
+
+/// Return [`None`] if `sleep` finishes before `f`
+pub(crate) async fn timeout<F: Future>(sleep: BoxFuture, f: F) -> Option<F::Output> {
+    let mut sleep = sleep;
+    let mut f = std::pin::pin!(f);
+    std::future::poll_fn(|cx: &mut Context<'_>| {
+        if let Poll::Ready(output) = f.as_mut().poll(cx) {
+            return Poll::Ready(Some(output));
+        }
+        match sleep.as_mut().poll(cx) {
+            Poll::Ready(()) => Poll::Ready(None),
+            Poll::Pending => Poll::Pending,
+        }
+    })
+    .await
+}
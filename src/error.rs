@@ -0,0 +1,206 @@
+use crate::{
+    builder::NewSessionLayerError,
+    mut_session::{
+        FreshnessError, MutInsertError, MutSessionCollision, SessionBusy, TryGetMutError,
+    },
+    session::{
+        AdmissionDenied, AliasError, InsertError, RekeyError, SessionCollision, VersionConflict,
+    },
+};
+
+/// Any failure of the layers, for callers that propagate several kinds of them with `?`
+///
+/// Every error type of the crate converts into it except [`crate::MergeConflict`], which carries keys instead of handles.
+#[derive(Debug, Clone, thiserror::Error)]
+#[non_exhaustive]
+pub enum SessionError<SessionHandle: std::fmt::Debug> {
+    /// Another session is already under the key
+    ///
+    /// From [`SessionCollision`], [`InsertError::Collision`], [`MutInsertError::Collision`], [`MutSessionCollision`], [`RekeyError::Collision`], and [`AliasError::Collision`].
+    /// Holds the rejected handle if the failed operation took one.
+    #[error("session collision")]
+    Collision(Option<SessionHandle>),
+    /// From [`InsertError::Denied`] and [`MutInsertError::Denied`]
+    #[error("{denied}")]
+    Denied {
+        session: SessionHandle,
+        denied: AdmissionDenied,
+    },
+    /// From [`InsertError::RateLimited`] and [`MutInsertError::RateLimited`]
+    #[error("insert rate limited")]
+    RateLimited(SessionHandle),
+    /// From [`TryGetMutError::Closed`] and [`FreshnessError::Closed`]
+    #[error("session layer closed")]
+    Closed,
+    /// From [`TryGetMutError::Busy`], [`SessionBusy`], and [`FreshnessError::Busy`]
+    #[error("session is busy")]
+    Busy,
+    /// From [`RekeyError::NotFound`], [`AliasError::NotFound`], and [`FreshnessError::NotFound`]
+    #[error("session not found")]
+    NotFound,
+    /// From [`FreshnessError::Stale`]
+    #[error("session expires in {remaining:?}")]
+    Stale { remaining: std::time::Duration },
+    #[error(transparent)]
+    VersionConflict(#[from] VersionConflict),
+    #[error(transparent)]
+    NewSessionLayer(#[from] NewSessionLayerError),
+}
+impl<SessionHandle: std::fmt::Debug> From<SessionCollision<SessionHandle>>
+    for SessionError<SessionHandle>
+{
+    fn from(SessionCollision(session): SessionCollision<SessionHandle>) -> Self {
+        Self::Collision(Some(session))
+    }
+}
+impl<SessionHandle: std::fmt::Debug> From<InsertError<SessionHandle>>
+    for SessionError<SessionHandle>
+{
+    fn from(e: InsertError<SessionHandle>) -> Self {
+        match e {
+            InsertError::Collision(session) => Self::Collision(Some(session)),
+            InsertError::Denied { session, denied } => Self::Denied { session, denied },
+            InsertError::RateLimited(session) => Self::RateLimited(session),
+        }
+    }
+}
+impl<MutSession: std::fmt::Debug> From<MutInsertError<MutSession>> for SessionError<MutSession> {
+    fn from(e: MutInsertError<MutSession>) -> Self {
+        match e {
+            MutInsertError::Collision => Self::Collision(None),
+            MutInsertError::Denied {
+                mut_session,
+                denied,
+            } => Self::Denied {
+                session: mut_session,
+                denied,
+            },
+            MutInsertError::RateLimited(mut_session) => Self::RateLimited(mut_session),
+        }
+    }
+}
+impl<SessionHandle: std::fmt::Debug> From<MutSessionCollision> for SessionError<SessionHandle> {
+    fn from(MutSessionCollision: MutSessionCollision) -> Self {
+        Self::Collision(None)
+    }
+}
+impl<SessionHandle: std::fmt::Debug> From<TryGetMutError> for SessionError<SessionHandle> {
+    fn from(e: TryGetMutError) -> Self {
+        match e {
+            TryGetMutError::Closed => Self::Closed,
+            TryGetMutError::Busy => Self::Busy,
+        }
+    }
+}
+impl<SessionHandle: std::fmt::Debug> From<SessionBusy> for SessionError<SessionHandle> {
+    fn from(SessionBusy: SessionBusy) -> Self {
+        Self::Busy
+    }
+}
+impl<SessionHandle: std::fmt::Debug> From<RekeyError> for SessionError<SessionHandle> {
+    fn from(e: RekeyError) -> Self {
+        match e {
+            RekeyError::NotFound => Self::NotFound,
+            RekeyError::Collision => Self::Collision(None),
+        }
+    }
+}
+impl<SessionHandle: std::fmt::Debug> From<AliasError> for SessionError<SessionHandle> {
+    fn from(e: AliasError) -> Self {
+        match e {
+            AliasError::NotFound => Self::NotFound,
+            AliasError::Collision => Self::Collision(None),
+        }
+    }
+}
+impl<SessionHandle: std::fmt::Debug> From<FreshnessError> for SessionError<SessionHandle> {
+    fn from(e: FreshnessError) -> Self {
+        match e {
+            FreshnessError::NotFound => Self::NotFound,
+            FreshnessError::Stale { remaining } => Self::Stale { remaining },
+            FreshnessError::Closed => Self::Closed,
+            FreshnessError::Busy => Self::Busy,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{session::SessionLayer, time::ManualClock, SessionLayerBuilder, TimeSource};
+
+    #[test]
+    fn layer_failures_propagate_with_question_mark() {
+        fn rekey_twice(layer: &SessionLayer<u32, u32>) -> Result<(), SessionError<u32>> {
+            layer.insert(1, 10)?;
+            layer.rekey(&1, 2)?;
+            layer.rekey(&1, 3)?;
+            Ok(())
+        }
+
+        let layer = SessionLayerBuilder::new(Duration::from_secs(10))
+            .manual_sweep()
+            .time_source(TimeSource::Manual(ManualClock::new()))
+            .build()
+            .unwrap();
+        assert!(matches!(rekey_twice(&layer), Err(SessionError::NotFound)));
+        assert!(matches!(
+            rekey_twice(&layer),
+            Err(SessionError::Collision(None))
+        ));
+        assert!(matches!(
+            rekey_twice(&layer),
+            Err(SessionError::Collision(Some(10)))
+        ));
+    }
+
+    #[test]
+    fn conversions_keep_the_handed_back_session() {
+        let denied = AdmissionDenied::new("draining");
+        let e = SessionError::from(MutInsertError::Denied {
+            mut_session: 1,
+            denied: denied.clone(),
+        });
+        assert!(matches!(e, SessionError::Denied { session: 1, .. }));
+        assert_eq!(e.to_string(), "admission denied: draining");
+        assert!(matches!(
+            SessionError::<u32>::from(InsertError::RateLimited(2)),
+            SessionError::RateLimited(2)
+        ));
+        assert!(matches!(
+            SessionError::<u32>::from(MutSessionCollision),
+            SessionError::Collision(None)
+        ));
+        assert!(matches!(
+            SessionError::<u32>::from(TryGetMutError::Closed),
+            SessionError::Closed
+        ));
+        assert!(matches!(
+            SessionError::<u32>::from(SessionBusy),
+            SessionError::Busy
+        ));
+        assert!(matches!(
+            SessionError::<u32>::from(MutInsertError::RateLimited(3)),
+            SessionError::RateLimited(3)
+        ));
+        let remaining = Duration::from_secs(1);
+        assert!(matches!(
+            SessionError::<u32>::from(FreshnessError::Stale { remaining }),
+            SessionError::Stale { remaining: r } if r == remaining
+        ));
+        assert!(matches!(
+            SessionError::<u32>::from(AliasError::NotFound),
+            SessionError::NotFound
+        ));
+        assert!(matches!(
+            SessionError::<u32>::from(VersionConflict { current: Some(3) }),
+            SessionError::VersionConflict(VersionConflict { current: Some(3) })
+        ));
+        assert!(matches!(
+            SessionError::<u32>::from(NewSessionLayerError::ZeroTimeout),
+            SessionError::NewSessionLayer(NewSessionLayerError::ZeroTimeout)
+        ));
+    }
+}
@@ -0,0 +1,58 @@
+use std::time::Instant;
+
+/// Something that happened to a session in a [`crate::SessionLayer`]
+///
+/// Handles are not carried so that the event stays [`Clone`] regardless of the handle type.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SessionEvent<SessionKey> {
+    Inserted {
+        key: SessionKey,
+        at: Instant,
+    },
+    /// Only published if enabled by [`crate::SessionLayerBuilder::access_events`]
+    Accessed {
+        key: SessionKey,
+        at: Instant,
+    },
+    Expired {
+        key: SessionKey,
+        at: Instant,
+    },
+    Removed {
+        key: SessionKey,
+        at: Instant,
+    },
+    Replaced {
+        key: SessionKey,
+        at: Instant,
+    },
+    /// `key` is the new key
+    Rekeyed {
+        from: SessionKey,
+        key: SessionKey,
+        at: Instant,
+    },
+}
+impl<SessionKey> SessionEvent<SessionKey> {
+    pub fn key(&self) -> &SessionKey {
+        match self {
+            SessionEvent::Inserted { key, .. }
+            | SessionEvent::Accessed { key, .. }
+            | SessionEvent::Expired { key, .. }
+            | SessionEvent::Removed { key, .. }
+            | SessionEvent::Replaced { key, .. }
+            | SessionEvent::Rekeyed { key, .. } => key,
+        }
+    }
+
+    pub fn at(&self) -> Instant {
+        match self {
+            SessionEvent::Inserted { at, .. }
+            | SessionEvent::Accessed { at, .. }
+            | SessionEvent::Expired { at, .. }
+            | SessionEvent::Removed { at, .. }
+            | SessionEvent::Replaced { at, .. }
+            | SessionEvent::Rekeyed { at, .. } => *at,
+        }
+    }
+}
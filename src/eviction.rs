@@ -0,0 +1,121 @@
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    mpsc::{self, SyncSender, TrySendError},
+};
+
+/// What a sweep does when the eviction worker falls behind, see [`crate::SessionLayerBuilder::eviction_worker`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FullQueuePolicy {
+    /// Wait for room in the queue, so every callback runs but a slow worker slows down the sweep again
+    #[default]
+    Block,
+    /// Skip the callback along with the session, counted by [`crate::SessionLayer::dropped_evictions`]
+    Drop,
+}
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// A bounded queue of eviction callbacks run in order by a dedicated thread
+///
+/// The thread exits once the layer and all its entries are gone.
+#[derive(Debug)]
+pub(crate) struct EvictionQueue {
+    sender: SyncSender<Job>,
+    policy: FullQueuePolicy,
+    dropped: AtomicU64,
+}
+impl EvictionQueue {
+    pub fn spawn(capacity: usize, policy: FullQueuePolicy) -> std::io::Result<Self> {
+        let (sender, receiver) = mpsc::sync_channel::<Job>(capacity);
+        std::thread::Builder::new()
+            .name("session-eviction".to_owned())
+            .spawn(move || {
+                for job in receiver {
+                    job();
+                }
+            })?;
+        Ok(Self {
+            sender,
+            policy,
+            dropped: AtomicU64::new(0),
+        })
+    }
+
+    pub fn run(&self, job: Job) {
+        match self.policy {
+            // The thread only exits once every sender is dropped
+            FullQueuePolicy::Block => {
+                let _ = self.sender.send(job);
+            }
+            FullQueuePolicy::Drop => match self.sender.try_send(job) {
+                Ok(()) | Err(TrySendError::Disconnected(_)) => (),
+                Err(TrySendError::Full(_)) => {
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                }
+            },
+        }
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    /// Occupy the worker until the returned sender is dropped
+    fn stall(queue: &EvictionQueue) -> SyncSender<()> {
+        let (started, wait_started) = mpsc::sync_channel(0);
+        let (release, wait_release) = mpsc::sync_channel::<()>(0);
+        queue.run(Box::new(move || {
+            started.send(()).unwrap();
+            let _ = wait_release.recv();
+        }));
+        wait_started.recv().unwrap();
+        release
+    }
+
+    fn record(order: &Arc<Mutex<Vec<u32>>>, id: u32) -> Job {
+        let order = Arc::clone(order);
+        Box::new(move || order.lock().unwrap().push(id))
+    }
+
+    /// Wait for the worker to run everything queued so far
+    fn flush(queue: &EvictionQueue) {
+        let (done, wait_done) = mpsc::channel();
+        queue
+            .sender
+            .send(Box::new(move || done.send(()).unwrap()))
+            .unwrap();
+        wait_done.recv().unwrap();
+    }
+
+    #[test]
+    fn block_policy_runs_every_job_in_order() {
+        let queue = EvictionQueue::spawn(1, FullQueuePolicy::Block).unwrap();
+        let order = Arc::new(Mutex::new(vec![]));
+        for id in 0..10 {
+            queue.run(record(&order, id));
+        }
+        flush(&queue);
+        assert_eq!(*order.lock().unwrap(), (0..10).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn drop_policy_skips_jobs_while_the_queue_is_full() {
+        let queue = EvictionQueue::spawn(1, FullQueuePolicy::Drop).unwrap();
+        let order = Arc::new(Mutex::new(vec![]));
+        let release = stall(&queue);
+        queue.run(record(&order, 0));
+        queue.run(record(&order, 1));
+        queue.run(record(&order, 2));
+        assert_eq!(queue.dropped(), 2);
+        drop(release);
+        flush(&queue);
+        assert_eq!(*order.lock().unwrap(), [0]);
+    }
+}
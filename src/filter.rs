@@ -0,0 +1,108 @@
+use std::{
+    hash::{BuildHasher, Hash, RandomState},
+    sync::atomic::{fence, AtomicU64, Ordering},
+};
+
+/// Bits set per key
+const HASHES: u64 = 3;
+
+/// A Bloom filter of the keys in a map, read without any lock
+///
+/// Writers hold the write lock of the map.
+/// Removals only clear bits on [`Self::rebuild`], so between rebuilds the filter holds a superset of the keys and never rules out a present one.
+/// A rebuild is bracketed by an odd generation like a seqlock, and readers overlapping one do not trust the bits.
+#[derive(Debug)]
+pub(crate) struct KeyFilter {
+    hasher: RandomState,
+    bits: Box<[AtomicU64]>,
+    generation: AtomicU64,
+}
+impl KeyFilter {
+    /// Rounded up to whole words
+    pub fn new(bits: usize) -> Self {
+        let words = bits.div_ceil(64).max(1);
+        Self {
+            hasher: RandomState::new(),
+            bits: (0..words).map(|_| AtomicU64::new(0)).collect(),
+            generation: AtomicU64::new(0),
+        }
+    }
+
+    /// The word index and the mask of each bit of a key, by double hashing
+    fn positions<K: ?Sized + Hash>(&self, key: &K) -> impl Iterator<Item = (usize, u64)> {
+        let hash = self.hasher.hash_one(key);
+        let step = hash.rotate_left(32) | 1;
+        let len = self.bits.len() as u64 * 64;
+        (0..HASHES).map(move |i| {
+            let bit = hash.wrapping_add(i.wrapping_mul(step)) % len;
+            ((bit / 64) as usize, 1 << (bit % 64))
+        })
+    }
+
+    pub fn insert<K: ?Sized + Hash>(&self, key: &K) {
+        for (word, mask) in self.positions(key) {
+            self.bits[word].fetch_or(mask, Ordering::Relaxed);
+        }
+    }
+
+    /// Whether the key is certainly not in the map
+    ///
+    /// Inconclusive while a rebuild is in progress.
+    pub fn rules_out<K: ?Sized + Hash>(&self, key: &K) -> bool {
+        let before = self.generation.load(Ordering::Acquire);
+        if !before.is_multiple_of(2) {
+            return false;
+        }
+        let absent = self
+            .positions(key)
+            .any(|(word, mask)| self.bits[word].load(Ordering::Relaxed) & mask == 0);
+        if !absent {
+            return false;
+        }
+        fence(Ordering::Acquire);
+        self.generation.load(Ordering::Relaxed) == before
+    }
+
+    /// Forget removed keys by setting the bits of the remaining ones only
+    pub fn rebuild<'a, K: ?Sized + Hash + 'a>(&self, keys: impl Iterator<Item = &'a K>) {
+        let generation = self.generation.load(Ordering::Relaxed);
+        self.generation.store(generation + 1, Ordering::Relaxed);
+        fence(Ordering::Release);
+        for word in self.bits.iter() {
+            word.store(0, Ordering::Relaxed);
+        }
+        for key in keys {
+            self.insert(key);
+        }
+        self.generation.store(generation + 2, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn present_keys_are_never_ruled_out() {
+        let filter = KeyFilter::new(256);
+        for key in 0..100u32 {
+            filter.insert(&key);
+        }
+        assert!((0..100u32).all(|key| !filter.rules_out(&key)));
+        // Even at under three bits per key some absent keys are ruled out
+        assert!((100..10_000u32).any(|key| filter.rules_out(&key)));
+    }
+
+    #[test]
+    fn rebuild_forgets_removed_keys() {
+        let filter = KeyFilter::new(1 << 16);
+        for key in 0..100u32 {
+            filter.insert(&key);
+        }
+        let kept = [7u32, 42];
+        filter.rebuild(kept.iter());
+        assert!(kept.iter().all(|key| !filter.rules_out(key)));
+        let ruled_out = (0..100u32).filter(|key| filter.rules_out(key)).count();
+        assert!(90 < ruled_out);
+    }
+}
@@ -0,0 +1,25 @@
+use std::ops::Deref;
+
+/// A user callback that can sit in structs deriving [`Debug`]
+pub(crate) struct Hook<F: ?Sized>(Box<F>);
+impl<F: ?Sized> Hook<F> {
+    pub fn new(f: Box<F>) -> Self {
+        Self(f)
+    }
+
+    pub fn into_inner(self) -> Box<F> {
+        self.0
+    }
+}
+impl<F: ?Sized> Deref for Hook<F> {
+    type Target = F;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+impl<F: ?Sized> std::fmt::Debug for Hook<F> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Hook")
+    }
+}
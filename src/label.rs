@@ -0,0 +1,54 @@
+use std::hash::{Hash, Hasher};
+
+/// The default label of a key: a truncated hash, stable across runs and processes of the same build
+///
+/// Not a cryptographic hash, so it hides keys from casual readers of logs, not from anyone able to enumerate the key space.
+pub(crate) fn short_hash<K: Hash + ?Sized>(key: &K) -> String {
+    let mut hasher = Fnv1a::default();
+    key.hash(&mut hasher);
+    let hash = hasher.finish();
+    format!("{:012x}", hash >> 16)
+}
+
+/// 64-bit FNV-1a, unkeyed unlike [`std::collections::hash_map::RandomState`]
+struct Fnv1a(u64);
+impl Default for Fnv1a {
+    fn default() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+}
+impl Hasher for Fnv1a {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= u64::from(byte);
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn short_hash_hides_the_key_but_correlates() {
+        let label = short_hash("secret-token");
+        assert_eq!(label.len(), 12);
+        assert!(!label.contains("secret"));
+        assert_eq!(label, short_hash("secret-token"));
+        assert_ne!(label, short_hash("secret-token2"));
+    }
+
+    #[test]
+    fn short_hash_is_stable_across_runs() {
+        // FNV-1a of the bytes of "a" followed by the terminator that `str` hashes
+        assert_eq!(short_hash("a"), "089bc907b544");
+        assert_eq!(short_hash("a"), short_hash(&"a".to_owned()));
+        assert_eq!(short_hash(&1_u32), short_hash(&1_u32));
+        assert_ne!(short_hash(&1_u32), short_hash(&1_u64));
+    }
+}
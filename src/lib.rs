@@ -1,4 +1,53 @@
+mod audit;
+pub use audit::*;
+mod blocking_mut_session;
+pub use blocking_mut_session::*;
+mod builder;
+pub use builder::*;
+mod concurrent;
+mod error;
+pub use error::*;
+mod event;
+pub use event::*;
+mod eviction;
+pub use eviction::*;
+mod filter;
+mod hook;
+mod label;
+mod lock;
+mod map;
+pub use map::IntKey;
+#[cfg(feature = "tower")]
+mod middleware;
+mod miss;
+#[cfg(feature = "tower")]
+pub use middleware::*;
 mod mut_session;
 pub use mut_session::*;
+mod rate_limit;
+mod reentrancy;
+pub use reentrancy::detect_reentrancy;
+mod replication;
+pub use replication::*;
+mod reservation;
+pub use reservation::*;
+mod runtime;
+pub use runtime::*;
+mod scheduler;
+pub use scheduler::SweepScheduler;
 mod session;
 pub use session::*;
+mod stats;
+pub use stats::*;
+#[cfg(feature = "stream")]
+mod stream;
+#[cfg(feature = "stream")]
+pub use stream::*;
+mod time;
+pub use time::*;
+mod tombstone;
+mod typed_session;
+pub use typed_session::*;
+mod view;
+pub use view::*;
+mod wheel;
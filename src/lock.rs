@@ -0,0 +1,111 @@
+//! Locks that do not poison
+//!
+//! Backed by `parking_lot` if its feature is enabled, or else by thin wrappers around the std locks that ignore poisoning.
+
+#[cfg(feature = "parking_lot")]
+pub(crate) use parking_lot::{Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+#[cfg(not(feature = "parking_lot"))]
+pub(crate) use std::sync::{RwLockReadGuard, RwLockWriteGuard};
+#[cfg(not(feature = "parking_lot"))]
+pub(crate) use std_lock::{Mutex, RwLock};
+
+#[cfg(not(feature = "parking_lot"))]
+mod std_lock {
+    use std::sync::{MutexGuard, PoisonError, RwLockReadGuard, RwLockWriteGuard, TryLockError};
+
+    /// The protected data stay consistent even if a holder panics, so poisoning is ignored
+    #[derive(Debug, Default)]
+    pub(crate) struct RwLock<T>(std::sync::RwLock<T>);
+    impl<T> RwLock<T> {
+        pub fn new(value: T) -> Self {
+            Self(std::sync::RwLock::new(value))
+        }
+
+        pub fn read(&self) -> RwLockReadGuard<'_, T> {
+            self.0.read().unwrap_or_else(PoisonError::into_inner)
+        }
+
+        pub fn write(&self) -> RwLockWriteGuard<'_, T> {
+            self.0.write().unwrap_or_else(PoisonError::into_inner)
+        }
+
+        pub fn try_read(&self) -> Option<RwLockReadGuard<'_, T>> {
+            match self.0.try_read() {
+                Ok(guard) => Some(guard),
+                Err(TryLockError::Poisoned(e)) => Some(e.into_inner()),
+                Err(TryLockError::WouldBlock) => None,
+            }
+        }
+
+        pub fn try_write(&self) -> Option<RwLockWriteGuard<'_, T>> {
+            match self.0.try_write() {
+                Ok(guard) => Some(guard),
+                Err(TryLockError::Poisoned(e)) => Some(e.into_inner()),
+                Err(TryLockError::WouldBlock) => None,
+            }
+        }
+    }
+
+    /// The protected data stay consistent even if a holder panics, so poisoning is ignored
+    #[derive(Debug, Default)]
+    pub(crate) struct Mutex<T>(std::sync::Mutex<T>);
+    impl<T> Mutex<T> {
+        pub fn new(value: T) -> Self {
+            Self(std::sync::Mutex::new(value))
+        }
+
+        pub fn lock(&self) -> MutexGuard<'_, T> {
+            self.0.lock().unwrap_or_else(PoisonError::into_inner)
+        }
+
+        pub fn get_mut(&mut self) -> &mut T {
+            self.0.get_mut().unwrap_or_else(PoisonError::into_inner)
+        }
+
+        pub fn into_inner(self) -> T {
+            self.0.into_inner().unwrap_or_else(PoisonError::into_inner)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    #[test]
+    fn panic_while_held_does_not_poison() {
+        let rw_lock = Arc::new(RwLock::new(0));
+        let mutex = Arc::new(Mutex::new(0));
+        let panicked = std::thread::spawn({
+            let rw_lock = Arc::clone(&rw_lock);
+            let mutex = Arc::clone(&mutex);
+            move || {
+                let _write = rw_lock.write();
+                let _lock = mutex.lock();
+                panic!("holder panics");
+            }
+        })
+        .join();
+        assert!(panicked.is_err());
+        *rw_lock.write() += 1;
+        *mutex.lock() += 1;
+        assert_eq!(*rw_lock.read(), 1);
+        assert_eq!(*mutex.lock(), 1);
+    }
+}
+
+#[cfg(all(test, feature = "parking_lot"))]
+mod parking_lot_tests {
+    use super::*;
+
+    #[test]
+    fn locks_are_backed_by_parking_lot() {
+        let rw_lock = RwLock::new(0);
+        let _read: parking_lot::RwLockReadGuard<'_, i32> = rw_lock.read();
+        let mutex = Mutex::new(0);
+        let _lock: parking_lot::MutexGuard<'_, i32> = mutex.lock();
+    }
+}
@@ -0,0 +1,555 @@
+use std::{
+    borrow::Borrow,
+    collections::{btree_map, hash_map, BTreeMap, HashMap},
+    hash::{BuildHasher, DefaultHasher, Hash, Hasher, RandomState},
+    sync::Arc,
+};
+
+use crate::filter::KeyFilter;
+
+/// A map with at most this many entries is a plain vector
+const SMALL_MAP_CAPACITY: usize = 8;
+/// A hash map shrinking to this many entries turns back into a vector
+///
+/// Lower than [`SMALL_MAP_CAPACITY`] so that a map hovering around the threshold does not convert back and forth.
+const DEMOTE_LEN: usize = SMALL_MAP_CAPACITY / 2;
+/// A hash map using less than `1 / SPARSE_RATIO` of its capacity is worth shrinking
+const SPARSE_RATIO: usize = 4;
+
+/// Integer keys that [`crate::SessionLayer::new_integer_keyed`] can hash without SipHash
+///
+/// Sealed, since the fast hash relies on keys hashing as a single integer.
+pub trait IntKey: sealed::Sealed {}
+impl IntKey for u32 {}
+impl IntKey for u64 {}
+impl IntKey for u128 {}
+mod sealed {
+    pub trait Sealed {}
+    impl Sealed for u32 {}
+    impl Sealed for u64 {}
+    impl Sealed for u128 {}
+}
+
+/// How a [`SessionMap`] hashes its keys once it outgrows the vector
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum KeyHashing {
+    /// SipHash with random keys, resistant to keys chosen to collide
+    #[default]
+    Random,
+    /// One multiplication per integer, see [`IntKey`]
+    Integer,
+}
+
+/// The [`BuildHasher`] picked by a [`KeyHashing`]
+#[derive(Debug, Clone)]
+pub(crate) enum MapHasher {
+    Random(RandomState),
+    Integer,
+}
+impl MapHasher {
+    fn new(hashing: KeyHashing) -> Self {
+        match hashing {
+            KeyHashing::Random => Self::Random(RandomState::new()),
+            KeyHashing::Integer => Self::Integer,
+        }
+    }
+}
+impl BuildHasher for MapHasher {
+    type Hasher = MapHashState;
+
+    fn build_hasher(&self) -> Self::Hasher {
+        match self {
+            Self::Random(random) => MapHashState::Random(random.build_hasher()),
+            Self::Integer => MapHashState::Integer(0),
+        }
+    }
+}
+
+pub(crate) enum MapHashState {
+    Random(DefaultHasher),
+    /// The same mixing as FxHash, with the top bits well spread for the probing of [`HashMap`]
+    Integer(u64),
+}
+impl MapHashState {
+    const SEED: u64 = 0x51_7c_c1_b7_27_22_0a_95;
+
+    fn mix(state: &mut u64, word: u64) {
+        *state = (state.rotate_left(5) ^ word).wrapping_mul(Self::SEED);
+    }
+}
+impl Hasher for MapHashState {
+    fn finish(&self) -> u64 {
+        match self {
+            Self::Random(hasher) => hasher.finish(),
+            Self::Integer(state) => *state,
+        }
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        match self {
+            Self::Random(hasher) => hasher.write(bytes),
+            // Only reached by borrowed forms of the key that do not hash as integers
+            Self::Integer(state) => {
+                for &byte in bytes {
+                    Self::mix(state, u64::from(byte));
+                }
+            }
+        }
+    }
+
+    fn write_u32(&mut self, i: u32) {
+        match self {
+            Self::Random(hasher) => hasher.write_u32(i),
+            Self::Integer(state) => Self::mix(state, u64::from(i)),
+        }
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        match self {
+            Self::Random(hasher) => hasher.write_u64(i),
+            Self::Integer(state) => Self::mix(state, i),
+        }
+    }
+
+    fn write_u128(&mut self, i: u128) {
+        match self {
+            Self::Random(hasher) => hasher.write_u128(i),
+            Self::Integer(state) => {
+                Self::mix(state, i as u64);
+                Self::mix(state, (i >> 64) as u64);
+            }
+        }
+    }
+
+    fn write_usize(&mut self, i: usize) {
+        match self {
+            Self::Random(hasher) => hasher.write_usize(i),
+            Self::Integer(state) => Self::mix(state, i as u64),
+        }
+    }
+}
+
+/// A value that counts against a weight budget
+pub(crate) trait Weighted {
+    /// Must not change while the value is in a map
+    fn weight(&self) -> usize;
+}
+
+/// The keys of a [`SessionMap`] ordered by a hash of them, so that [`crate::SessionLayer::scan`] resumes a page without visiting the keys before it
+#[derive(Debug)]
+struct ScanIndex<K> {
+    hasher: RandomState,
+    /// Keys sharing a hash are kept together so that a page never splits them
+    keys: BTreeMap<u64, Vec<K>>,
+}
+impl<K: Eq + Hash + Clone> ScanIndex<K> {
+    fn new<'a>(hasher: RandomState, keys: impl Iterator<Item = &'a K>) -> Self
+    where
+        K: 'a,
+    {
+        let mut index = Self {
+            hasher,
+            keys: BTreeMap::new(),
+        };
+        for key in keys {
+            index.insert(key);
+        }
+        index
+    }
+
+    fn insert(&mut self, key: &K) {
+        let hash = self.hasher.hash_one(key);
+        self.keys.entry(hash).or_default().push(key.clone());
+    }
+}
+impl<K: Eq + Hash> ScanIndex<K> {
+    fn remove<Q>(&mut self, key: &Q)
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Eq + Hash,
+    {
+        let hash = self.hasher.hash_one(key);
+        let btree_map::Entry::Occupied(mut entry) = self.keys.entry(hash) else {
+            return;
+        };
+        entry.get_mut().retain(|k| k.borrow() != key);
+        if entry.get().is_empty() {
+            entry.remove();
+        }
+    }
+}
+
+/// A map that scans a small vector linearly while it holds few entries and becomes a [`HashMap`] beyond that
+///
+/// Backends hosting only a handful of sessions then skip hashing and keep their entries in one cache-friendly allocation.
+/// All conversions happen inside `&mut self` methods, so they are as consistent as the lock around the map.
+/// So is the total weight of the values, and so are the insertions into the key filter if any.
+#[derive(Debug)]
+pub(crate) struct SessionMap<K, V> {
+    entries: Entries<K, V>,
+    total_weight: usize,
+    hashing: KeyHashing,
+    filter: Option<Arc<KeyFilter>>,
+    /// Built by the first scan, see [`Self::index_for_scan`]
+    scan_index: Option<ScanIndex<K>>,
+}
+#[derive(Debug)]
+enum Entries<K, V> {
+    Small(Vec<(K, V)>),
+    Large(HashMap<K, V, MapHasher>),
+}
+impl<K, V> Default for SessionMap<K, V> {
+    fn default() -> Self {
+        Self::new(KeyHashing::default())
+    }
+}
+impl<K, V> SessionMap<K, V> {
+    pub fn new(hashing: KeyHashing) -> Self {
+        Self {
+            entries: Entries::Small(Vec::new()),
+            total_weight: 0,
+            hashing,
+            filter: None,
+            scan_index: None,
+        }
+    }
+
+    /// Record every key inserted from now on in `filter`
+    pub fn with_filter(mut self, filter: Arc<KeyFilter>) -> Self {
+        self.filter = Some(filter);
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        match &self.entries {
+            Entries::Small(entries) => entries.len(),
+            Entries::Large(map) => map.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        match &self.entries {
+            Entries::Small(entries) => entries.capacity(),
+            Entries::Large(map) => map.capacity(),
+        }
+    }
+
+    /// Only a hash map can be sparse, since a vector never holds more than a few entries anyway
+    pub fn is_sparse(&self) -> bool {
+        match &self.entries {
+            Entries::Small(_) => false,
+            Entries::Large(map) => map.len() < map.capacity() / SPARSE_RATIO,
+        }
+    }
+
+    pub fn total_weight(&self) -> usize {
+        self.total_weight
+    }
+
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        match &self.entries {
+            Entries::Small(entries) => Iter::Small(entries.iter()),
+            Entries::Large(map) => Iter::Large(map.iter()),
+        }
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.iter().map(|(k, _)| k)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &V> {
+        self.iter().map(|(_, v)| v)
+    }
+
+    /// The key filter and the scan index if any stay with the now empty map
+    pub fn drain(&mut self) -> Vec<(K, V)> {
+        let scan_index = self.scan_index.as_ref().map(|index| ScanIndex {
+            hasher: index.hasher.clone(),
+            keys: BTreeMap::new(),
+        });
+        let empty = Self {
+            filter: self.filter.clone(),
+            scan_index,
+            ..Self::new(self.hashing)
+        };
+        match std::mem::replace(self, empty).entries {
+            Entries::Small(entries) => entries,
+            Entries::Large(map) => map.into_iter().collect(),
+        }
+    }
+
+    fn demote_if_small(&mut self) {
+        let Entries::Large(map) = &mut self.entries else {
+            return;
+        };
+        if DEMOTE_LEN < map.len() {
+            return;
+        }
+        let entries = map.drain().collect();
+        self.entries = Entries::Small(entries);
+    }
+}
+impl<K: Eq + Hash, V: Weighted> SessionMap<K, V> {
+    /// Remove and return the entries matching `pred`
+    pub fn extract_if(&mut self, mut pred: impl FnMut(&K, &mut V) -> bool) -> Vec<(K, V)> {
+        let extracted = match &mut self.entries {
+            Entries::Small(entries) => entries
+                .extract_if(.., |(k, v)| pred(k, v))
+                .collect::<Vec<_>>(),
+            Entries::Large(map) => map.extract_if(|k, v| pred(k, v)).collect(),
+        };
+        if let Some(index) = &mut self.scan_index {
+            for (k, _) in &extracted {
+                index.remove::<K>(k);
+            }
+        }
+        self.total_weight -= extracted.iter().map(|(_, v)| v.weight()).sum::<usize>();
+        self.demote_if_small();
+        extracted
+    }
+}
+impl<K: Eq + Hash, V> SessionMap<K, V> {
+    pub fn get_key_value<Q>(&self, key: &Q) -> Option<(&K, &V)>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Eq + Hash,
+    {
+        match &self.entries {
+            Entries::Small(entries) => entries
+                .iter()
+                .find(|(k, _)| k.borrow() == key)
+                .map(|(k, v)| (k, v)),
+            Entries::Large(map) => map.get_key_value(key),
+        }
+    }
+
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Eq + Hash,
+    {
+        self.get_key_value(key).map(|(_, v)| v)
+    }
+
+    /// The weight of the value must stay the same, see [`Weighted::weight`]
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Eq + Hash,
+    {
+        match &mut self.entries {
+            Entries::Small(entries) => entries
+                .iter_mut()
+                .find(|(k, _)| k.borrow() == key)
+                .map(|(_, v)| v),
+            Entries::Large(map) => map.get_mut(key),
+        }
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Eq + Hash,
+    {
+        self.get_key_value(key).is_some()
+    }
+
+    /// Keep the keys ordered by their hash under `hasher` from now on, unless they already are
+    ///
+    /// Costs a clone of every key, once now and then on every insertion.
+    pub fn index_for_scan(&mut self, hasher: &RandomState)
+    where
+        K: Clone,
+    {
+        if self.scan_index.is_none() {
+            self.scan_index = Some(ScanIndex::new(hasher.clone(), self.keys()));
+        }
+    }
+
+    /// The keys with hashes after `after` in the order of the hashes, or [`None`] unless [`Self::index_for_scan`] has been called
+    pub fn scan_after(&self, after: Option<u64>) -> Option<impl Iterator<Item = (u64, &[K])>> {
+        let index = self.scan_index.as_ref()?;
+        let range = match after {
+            Some(after) => index.keys.range(after.saturating_add(1)..),
+            None => index.keys.range(..),
+        };
+        // Nothing follows the largest hash
+        let range = range.filter(move |(hash, _)| after.is_none_or(|after| after < **hash));
+        Some(range.map(|(hash, keys)| (*hash, keys.as_slice())))
+    }
+
+    pub fn shrink_to_fit(&mut self) {
+        match &mut self.entries {
+            Entries::Small(entries) => entries.shrink_to_fit(),
+            Entries::Large(map) => map.shrink_to_fit(),
+        }
+    }
+}
+impl<K: Hash, V> SessionMap<K, V> {
+    /// Clear the removed keys out of the key filter if any, keeping `extra` keys that are not in the map, e.g. aliases
+    pub fn rebuild_filter<'a>(&'a self, extra: impl Iterator<Item = &'a K>)
+    where
+        K: 'a,
+    {
+        if let Some(filter) = &self.filter {
+            filter.rebuild(self.keys().chain(extra));
+        }
+    }
+
+    /// Take over the key filter of `other`, e.g. after swapping a new map in for it
+    pub fn adopt_filter(&mut self, other: &mut Self) {
+        self.filter = other.filter.take();
+        self.rebuild_filter(std::iter::empty());
+    }
+}
+impl<K: Eq + Hash + Clone, V: Weighted> SessionMap<K, V> {
+    /// Return the replaced value if any
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        if let Some(filter) = &self.filter {
+            filter.insert(&key);
+        }
+        self.total_weight += value.weight();
+        let index_key = self.scan_index.as_ref().map(|_| key.clone());
+        let replaced = self.insert_unweighted(key, value);
+        match &replaced {
+            Some(replaced) => self.total_weight -= replaced.weight(),
+            None => {
+                if let (Some(index), Some(key)) = (&mut self.scan_index, &index_key) {
+                    index.insert(key);
+                }
+            }
+        }
+        replaced
+    }
+
+    fn insert_unweighted(&mut self, key: K, value: V) -> Option<V> {
+        let entries = match &mut self.entries {
+            Entries::Small(entries) => entries,
+            Entries::Large(map) => return map.insert(key, value),
+        };
+        if let Some((_, v)) = entries.iter_mut().find(|(k, _)| *k == key) {
+            return Some(std::mem::replace(v, value));
+        }
+        if entries.len() < SMALL_MAP_CAPACITY {
+            entries.push((key, value));
+            return None;
+        }
+        let mut map =
+            HashMap::with_capacity_and_hasher(SMALL_MAP_CAPACITY + 1, MapHasher::new(self.hashing));
+        map.extend(std::mem::take(entries));
+        map.insert(key, value);
+        self.entries = Entries::Large(map);
+        None
+    }
+
+    pub fn remove_entry<Q>(&mut self, key: &Q) -> Option<(K, V)>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Eq + Hash,
+    {
+        let removed = match &mut self.entries {
+            Entries::Small(entries) => {
+                let i = entries.iter().position(|(k, _)| k.borrow() == key)?;
+                Some(entries.swap_remove(i))
+            }
+            Entries::Large(map) => map.remove_entry(key),
+        };
+        if let Some((k, v)) = &removed {
+            self.total_weight -= v.weight();
+            if let Some(index) = &mut self.scan_index {
+                index.remove::<K>(k);
+            }
+        }
+        self.demote_if_small();
+        removed
+    }
+}
+impl<K: Eq + Hash + Clone, V: Weighted> Extend<(K, V)> for SessionMap<K, V> {
+    fn extend<T: IntoIterator<Item = (K, V)>>(&mut self, iter: T) {
+        for (k, v) in iter {
+            self.insert(k, v);
+        }
+    }
+}
+
+pub(crate) enum Iter<'a, K, V> {
+    Small(std::slice::Iter<'a, (K, V)>),
+    Large(hash_map::Iter<'a, K, V>),
+}
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            Self::Small(iter) => iter.next().map(|(k, v)| (k, v)),
+            Self::Large(iter) => iter.next(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    impl Weighted for u32 {
+        fn weight(&self) -> usize {
+            *self as usize
+        }
+    }
+
+    fn is_small<K, V>(map: &SessionMap<K, V>) -> bool {
+        matches!(map.entries, Entries::Small(_))
+    }
+
+    #[test]
+    fn promotes_past_the_small_capacity_and_demotes_when_shrunk() {
+        let mut map = SessionMap::default();
+        for key in 0..SMALL_MAP_CAPACITY as u32 {
+            map.insert(key, 1);
+        }
+        assert!(is_small(&map));
+        map.insert(SMALL_MAP_CAPACITY as u32, 1);
+        assert!(!is_small(&map));
+        assert_eq!(map.len(), SMALL_MAP_CAPACITY + 1);
+
+        for key in 0..(SMALL_MAP_CAPACITY - DEMOTE_LEN) as u32 {
+            assert!(map.remove_entry(&key).is_some());
+        }
+        assert!(!is_small(&map));
+        map.remove_entry(&((SMALL_MAP_CAPACITY - DEMOTE_LEN) as u32));
+        assert!(is_small(&map));
+        assert_eq!(map.len(), DEMOTE_LEN);
+        for key in (SMALL_MAP_CAPACITY - DEMOTE_LEN + 1) as u32..=SMALL_MAP_CAPACITY as u32 {
+            assert_eq!(map.get(&key), Some(&1));
+        }
+    }
+
+    #[test]
+    fn extract_if_demotes_a_shrunk_map() {
+        let mut map = SessionMap::default();
+        for key in 0..16_u32 {
+            map.insert(key, key);
+        }
+        assert_eq!(map.insert(3, 10), Some(3));
+        assert_eq!(map.extract_if(|key, _| 4 <= *key).len(), 12);
+        assert!(is_small(&map));
+        assert_eq!(map.get(&3), Some(&10));
+    }
+
+    #[test]
+    fn total_weight_survives_conversions() {
+        let mut map = SessionMap::default();
+        for key in 0..16_u32 {
+            map.insert(key, key);
+        }
+        assert_eq!(map.insert(3, 10), Some(3));
+        assert_eq!(map.total_weight(), (0..16).sum::<usize>() + 7);
+        map.extract_if(|key, _| 4 <= *key);
+        assert!(is_small(&map));
+        assert_eq!(map.total_weight(), 1 + 2 + 10);
+    }
+}
@@ -0,0 +1,240 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use tokio::sync::{Mutex as TokioMutex, OwnedMutexGuard};
+
+use crate::mut_session::MutSessionLayer;
+
+type KeyExtractor<SessionKey> = dyn Fn(&http::request::Parts) -> Option<SessionKey> + Send + Sync;
+type SessionFactory<MutSession> = dyn Fn() -> MutSession + Send + Sync;
+
+/// What to do if a request carries a key with no session under it
+pub enum NotFoundPolicy<MutSession> {
+    /// Fail the request with [`SessionMiddlewareError::NotFound`]
+    Reject,
+    /// Insert a session made by the factory
+    Create(Arc<SessionFactory<MutSession>>),
+}
+impl<MutSession> Clone for NotFoundPolicy<MutSession> {
+    fn clone(&self) -> Self {
+        match self {
+            Self::Reject => Self::Reject,
+            Self::Create(make) => Self::Create(Arc::clone(make)),
+        }
+    }
+}
+impl<MutSession> std::fmt::Debug for NotFoundPolicy<MutSession> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Reject => write!(f, "Reject"),
+            Self::Create(_) => write!(f, "Create"),
+        }
+    }
+}
+
+/// Attach the session of each request to its extensions as a [`SessionExtension`]
+pub struct SessionMiddlewareLayer<SessionKey, MutSession> {
+    sessions: Arc<MutSessionLayer<SessionKey, MutSession>>,
+    extract_key: Arc<KeyExtractor<SessionKey>>,
+    not_found: NotFoundPolicy<MutSession>,
+}
+impl<SessionKey, MutSession> SessionMiddlewareLayer<SessionKey, MutSession> {
+    pub fn new(
+        sessions: Arc<MutSessionLayer<SessionKey, MutSession>>,
+        extract_key: impl Fn(&http::request::Parts) -> Option<SessionKey> + Send + Sync + 'static,
+        not_found: NotFoundPolicy<MutSession>,
+    ) -> Self {
+        Self {
+            sessions,
+            extract_key: Arc::new(extract_key),
+            not_found,
+        }
+    }
+}
+impl<SessionKey, MutSession> Clone for SessionMiddlewareLayer<SessionKey, MutSession> {
+    fn clone(&self) -> Self {
+        Self {
+            sessions: Arc::clone(&self.sessions),
+            extract_key: Arc::clone(&self.extract_key),
+            not_found: self.not_found.clone(),
+        }
+    }
+}
+impl<S, SessionKey, MutSession> tower::Layer<S> for SessionMiddlewareLayer<SessionKey, MutSession> {
+    type Service = SessionMiddleware<S, SessionKey, MutSession>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        SessionMiddleware {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+/// The service made by [`SessionMiddlewareLayer`]
+pub struct SessionMiddleware<S, SessionKey, MutSession> {
+    inner: S,
+    layer: SessionMiddlewareLayer<SessionKey, MutSession>,
+}
+impl<S: Clone, SessionKey, MutSession> Clone for SessionMiddleware<S, SessionKey, MutSession> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            layer: self.layer.clone(),
+        }
+    }
+}
+impl<S, B, SessionKey, MutSession> tower::Service<http::Request<B>>
+    for SessionMiddleware<S, SessionKey, MutSession>
+where
+    S: tower::Service<http::Request<B>>,
+    S::Response: Send + 'static,
+    S::Error: Send + 'static,
+    S::Future: Send + 'static,
+    SessionKey: std::fmt::Debug + Clone + Eq + std::hash::Hash + Sync + Send + 'static,
+    MutSession: std::fmt::Debug + Sync + Send + 'static,
+{
+    type Response = S::Response;
+    type Error = SessionMiddlewareError<S::Error>;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner
+            .poll_ready(cx)
+            .map_err(SessionMiddlewareError::Inner)
+    }
+
+    fn call(&mut self, req: http::Request<B>) -> Self::Future {
+        let (mut parts, body) = req.into_parts();
+        let Some(key) = (self.layer.extract_key)(&parts) else {
+            return Box::pin(std::future::ready(Err(SessionMiddlewareError::MissingKey)));
+        };
+        let session = match &self.layer.not_found {
+            NotFoundPolicy::Reject => self.layer.sessions.get_shared(&key),
+            NotFoundPolicy::Create(make) => Some(
+                self.layer
+                    .sessions
+                    .get_shared_or_insert_with(key, || make()),
+            ),
+        };
+        let Some(session) = session else {
+            return Box::pin(std::future::ready(Err(SessionMiddlewareError::NotFound)));
+        };
+        parts.extensions.insert(SessionExtension(session));
+
+        let fut = self.inner.call(http::Request::from_parts(parts, body));
+        Box::pin(async move { fut.await.map_err(SessionMiddlewareError::Inner) })
+    }
+}
+
+/// The session attached to a request by [`SessionMiddleware`]
+pub struct SessionExtension<MutSession>(Arc<TokioMutex<MutSession>>);
+impl<MutSession> SessionExtension<MutSession> {
+    pub async fn lock(&self) -> OwnedMutexGuard<MutSession> {
+        Arc::clone(&self.0).lock_owned().await
+    }
+}
+impl<MutSession> Clone for SessionExtension<MutSession> {
+    fn clone(&self) -> Self {
+        Self(Arc::clone(&self.0))
+    }
+}
+impl<MutSession> std::fmt::Debug for SessionExtension<MutSession> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("SessionExtension").finish_non_exhaustive()
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SessionMiddlewareError<E> {
+    #[error("no session key in request")]
+    MissingKey,
+    #[error("session not found")]
+    NotFound,
+    #[error(transparent)]
+    Inner(E),
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{convert::Infallible, time::Duration};
+
+    use tower::{Layer, Service};
+
+    use super::*;
+
+    /// Count the requests seen by each session
+    #[derive(Clone)]
+    struct CountingService;
+    impl Service<http::Request<()>> for CountingService {
+        type Response = u32;
+        type Error = Infallible;
+        type Future = Pin<Box<dyn Future<Output = Result<u32, Infallible>> + Send>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, req: http::Request<()>) -> Self::Future {
+            let session = req.extensions().get::<SessionExtension<u32>>().cloned();
+            Box::pin(async move {
+                let mut count = session.expect("session attached").lock().await;
+                *count += 1;
+                Ok(*count)
+            })
+        }
+    }
+
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    /// Must be called within a runtime, which runs the sweeper
+    fn service(not_found: NotFoundPolicy<u32>) -> SessionMiddleware<CountingService, String, u32> {
+        let sessions = Arc::new(MutSessionLayer::new(TIMEOUT));
+        let extract_key = |parts: &http::request::Parts| {
+            let key = parts.headers.get("session")?.to_str().ok()?;
+            Some(key.to_owned())
+        };
+        SessionMiddlewareLayer::new(sessions, extract_key, not_found).layer(CountingService)
+    }
+
+    fn request(key: Option<&str>) -> http::Request<()> {
+        let mut req = http::Request::builder();
+        if let Some(key) = key {
+            req = req.header("session", key);
+        }
+        req.body(()).unwrap()
+    }
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    #[test]
+    fn create_policy_shares_one_session_per_key() {
+        block_on(async {
+            let mut service = service(NotFoundPolicy::Create(Arc::new(|| 0)));
+            assert_eq!(service.call(request(Some("a"))).await.unwrap(), 1);
+            assert_eq!(service.call(request(Some("a"))).await.unwrap(), 2);
+            assert_eq!(service.call(request(Some("b"))).await.unwrap(), 1);
+        });
+    }
+
+    #[test]
+    fn reject_policy_fails_unknown_and_missing_keys() {
+        block_on(async {
+            let mut service = service(NotFoundPolicy::Reject);
+            let unknown = service.call(request(Some("a"))).await;
+            assert!(matches!(unknown, Err(SessionMiddlewareError::NotFound)));
+            let missing = service.call(request(None)).await;
+            assert!(matches!(missing, Err(SessionMiddlewareError::MissingKey)));
+        });
+    }
+}
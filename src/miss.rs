@@ -0,0 +1,170 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::{Duration, Instant},
+};
+
+use crate::lock::Mutex;
+
+/// Spreads the bookkeeping of concurrent misses over this many locks
+const SHARDS: usize = 16;
+
+/// Lookups of unknown keys counted by key hash within a sliding window, e.g. to spot a client guessing keys
+///
+/// Each shard forgets its least recently missed hash beyond its share of the capacity.
+#[derive(Debug)]
+pub(crate) struct MissTracker {
+    window: Duration,
+    shards: Box<[Mutex<Shard>]>,
+}
+#[derive(Debug)]
+struct Shard {
+    capacity: usize,
+    misses: HashMap<u64, (Misses, u64)>,
+    /// Hashes by the tick of their latest miss, least recent first
+    order: BTreeMap<u64, u64>,
+    next_tick: u64,
+}
+
+/// Counts of the current and the previous window, blended into an estimate of the last `window`
+#[derive(Debug, Clone, Copy)]
+struct Misses {
+    window_start: Instant,
+    current: u32,
+    previous: u32,
+}
+impl Misses {
+    fn new(now: Instant) -> Self {
+        Self {
+            window_start: now,
+            current: 0,
+            previous: 0,
+        }
+    }
+
+    fn roll(&mut self, now: Instant, window: Duration) {
+        let elapsed = now.saturating_duration_since(self.window_start);
+        if elapsed < window {
+            return;
+        }
+        match elapsed < window * 2 {
+            true => {
+                self.previous = self.current;
+                self.window_start += window;
+            }
+            false => {
+                self.previous = 0;
+                self.window_start = now;
+            }
+        }
+        self.current = 0;
+    }
+
+    fn count(mut self, now: Instant, window: Duration) -> u32 {
+        self.roll(now, window);
+        let into = now.saturating_duration_since(self.window_start);
+        let left = window.saturating_sub(into).as_secs_f64() / window.as_secs_f64();
+        self.current + (f64::from(self.previous) * left) as u32
+    }
+}
+
+impl MissTracker {
+    /// `capacity` must be positive
+    pub fn new(capacity: usize, window: Duration) -> Self {
+        let shards = (0..SHARDS)
+            .map(|_| {
+                Mutex::new(Shard {
+                    capacity: capacity.div_ceil(SHARDS),
+                    misses: HashMap::new(),
+                    order: BTreeMap::new(),
+                    next_tick: 0,
+                })
+            })
+            .collect();
+        Self { window, shards }
+    }
+
+    fn shard(&self, hash: u64) -> &Mutex<Shard> {
+        &self.shards[hash as usize % SHARDS]
+    }
+
+    pub fn record(&self, hash: u64, now: Instant) {
+        let mut shard = self.shard(hash).lock();
+        let tick = shard.next_tick;
+        shard.next_tick += 1;
+        let (mut misses, last_tick) = match shard.misses.get(&hash) {
+            Some(&entry) => entry,
+            None => {
+                if shard.capacity <= shard.misses.len() {
+                    if let Some((_, oldest)) = shard.order.pop_first() {
+                        shard.misses.remove(&oldest);
+                    }
+                }
+                (Misses::new(now), tick)
+            }
+        };
+        shard.order.remove(&last_tick);
+        misses.roll(now, self.window);
+        misses.current = misses.current.saturating_add(1);
+        shard.misses.insert(hash, (misses, tick));
+        shard.order.insert(tick, hash);
+    }
+
+    pub fn misses(&self, hash: u64, now: Instant) -> u32 {
+        let shard = self.shard(hash).lock();
+        shard
+            .misses
+            .get(&hash)
+            .map_or(0, |(misses, _)| misses.count(now, self.window))
+    }
+
+    /// The `n` hashes with the most misses in the window, most first
+    pub fn top(&self, n: usize, now: Instant) -> Vec<(u64, u32)> {
+        let mut top = vec![];
+        for shard in self.shards.iter() {
+            let shard = shard.lock();
+            top.extend(
+                shard
+                    .misses
+                    .iter()
+                    .map(|(&hash, (misses, _))| (hash, misses.count(now, self.window)))
+                    .filter(|&(_, count)| count != 0),
+            );
+        }
+        top.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        top.truncate(n);
+        top
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WINDOW: Duration = Duration::from_secs(10);
+
+    #[test]
+    fn previous_window_fades_out_linearly() {
+        let tracker = MissTracker::new(SHARDS, WINDOW);
+        let start = Instant::now();
+        for _ in 0..4 {
+            tracker.record(1, start);
+        }
+        assert_eq!(tracker.misses(1, start + WINDOW / 2), 4);
+        assert_eq!(tracker.misses(1, start + WINDOW * 3 / 2), 2);
+        assert_eq!(tracker.misses(1, start + WINDOW * 2), 0);
+        assert_eq!(tracker.misses(2, start), 0);
+    }
+
+    #[test]
+    fn full_shards_forget_the_least_recently_missed_hash() {
+        // One hash per shard
+        let tracker = MissTracker::new(SHARDS, WINDOW);
+        let now = Instant::now();
+        tracker.record(0, now);
+        tracker.record(0, now);
+        tracker.record(1, now);
+        tracker.record(SHARDS as u64, now);
+        assert_eq!(tracker.misses(0, now), 0);
+        assert_eq!(tracker.top(2, now), [(1, 1), (SHARDS as u64, 1)]);
+    }
+}
@@ -1,49 +1,900 @@
-use std::{borrow::Borrow, sync::Arc, time::Duration};
+use std::{
+    borrow::Borrow,
+    collections::HashMap,
+    convert::Infallible,
+    future::Future,
+    ops::{Deref, DerefMut},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Weak,
+    },
+    time::{Duration, Instant},
+};
 
-use crate::session::SessionLayer;
+use crate::{
+    builder::{Admission, NewSessionLayerError, SessionLayerBuilder},
+    event::SessionEvent,
+    hook::Hook,
+    lock::Mutex,
+    rate_limit::RateLimiter,
+    reentrancy::{check_reentrancy, Held},
+    runtime::Runtime,
+    scheduler::SweepScheduler,
+    session::{AdmissionDenied, InsertError, Lookup, ResurrectPolicy, SessionLayer},
+    stats::{PopulationStats, SweepReport, SweeperStatus},
+    time::TimeSource,
+};
 
-use tokio::sync::{Mutex as TokioMutex, OwnedMutexGuard};
+use tokio::sync::{broadcast, Mutex as TokioMutex, OwnedMutexGuard, Semaphore};
 
 /// Store sessions that can be mutated asynchronously
-#[derive(Debug)]
 pub struct MutSessionLayer<SessionKey, MutSession> {
     session: Arc<SessionLayer<SessionKey, Session<MutSession>>>,
+    /// Whether [`Self::close`] has been called
+    closed: AtomicBool,
+    /// Serializes the initializations of each missing key in [`Self::get_mut_or_init`]
+    in_flight: Mutex<HashMap<SessionKey, Arc<TokioMutex<()>>>>,
+    /// How many tasks may queue on one session in [`Self::try_get_mut`]
+    max_waiters: Option<usize>,
+    /// Vets sessions in [`Self::insert`] and [`Self::insert_at`]
+    admission: Option<Hook<Admission<SessionKey, MutSession>>>,
+    /// Throttles [`Self::insert`] and [`Self::insert_at`]
+    insert_rate_limit: Option<RateLimiter>,
+    /// Bounds the factories running at once in [`Self::get_mut_or_try_insert`]
+    load_permits: Option<Semaphore>,
+    /// Whether lookups leave the idle time alone
+    refresh_on_mutation: bool,
+    /// See [`MutSessionLayerBuilder::soft_timeout`]
+    soft_timeout: Option<Duration>,
 }
 impl<SessionKey, MutSession> MutSessionLayer<SessionKey, MutSession>
 where
-    SessionKey: Sync + Send + 'static,
+    SessionKey: Eq + std::hash::Hash + Clone + Sync + Send + 'static,
     MutSession: Sync + Send + 'static,
 {
     pub fn new(timeout: Duration) -> Self {
+        Self::from_layer(SessionLayer::new(timeout))
+    }
+
+    pub fn try_new(timeout: Duration) -> Result<Self, NewSessionLayerError> {
+        Ok(Self::from_layer(SessionLayer::try_new(timeout)?))
+    }
+
+    /// Sessions never expire and no background task is spawned
+    pub fn new_unbounded() -> Self {
+        Self::from_layer(SessionLayer::new_unbounded())
+    }
+
+    pub fn builder(timeout: Duration) -> MutSessionLayerBuilder<SessionKey, MutSession> {
+        MutSessionLayerBuilder::new(timeout)
+    }
+
+    fn from_layer(session: Arc<SessionLayer<SessionKey, Session<MutSession>>>) -> Self {
         Self {
-            session: SessionLayer::new(timeout),
+            session,
+            closed: AtomicBool::new(false),
+            in_flight: Mutex::new(HashMap::new()),
+            max_waiters: None,
+            admission: None,
+            insert_rate_limit: None,
+            load_permits: None,
+            refresh_on_mutation: false,
+            soft_timeout: None,
+        }
+    }
+
+    /// Hand the session back if it exceeds the rate limit
+    fn throttle(&self, mut_session: MutSession) -> Result<MutSession, MutInsertError<MutSession>> {
+        match &self.insert_rate_limit {
+            Some(limiter) if !limiter.try_acquire(self.session.now().instant()) => {
+                Err(MutInsertError::RateLimited(mut_session))
+            }
+            _ => Ok(mut_session),
+        }
+    }
+
+    fn is_past_soft_timeout(&self, idle: Duration) -> bool {
+        self.soft_timeout
+            .is_some_and(|soft_timeout| soft_timeout <= idle)
+    }
+
+    /// Hand the session back if the admission callback denies it
+    fn admit(
+        &self,
+        key: &SessionKey,
+        mut_session: MutSession,
+    ) -> Result<MutSession, MutInsertError<MutSession>> {
+        let Some(admission) = &self.admission else {
+            return Ok(mut_session);
+        };
+        match admission(key, &mut_session) {
+            Ok(()) => Ok(mut_session),
+            Err(denied) => Err(MutInsertError::Denied {
+                mut_session,
+                denied,
+            }),
+        }
+    }
+
+    /// Stop handing out sessions from [`Self::get_mut`] and [`Self::try_get_mut`]
+    ///
+    /// Sessions stay in the layer until they expire, and guards already handed out remain valid.
+    pub fn close(&self) {
+        self.closed.store(true, Ordering::Release);
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Acquire)
+    }
+
+    /// Remove idle sessions now instead of waiting for the background task
+    pub fn sweep(&self) -> SweepReport {
+        self.session.sweep()
+    }
+
+    pub fn last_sweep(&self) -> Option<SweepReport> {
+        self.session.last_sweep()
+    }
+
+    /// See [`SessionLayer::sweep_overruns`]
+    pub fn sweep_overruns(&self) -> u64 {
+        self.session.sweep_overruns()
+    }
+
+    /// See [`SessionLayer::sweeper_status`]
+    pub fn sweeper_status(&self) -> SweeperStatus {
+        self.session.sweeper_status()
+    }
+
+    /// The number of sessions without taking any lock
+    pub fn approx_len(&self) -> usize {
+        self.session.approx_len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.session.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.session.is_empty()
+    }
+
+    /// Summarize the idle times of all sessions without refreshing any of them
+    pub fn stats_snapshot(&self) -> PopulationStats {
+        self.session.stats_snapshot()
+    }
+
+    /// Receive lifecycle events of all sessions from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<SessionEvent<SessionKey>> {
+        self.session.subscribe()
+    }
+}
+/// Options of a [`MutSessionLayer`] that compose with one another
+///
+/// Options of the underlying [`SessionLayer`] that do not see the sessions are forwarded to a [`SessionLayerBuilder`].
+#[derive(Debug)]
+pub struct MutSessionLayerBuilder<SessionKey, MutSession> {
+    session: SessionLayerBuilder<SessionKey, Session<MutSession>>,
+    max_waiters: Option<usize>,
+    max_concurrent_loads: Option<usize>,
+    admission: Option<Hook<Admission<SessionKey, MutSession>>>,
+    insert_rate_limit: Option<(u32, Duration)>,
+    refresh_on_mutation: bool,
+    soft_timeout: Option<Duration>,
+}
+impl<SessionKey, MutSession> MutSessionLayerBuilder<SessionKey, MutSession> {
+    pub fn new(timeout: Duration) -> Self {
+        Self::from_session(SessionLayerBuilder::new(timeout))
+    }
+
+    /// Sessions never expire and no background task is spawned
+    pub fn unbounded() -> Self {
+        Self::from_session(SessionLayerBuilder::unbounded())
+    }
+
+    fn from_session(session: SessionLayerBuilder<SessionKey, Session<MutSession>>) -> Self {
+        Self {
+            session,
+            max_waiters: None,
+            max_concurrent_loads: None,
+            admission: None,
+            insert_rate_limit: None,
+            refresh_on_mutation: false,
+            soft_timeout: None,
+        }
+    }
+
+    /// Only refresh sessions that are actually changed, through [`MutSessionLayer::get_mut_tracked`] or [`MutSessionLayer::modify`]
+    ///
+    /// By default, every lookup by [`MutSessionLayer::get_mut`] refreshes the session even if it is only read.
+    /// In this mode, [`MutSessionLayer::get_mut`] and [`MutSessionLayer::try_get_mut`] never refresh, so a session that is only ever read expires after the timeout.
+    pub fn refresh_on_mutation(mut self) -> Self {
+        self.refresh_on_mutation = true;
+        self
+    }
+
+    /// Flag sessions idle for `soft_timeout` as expiring until the timeout removes them, e.g. to prompt the client to renew
+    ///
+    /// Read the flag through [`MutSessionLayer::is_expiring`] and [`MutSessionLayer::get_mut_flagged`].
+    /// Expiry itself is unchanged.
+    pub fn soft_timeout(mut self, soft_timeout: Duration) -> Self {
+        self.soft_timeout = Some(soft_timeout);
+        self
+    }
+
+    /// Fail [`MutSessionLayer::get_mut`] and [`MutSessionLayer::try_get_mut`] fast instead of queuing behind `max` tasks already waiting for the same session
+    ///
+    /// Bounds the latency and memory under contention, e.g. from a client hammering one session.
+    /// Only tasks that find the session locked queue, so a `max` of zero fails only while the session is held.
+    /// Watch the queues with [`MutSessionLayer::waiters`].
+    /// Unbounded by default.
+    pub fn max_waiters(mut self, max: usize) -> Self {
+        self.max_waiters = Some(max);
+        self
+    }
+
+    /// Run at most `max` factories of [`MutSessionLayer::get_mut_or_try_insert`] and [`MutSessionLayer::get_mut_or_init`] at once across all keys, queueing the rest
+    ///
+    /// Protects the backing store from a stampede of loads when many keys miss together, e.g. on a cold start, at the cost of a longer creation latency during such a burst.
+    /// Concurrent callers of the same key already share one factory regardless.
+    /// Unbounded by default, and `max` must be positive.
+    pub fn max_concurrent_loads(mut self, max: usize) -> Self {
+        self.max_concurrent_loads = Some(max);
+        self
+    }
+
+    /// Vet every session before [`MutSessionLayer::insert`] and [`MutSessionLayer::insert_at`] take it, see [`SessionLayerBuilder::admission`]
+    ///
+    /// A denied session is handed back in [`MutInsertError::Denied`].
+    /// It runs outside the locks.
+    /// State registered by [`MutSessionLayer::insert_shared`] and sessions made by the factories of the `get_mut_or_*` methods are not vetted.
+    pub fn admission(
+        mut self,
+        admission: impl Fn(&SessionKey, &MutSession) -> Result<(), AdmissionDenied>
+            + Send
+            + Sync
+            + 'static,
+    ) -> Self {
+        self.admission = Some(Hook::new(Box::new(admission)));
+        self
+    }
+
+    /// Reject [`MutSessionLayer::insert`] and [`MutSessionLayer::insert_at`] with [`MutInsertError::RateLimited`] once they outpace a token bucket, see [`SessionLayerBuilder::insert_rate_limit`]
+    ///
+    /// State registered by [`MutSessionLayer::insert_shared`] and sessions made by the factories of the `get_mut_or_*` methods are not throttled.
+    pub fn insert_rate_limit(mut self, capacity: u32, refill_interval: Duration) -> Self {
+        self.insert_rate_limit = Some((capacity, refill_interval));
+        self
+    }
+
+    /// Remember removed keys for [`MutSessionLayer::lookup_mut`], see [`SessionLayerBuilder::tombstones`]
+    pub fn tombstones(mut self, capacity: usize, retention: Duration) -> Self {
+        self.session = self.session.tombstones(capacity, retention);
+        self
+    }
+
+    /// How [`MutSessionLayer::get_mut`] and its variants treat a session idle past the timeout that no sweep has removed yet, see [`SessionLayerBuilder::resurrect_policy`]
+    ///
+    /// Defaults to [`ResurrectPolicy::Lenient`], which hands such a session out and refreshes it.
+    pub fn resurrect_policy(mut self, policy: ResurrectPolicy) -> Self {
+        self.session = self.session.resurrect_policy(policy);
+        self
+    }
+
+    /// See [`SessionLayerBuilder::time_source`]
+    pub fn time_source(mut self, time_source: TimeSource) -> Self {
+        self.session = self.session.time_source(time_source);
+        self
+    }
+
+    /// See [`SessionLayerBuilder::runtime`]
+    pub fn runtime(mut self, runtime: impl Runtime) -> Self {
+        self.session = self.session.runtime(runtime);
+        self
+    }
+
+    /// See [`SessionLayerBuilder::manual_sweep`]
+    pub fn manual_sweep(mut self) -> Self {
+        self.session = self.session.manual_sweep();
+        self
+    }
+
+    /// See [`SessionLayerBuilder::scheduler`]
+    pub fn scheduler(mut self, scheduler: &SweepScheduler) -> Self {
+        self.session = self.session.scheduler(scheduler);
+        self
+    }
+}
+impl<SessionKey, MutSession> MutSessionLayerBuilder<SessionKey, MutSession>
+where
+    SessionKey: Eq + std::hash::Hash + Clone + Sync + Send + 'static,
+    MutSession: Sync + Send + 'static,
+{
+    pub fn build(self) -> Result<MutSessionLayer<SessionKey, MutSession>, NewSessionLayerError> {
+        if self.max_concurrent_loads == Some(0) {
+            return Err(NewSessionLayerError::ZeroConcurrentLoads);
         }
+        if matches!(self.insert_rate_limit, Some((0, _))) {
+            return Err(NewSessionLayerError::ZeroRateLimitCapacity);
+        }
+        let mut layer = MutSessionLayer::from_layer(self.session.build()?);
+        let origin = layer.session.now().instant();
+        layer.insert_rate_limit = self
+            .insert_rate_limit
+            .map(|(capacity, refill_interval)| RateLimiter::new(capacity, refill_interval, origin));
+        layer.max_waiters = self.max_waiters;
+        layer.admission = self.admission;
+        layer.load_permits = self.max_concurrent_loads.map(Semaphore::new);
+        layer.refresh_on_mutation = self.refresh_on_mutation;
+        layer.soft_timeout = self.soft_timeout;
+        Ok(layer)
     }
 }
+
 impl<SessionKey, MutSession> MutSessionLayer<SessionKey, MutSession>
 where
     SessionKey: std::fmt::Debug + Clone + Eq + std::hash::Hash + Sync + Send + 'static,
     MutSession: std::fmt::Debug + Sync + Send + 'static,
 {
-    pub async fn get_mut<Q: ?Sized>(&self, key: &Q) -> Option<OwnedMutexGuard<MutSession>>
+    /// Return [`None`] if the key is not found, the layer is closed, or the session is busy
+    pub async fn get_mut<Q>(&self, key: &Q) -> Option<MutSessionGuard<SessionKey, MutSession>>
+    where
+        SessionKey: Borrow<Q>,
+        Q: ?Sized + Eq + std::hash::Hash,
+    {
+        self.try_get_mut(key).await.ok().flatten()
+    }
+
+    /// Same as [`Self::get_mut`] but also return how long the session had been idle before this access, see [`SessionLayer::get_and_idle`]
+    ///
+    /// The session is refreshed even in [`MutSessionLayerBuilder::refresh_on_mutation`] mode, since the idle time is reset.
+    pub async fn get_mut_and_idle<Q>(
+        &self,
+        key: &Q,
+    ) -> Option<(MutSessionGuard<SessionKey, MutSession>, Duration)>
+    where
+        SessionKey: Borrow<Q>,
+        Q: ?Sized + Eq + std::hash::Hash,
+    {
+        if self.is_closed() {
+            return None;
+        }
+        let (key, session, idle) = self.session.get_with(key, |key, session, idle| {
+            (key.clone(), session.clone(), idle)
+        })?;
+        session.check_reentrancy();
+        let mut_session = session.lock(self.max_waiters).await?;
+        let guard = MutSessionGuard::new(mut_session, session.id(), key, &self.session);
+        Some((guard, idle))
+    }
+
+    /// Same as [`Self::get_mut`] but refuse a session with less than `min_remaining` left before it expires, e.g. before starting an operation that must not be lost to the sweep
+    ///
+    /// The time left is checked and the session refreshed in one step, so a session handed out has the full timeout left, minus the wait for the lock.
+    /// The session is refreshed even in [`MutSessionLayerBuilder::refresh_on_mutation`] mode, and a refused one is left untouched.
+    pub async fn get_mut_if_fresh<Q>(
+        &self,
+        key: &Q,
+        min_remaining: Duration,
+    ) -> Result<MutSessionGuard<SessionKey, MutSession>, FreshnessError>
+    where
+        SessionKey: Borrow<Q>,
+        Q: ?Sized + Eq + std::hash::Hash,
+    {
+        if self.is_closed() {
+            return Err(FreshnessError::Closed);
+        }
+        let (key, session) = self
+            .session
+            .get_with_if_fresh(key, min_remaining, |key, session, _| {
+                (key.clone(), session.clone())
+            })
+            .ok_or(FreshnessError::NotFound)?
+            .map_err(|remaining| FreshnessError::Stale { remaining })?;
+        session.check_reentrancy();
+        let mut_session = session
+            .lock(self.max_waiters)
+            .await
+            .ok_or(FreshnessError::Busy)?;
+        Ok(MutSessionGuard::new(
+            mut_session,
+            session.id(),
+            key,
+            &self.session,
+        ))
+    }
+
+    /// Whether the session has been idle past [`MutSessionLayerBuilder::soft_timeout`], without refreshing it
+    ///
+    /// Return [`None`] if the key is not found.
+    pub fn is_expiring<Q>(&self, key: &Q) -> Option<bool>
+    where
+        SessionKey: Borrow<Q>,
+        Q: ?Sized + Eq + std::hash::Hash,
+    {
+        let idle = self.session.idle_time(key)?;
+        Some(self.is_past_soft_timeout(idle))
+    }
+
+    /// Same as [`Self::get_mut`] but also return whether the session was expiring as of this access, see [`MutSessionLayerBuilder::soft_timeout`]
+    ///
+    /// An access refreshing the session ends the expiring state, but the flag still reports it so that the handler can prompt renewal.
+    pub async fn get_mut_flagged<Q>(
+        &self,
+        key: &Q,
+    ) -> Option<(MutSessionGuard<SessionKey, MutSession>, bool)>
+    where
+        SessionKey: Borrow<Q>,
+        Q: ?Sized + Eq + std::hash::Hash,
+    {
+        if !self.refresh_on_mutation {
+            let (guard, idle) = self.get_mut_and_idle(key).await?;
+            return Some((guard, self.is_past_soft_timeout(idle)));
+        }
+        let expiring = self.is_expiring(key)?;
+        let guard = self.get_mut(key).await?;
+        Some((guard, expiring))
+    }
+
+    /// Same as [`Self::get_mut`] but tell a recently expired key from an unknown one, see [`SessionLayer::lookup`]
+    ///
+    /// A closed layer or a busy session is reported as a missing key.
+    pub async fn lookup_mut<Q>(&self, key: &Q) -> Lookup<MutSessionGuard<SessionKey, MutSession>>
+    where
+        SessionKey: Borrow<Q>,
+        Q: ?Sized + Eq + std::hash::Hash,
+    {
+        match self.get_mut(key).await {
+            Some(mut_session) => Lookup::Found(mut_session),
+            None => self.session.missing(key),
+        }
+    }
+
+    /// Same as [`Self::get_mut`] but tell a closed layer or a busy session apart from a missing key
+    pub async fn try_get_mut<Q>(
+        &self,
+        key: &Q,
+    ) -> Result<Option<MutSessionGuard<SessionKey, MutSession>>, TryGetMutError>
+    where
+        SessionKey: Borrow<Q>,
+        Q: ?Sized + Eq + std::hash::Hash,
+    {
+        if self.is_closed() {
+            return Err(TryGetMutError::Closed);
+        }
+        let Some((key, session)) = self.lookup_key_value(key) else {
+            return Ok(None);
+        };
+        session.check_reentrancy();
+        let mut_session = session
+            .lock(self.max_waiters)
+            .await
+            .ok_or(TryGetMutError::Busy)?;
+        let guard = MutSessionGuard::new(mut_session, session.id(), key, &self.session);
+        Ok(Some(guard))
+    }
+
+    /// Same as [`Self::get_mut`] but refresh the session once the guard is dropped if it was used mutably
+    ///
+    /// Meant for layers set to [`MutSessionLayerBuilder::refresh_on_mutation`].
+    pub async fn get_mut_tracked<'a, Q>(
+        &'a self,
+        key: &'a Q,
+    ) -> Option<TrackedMutSessionGuard<'a, SessionKey, MutSession>>
+    where
+        SessionKey: Borrow<Q>,
+        Q: ?Sized + Eq + std::hash::Hash + Sync,
+    {
+        let guard = self.get_mut(key).await?;
+        Some(TrackedMutSessionGuard {
+            guard,
+            dirty: false,
+            refresh: Box::new(move || {
+                self.session.contains_and_touch(key);
+            }),
+        })
+    }
+
+    /// Lock the session and run `f` on it, refreshing the session only if `f` returns `true` to report a change
+    ///
+    /// Return whether the session was found.
+    pub async fn modify<Q>(&self, key: &Q, f: impl FnOnce(&mut MutSession) -> bool) -> bool
+    where
+        SessionKey: Borrow<Q>,
+        Q: ?Sized + Eq + std::hash::Hash + Sync,
+    {
+        let Some(mut guard) = self.get_mut_tracked(key).await else {
+            return false;
+        };
+        guard.dirty = f(&mut guard.guard);
+        true
+    }
+
+    /// Block the current thread until the session is locked
+    ///
+    /// For synchronous call sites such as `spawn_blocking` or plain threads.
+    /// Return [`None`] if the key is not found or the layer is closed.
+    ///
+    /// # Panics
+    ///
+    /// Must not be called from an async context, where it would stall the runtime.
+    pub fn blocking_get_mut<Q>(&self, key: &Q) -> Option<OwnedMutexGuard<MutSession>>
+    where
+        SessionKey: Borrow<Q>,
+        Q: ?Sized + Eq + std::hash::Hash,
+    {
+        if self.is_closed() {
+            return None;
+        }
+        let session = self.lookup(key)?;
+        session.check_reentrancy();
+        Some(session.mutex.blocking_lock_owned())
+    }
+
+    /// Lock the session or insert a new one made by `make`
+    ///
+    /// Nothing is inserted if `make` fails.
+    pub async fn get_mut_or_try_insert_with<E>(
+        &self,
+        key: SessionKey,
+        make: impl FnOnce() -> Result<MutSession, E>,
+    ) -> Result<MutSessionGuard<SessionKey, MutSession>, E> {
+        let key = self.session.normalize_key(&key);
+        let session = self.session.get_or_try_insert_with(key.clone(), || {
+            make().map(|mut_session| Session::new(Arc::new(TokioMutex::new(mut_session))))
+        })?;
+        Ok(self.lock_guard(session, key).await)
+    }
+
+    /// Resume the session of a reconnecting client if it is still there, or else start a fresh one made by `make`
+    ///
+    /// The lookup and the insertion happen under one write lock, and the session is refreshed on resumption, so concurrent reconnections of the same key all share one session.
+    pub async fn resume_or_insert(
+        &self,
+        key: SessionKey,
+        make: impl FnOnce() -> MutSession,
+    ) -> MutSessionGuard<SessionKey, MutSession> {
+        match self
+            .get_mut_or_try_insert_with(key, || Ok::<_, Infallible>(make()))
+            .await
+        {
+            Ok(mut_session) => mut_session,
+        }
+    }
+
+    /// Clone out the shared state without locking it
+    pub fn get_shared<Q>(&self, key: &Q) -> Option<Arc<TokioMutex<MutSession>>>
+    where
+        SessionKey: Borrow<Q>,
+        Q: ?Sized + Eq + std::hash::Hash,
+    {
+        let session = self.lookup(key)?;
+        Some(session.mutex)
+    }
+
+    /// Refresh the session unless in [`MutSessionLayerBuilder::refresh_on_mutation`] mode
+    fn lookup<Q>(&self, key: &Q) -> Option<Session<MutSession>>
+    where
+        SessionKey: Borrow<Q>,
+        Q: ?Sized + Eq + std::hash::Hash,
+    {
+        match self.refresh_on_mutation {
+            true => self.session.peek(key),
+            false => self.session.get(key),
+        }
+    }
+
+    /// Same as [`Self::lookup`] but also clone out the stored key
+    fn lookup_key_value<Q>(&self, key: &Q) -> Option<(SessionKey, Session<MutSession>)>
+    where
+        SessionKey: Borrow<Q>,
+        Q: ?Sized + Eq + std::hash::Hash,
+    {
+        match self.refresh_on_mutation {
+            true => self.session.peek_key_value(key),
+            false => self.session.get_key_value(key),
+        }
+    }
+
+    #[cfg(feature = "tower")]
+    pub(crate) fn get_shared_or_insert_with(
+        &self,
+        key: SessionKey,
+        make: impl FnOnce() -> MutSession,
+    ) -> Arc<TokioMutex<MutSession>> {
+        let make = || Ok::<_, Infallible>(Session::new(Arc::new(TokioMutex::new(make()))));
+        match self.session.get_or_try_insert_with(key, make) {
+            Ok(session) => session.mutex,
+        }
+    }
+
+    /// Lock the session or insert a new one made by `init`
+    ///
+    /// Only one caller at a time runs its `init` for a missing key while the others wait, so concurrent callers do not all construct a session only to throw it away.
+    /// If that caller is cancelled, the next waiter takes over and runs its own `init`.
+    pub async fn get_mut_or_init<F, Fut>(
+        &self,
+        key: SessionKey,
+        init: F,
+    ) -> MutSessionGuard<SessionKey, MutSession>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = MutSession>,
+    {
+        let init = || async { Ok::<_, Infallible>(init().await) };
+        match self.get_mut_or_try_insert(key, init).await {
+            Ok(mut_session) => mut_session,
+        }
+    }
+
+    /// Same as [`Self::get_mut_or_init`] but with a fallible `make`, e.g. to open a file or connect upstream
+    ///
+    /// The error of `make` is returned and nothing is inserted.
+    /// The next caller waiting on the same key then runs its own `make`.
+    pub async fn get_mut_or_try_insert<F, Fut, E>(
+        &self,
+        key: SessionKey,
+        make: F,
+    ) -> Result<MutSessionGuard<SessionKey, MutSession>, E>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<MutSession, E>>,
+    {
+        let key = self.session.normalize_key(&key);
+        if let Some(session) = self.session.get(&key) {
+            return Ok(self.lock_guard(session, key).await);
+        }
+
+        let in_flight = InFlight::new(self, &key);
+        let _turn = Arc::clone(&in_flight.lock).lock_owned().await;
+        if let Some(session) = self.session.get(&key) {
+            return Ok(self.lock_guard(session, key.clone()).await);
+        }
+        // The semaphore is never closed
+        let permit = match &self.load_permits {
+            Some(load_permits) => load_permits.acquire().await.ok(),
+            None => None,
+        };
+        let mut_session = make().await?;
+        drop(permit);
+        let make = || Ok::<_, Infallible>(Session::new(Arc::new(TokioMutex::new(mut_session))));
+        let Ok(session) = self.session.get_or_try_insert_with(key.clone(), make);
+        Ok(self.lock_guard(session, key.clone()).await)
+    }
+
+    /// Wait for the session and register the guard with [`detect_reentrancy`]
+    async fn lock_guard(
+        &self,
+        session: Session<MutSession>,
+        key: SessionKey,
+    ) -> MutSessionGuard<SessionKey, MutSession> {
+        session.check_reentrancy();
+        let mut_session = Arc::clone(&session.mutex).lock_owned().await;
+        MutSessionGuard::new(mut_session, session.id(), key, &self.session)
+    }
+
+    /// [`Self::close`] the layer, then stop its background task and run `close` on every session with at most `concurrency` of them at once
+    ///
+    /// Each session is locked before being passed on, so this waits for guards still held elsewhere.
+    /// See [`SessionLayer::shutdown_with`].
+    pub async fn shutdown_with<F, Fut>(&self, concurrency: usize, close: F)
+    where
+        F: Fn(SessionKey, OwnedMutexGuard<MutSession>) -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        self.close();
+        let close = &close;
+        self.session
+            .shutdown_with(concurrency, |key, session| async move {
+                session.check_reentrancy();
+                let mut_session = session.mutex.lock_owned().await;
+                close(key, mut_session).await;
+            })
+            .await;
+    }
+
+    /// Yield all keys chunk by chunk without locking any session, see [`crate::SessionStream`]
+    #[cfg(feature = "stream")]
+    pub fn stream_keys(&self) -> impl futures_core::Stream<Item = SessionKey> + '_ {
+        crate::stream::SessionStream::new(&*self.session, |key, _| key)
+    }
+
+    /// The number of tasks queued in [`Self::get_mut`] and its variants for the session's lock, e.g. to spot a pathologically contended session
+    ///
+    /// The session is not refreshed.
+    pub fn waiters<Q>(&self, key: &Q) -> Option<usize>
+    where
+        SessionKey: Borrow<Q>,
+        Q: ?Sized + Eq + std::hash::Hash,
+    {
+        let session = self.session.peek(key)?;
+        Some(session.waiters.load(Ordering::Acquire))
+    }
+
+    /// Project the session state without waiting for its lock
+    ///
+    /// Fail with [`SessionBusy`] if the session is locked elsewhere.
+    /// The session is not refreshed.
+    pub fn try_peek<Q, R>(
+        &self,
+        key: &Q,
+        f: impl FnOnce(&MutSession) -> R,
+    ) -> Option<Result<R, SessionBusy>>
+    where
+        SessionKey: Borrow<Q>,
+        Q: ?Sized + Eq + std::hash::Hash,
+    {
+        let session = self.session.peek(key)?;
+        let res = match session.mutex.try_lock() {
+            Ok(mut_session) => Ok(f(&mut_session)),
+            Err(_) => Err(SessionBusy),
+        };
+        Some(res)
+    }
+
+    /// Clone every session state that is not locked at the moment
+    ///
+    /// Never waits, so it cannot stall behind a long-held guard, at the cost of skipping the sessions locked elsewhere.
+    /// Nothing stays locked while the caller handles the snapshot.
+    pub fn clone_sessions(&self) -> SessionSnapshot<SessionKey, MutSession>
+    where
+        MutSession: Clone,
+    {
+        let mut snapshot = SessionSnapshot::default();
+        for (key, session) in self.session.entries() {
+            match session.mutex.try_lock() {
+                Ok(mut_session) => snapshot.cloned.push((key, mut_session.clone())),
+                Err(_) => snapshot.skipped.push(key),
+            }
+        }
+        snapshot
+    }
+
+    /// Same as [`Self::clone_sessions`] but wait up to `timeout` for each locked session
+    ///
+    /// # Panics
+    ///
+    /// Panics if the layer has no runtime to time out on.
+    pub async fn clone_sessions_within(
+        &self,
+        timeout: Duration,
+    ) -> SessionSnapshot<SessionKey, MutSession>
+    where
+        MutSession: Clone,
+    {
+        let runtime = Arc::clone(
+            self.session
+                .runtime()
+                .expect("cloning sessions within a timeout needs a runtime to sleep on"),
+        );
+        let mut snapshot = SessionSnapshot::default();
+        for (key, session) in self.session.entries() {
+            let locked = crate::concurrent::timeout(runtime.sleep(timeout), session.mutex.lock());
+            match locked.await {
+                Some(mut_session) => snapshot.cloned.push((key, mut_session.clone())),
+                None => snapshot.skipped.push(key),
+            }
+        }
+        snapshot
+    }
+
+    /// Remove the session and take back its value
+    ///
+    /// Fail with [`SessionBusy`] and keep the session if any guard or other reference to it is outstanding, since the value cannot be moved out then.
+    /// Return `Ok(None)` if the key is not found.
+    ///
+    /// This never waits for outstanding guards to be dropped, which could take as long as their holders like, so it is not `async`: retry or drop the guards first.
+    pub fn take<Q>(&self, key: &Q) -> Result<Option<MutSession>, SessionBusy>
     where
         SessionKey: Borrow<Q>,
-        Q: Eq + std::hash::Hash,
+        Q: ?Sized + Eq + std::hash::Hash,
     {
-        let session = self.session.get(key)?;
-        let mut_session = Arc::clone(&session.0).lock_owned().await;
-        Some(mut_session)
+        // Other references can only be made from the map, which is write-locked during the check
+        let Some(session) = self
+            .session
+            .remove_if(key, |session| Arc::strong_count(&session.mutex) == 1)
+        else {
+            return Ok(None);
+        };
+        let session = session.ok_or(SessionBusy)?;
+        let mutex = Arc::try_unwrap(session.mutex).map_err(|_| SessionBusy)?;
+        Ok(Some(mutex.into_inner()))
+    }
+
+    /// See [`SessionLayer::remove_many`]
+    ///
+    /// Never blocks on a session lock, so it can be called from both synchronous and async contexts.
+    /// Guards already handed out remain valid.
+    pub fn remove_many<'a, Q>(
+        &self,
+        keys: impl IntoIterator<Item = &'a Q>,
+    ) -> Vec<(SessionKey, Arc<TokioMutex<MutSession>>)>
+    where
+        SessionKey: Borrow<Q>,
+        Q: ?Sized + Eq + std::hash::Hash + 'a,
+    {
+        self.session
+            .remove_many(keys)
+            .into_iter()
+            .map(|(key, session)| (key, session.mutex))
+            .collect()
+    }
+
+    /// Register state that the caller already shares instead of moving it in
+    ///
+    /// The caller and the layer lock the same mutex.
+    pub fn insert_shared(
+        &self,
+        key: SessionKey,
+        state: Arc<TokioMutex<MutSession>>,
+    ) -> Result<(), MutSessionCollision> {
+        self.session
+            .insert(key, Session::new(state))
+            .map_err(|_| MutSessionCollision)
+    }
+
+    /// See [`SessionLayer::insert_at`]
+    pub fn insert_at(
+        &self,
+        key: SessionKey,
+        mut_session: MutSession,
+        last_access: Instant,
+    ) -> Result<(), MutInsertError<MutSession>> {
+        let mut_session = self.throttle(mut_session)?;
+        let mut_session = self.admit(&key, mut_session)?;
+        let session = Session::new(Arc::new(TokioMutex::new(mut_session)));
+        self.session
+            .insert_at(key, session, last_access)
+            .map_err(MutInsertError::from_layer)
     }
 
     pub fn insert(
         &self,
         key: SessionKey,
         mut_session: MutSession,
-    ) -> Result<(), MutSessionCollision> {
-        let session = Session(Arc::new(TokioMutex::new(mut_session)));
+    ) -> Result<(), MutInsertError<MutSession>> {
+        let mut_session = self.throttle(mut_session)?;
+        let mut_session = self.admit(&key, mut_session)?;
+        let session = Session::new(Arc::new(TokioMutex::new(mut_session)));
         self.session
             .insert(key, session)
-            .map_err(|_| MutSessionCollision)
+            .map_err(MutInsertError::from_layer)
+    }
+
+    /// See [`SessionLayer::debug_dump`]
+    ///
+    /// Sessions locked by a guard are shown as locked rather than waited for.
+    pub fn debug_dump(&self, max_entries: usize) -> String {
+        self.session.debug_dump(max_entries)
+    }
+}
+
+/// Leaves out keys and sessions like the [`Debug`] of [`SessionLayer`], see [`MutSessionLayer::debug_dump`] for the contents
+impl<SessionKey, MutSession> std::fmt::Debug for MutSessionLayer<SessionKey, MutSession> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MutSessionLayer")
+            .field("session", &self.session)
+            .field("closed", &self.closed)
+            .field("max_waiters", &self.max_waiters)
+            .field("refresh_on_mutation", &self.refresh_on_mutation)
+            .field("soft_timeout", &self.soft_timeout)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A point-in-time copy of session states from [`MutSessionLayer::clone_sessions`]
+#[derive(Debug, Clone)]
+pub struct SessionSnapshot<SessionKey, MutSession> {
+    pub cloned: Vec<(SessionKey, MutSession)>,
+    /// Sessions that were locked elsewhere
+    pub skipped: Vec<SessionKey>,
+}
+impl<SessionKey, MutSession> Default for SessionSnapshot<SessionKey, MutSession> {
+    fn default() -> Self {
+        Self {
+            cloned: vec![],
+            skipped: vec![],
+        }
     }
 }
 
@@ -51,11 +902,1022 @@ where
 #[error("mut session collision")]
 pub struct MutSessionCollision;
 
+/// Why [`MutSessionLayer::insert`] or [`MutSessionLayer::insert_at`] failed
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum MutInsertError<MutSession> {
+    #[error("mut session collision")]
+    Collision,
+    /// Hands the session back, see [`MutSessionLayerBuilder::admission`]
+    #[error("{denied}")]
+    Denied {
+        mut_session: MutSession,
+        denied: AdmissionDenied,
+    },
+    /// Hands the session back, see [`MutSessionLayerBuilder::insert_rate_limit`]
+    #[error("insert rate limited")]
+    RateLimited(MutSession),
+}
+impl<MutSession: std::fmt::Debug> MutInsertError<MutSession> {
+    /// Hand the state of a session refused by the underlying layer back if it is not shared
+    fn from_layer(err: InsertError<Session<MutSession>>) -> Self {
+        match err {
+            InsertError::Collision(_) => Self::Collision,
+            InsertError::Denied { session, denied } => match Arc::try_unwrap(session.mutex) {
+                Ok(mutex) => Self::Denied {
+                    mut_session: mutex.into_inner(),
+                    denied,
+                },
+                Err(_) => Self::Collision,
+            },
+            InsertError::RateLimited(session) => match Arc::try_unwrap(session.mutex) {
+                Ok(mutex) => Self::RateLimited(mutex.into_inner()),
+                Err(_) => Self::Collision,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum TryGetMutError {
+    #[error("session layer closed")]
+    Closed,
+    /// Too many tasks are already waiting for the session, see [`MutSessionLayerBuilder::max_waiters`]
+    #[error("session is busy")]
+    Busy,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+#[error("session is busy")]
+pub struct SessionBusy;
+
+/// Why [`MutSessionLayer::get_mut_if_fresh`] handed out no session
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum FreshnessError {
+    #[error("no session under the key")]
+    NotFound,
+    /// Too close to expiring, so left untouched
+    #[error("session expires in {remaining:?}")]
+    Stale { remaining: Duration },
+    #[error("session layer closed")]
+    Closed,
+    /// See [`MutSessionLayerBuilder::max_waiters`]
+    #[error("session is busy")]
+    Busy,
+}
+
+/// A stake in the initialization of a missing key
+struct InFlight<'a, SessionKey: Eq + std::hash::Hash, MutSession> {
+    layer: &'a MutSessionLayer<SessionKey, MutSession>,
+    key: &'a SessionKey,
+    lock: Arc<TokioMutex<()>>,
+}
+impl<'a, SessionKey: Eq + std::hash::Hash + Clone, MutSession>
+    InFlight<'a, SessionKey, MutSession>
+{
+    fn new(layer: &'a MutSessionLayer<SessionKey, MutSession>, key: &'a SessionKey) -> Self {
+        let mut in_flight = layer.in_flight.lock();
+        let lock = Arc::clone(in_flight.entry(key.clone()).or_default());
+        Self { layer, key, lock }
+    }
+}
+impl<SessionKey: Eq + std::hash::Hash, MutSession> Drop for InFlight<'_, SessionKey, MutSession> {
+    fn drop(&mut self) {
+        // The lock is only cloned under the map lock, so its count cannot grow behind our back
+        let mut in_flight = self.layer.in_flight.lock();
+        let Some(lock) = in_flight.get(self.key) else {
+            return;
+        };
+        if Arc::ptr_eq(lock, &self.lock) && Arc::strong_count(&self.lock) == 2 {
+            in_flight.remove(self.key);
+        }
+    }
+}
+
+/// A lock on a session from [`MutSessionLayer::get_mut`] and its variants that also knows its key and layer
+///
+/// Derefs to the session like the [`OwnedMutexGuard`] it wraps.
+/// The layer is only weakly referenced, so a guard outliving its layer does nothing more than unlock.
+pub struct MutSessionGuard<SessionKey, MutSession> {
+    guard: OwnedMutexGuard<MutSession>,
+    key: SessionKey,
+    layer: Weak<SessionLayer<SessionKey, Session<MutSession>>>,
+    remove_on_drop: bool,
+    /// Captured where the bounds of the layer hold, so they need not be repeated on the guard
+    remove: RemoveLocked<SessionKey, MutSession>,
+    _held: Held,
+}
+type RemoveLocked<SessionKey, MutSession> =
+    fn(&SessionLayer<SessionKey, Session<MutSession>>, &SessionKey, &Arc<TokioMutex<MutSession>>);
+impl<SessionKey, MutSession> MutSessionGuard<SessionKey, MutSession>
+where
+    SessionKey: std::fmt::Debug + Clone + Eq + std::hash::Hash + Sync + Send + 'static,
+    MutSession: std::fmt::Debug + Sync + Send + 'static,
+{
+    fn new(
+        guard: OwnedMutexGuard<MutSession>,
+        id: usize,
+        key: SessionKey,
+        layer: &Arc<SessionLayer<SessionKey, Session<MutSession>>>,
+    ) -> Self {
+        Self {
+            guard,
+            key,
+            layer: Arc::downgrade(layer),
+            remove_on_drop: false,
+            remove: remove_locked,
+            _held: Held::new(id),
+        }
+    }
+
+    /// Refresh the session while holding it, e.g. during a long operation that would otherwise outlast the timeout
+    ///
+    /// Return whether the key is still in the layer.
+    pub fn touch(&self) -> bool {
+        self.layer
+            .upgrade()
+            .is_some_and(|layer| layer.contains_and_touch(&self.key))
+    }
+}
+impl<SessionKey, MutSession> MutSessionGuard<SessionKey, MutSession> {
+    /// The key as stored in the layer
+    pub fn key(&self) -> &SessionKey {
+        &self.key
+    }
+
+    /// Remove the session from the layer once this guard is dropped, e.g. after processing a logout
+    ///
+    /// Nothing is removed if the key has been given to another session in the meantime.
+    pub fn remove_on_drop(&mut self) {
+        self.remove_on_drop = true;
+    }
+}
+impl<SessionKey, MutSession> Deref for MutSessionGuard<SessionKey, MutSession> {
+    type Target = MutSession;
+
+    fn deref(&self) -> &Self::Target {
+        &self.guard
+    }
+}
+impl<SessionKey, MutSession> DerefMut for MutSessionGuard<SessionKey, MutSession> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.guard
+    }
+}
+impl<SessionKey, MutSession> Drop for MutSessionGuard<SessionKey, MutSession> {
+    fn drop(&mut self) {
+        if !self.remove_on_drop {
+            return;
+        }
+        let Some(layer) = self.layer.upgrade() else {
+            return;
+        };
+        // Still locked, so no one else can act on the session before it is gone
+        (self.remove)(&layer, &self.key, OwnedMutexGuard::mutex(&self.guard));
+    }
+}
+impl<SessionKey, MutSession: std::fmt::Debug> std::fmt::Debug
+    for MutSessionGuard<SessionKey, MutSession>
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Debug::fmt(&*self.guard, f)
+    }
+}
+
+fn remove_locked<SessionKey, MutSession>(
+    layer: &SessionLayer<SessionKey, Session<MutSession>>,
+    key: &SessionKey,
+    mutex: &Arc<TokioMutex<MutSession>>,
+) where
+    SessionKey: std::fmt::Debug + Clone + Eq + std::hash::Hash + Sync + Send + 'static,
+    MutSession: std::fmt::Debug + Sync + Send + 'static,
+{
+    layer.remove_if(key, |session| Arc::ptr_eq(&session.mutex, mutex));
+}
+
+/// A lock on a session that remembers whether it was used mutably
+///
+/// Refreshes the session when dropped if so.
+pub struct TrackedMutSessionGuard<'a, SessionKey, MutSession> {
+    guard: MutSessionGuard<SessionKey, MutSession>,
+    dirty: bool,
+    refresh: Box<dyn Fn() + Send + Sync + 'a>,
+}
+impl<SessionKey, MutSession> Deref for TrackedMutSessionGuard<'_, SessionKey, MutSession> {
+    type Target = MutSession;
+
+    fn deref(&self) -> &Self::Target {
+        &self.guard
+    }
+}
+impl<SessionKey, MutSession> DerefMut for TrackedMutSessionGuard<'_, SessionKey, MutSession> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.dirty = true;
+        &mut self.guard
+    }
+}
+impl<SessionKey, MutSession> Drop for TrackedMutSessionGuard<'_, SessionKey, MutSession> {
+    fn drop(&mut self) {
+        if self.dirty {
+            (self.refresh)();
+        }
+    }
+}
+impl<SessionKey, MutSession: std::fmt::Debug> std::fmt::Debug
+    for TrackedMutSessionGuard<'_, SessionKey, MutSession>
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Debug::fmt(&*self.guard, f)
+    }
+}
+
+/// A place in the queue of a session lock
+struct Waiting<'a>(&'a AtomicUsize);
+impl<'a> Waiting<'a> {
+    /// Return [`None`] if `max` tasks are already waiting
+    fn enter(waiters: &'a AtomicUsize, max: Option<usize>) -> Option<Self> {
+        let before = waiters.fetch_add(1, Ordering::AcqRel);
+        let this = Self(waiters);
+        if max.is_some_and(|max| max <= before) {
+            return None;
+        }
+        Some(this)
+    }
+}
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
 /// Satisfy any bounds that [`SessionLayer`] requires
 #[derive(Debug)]
-struct Session<MutSession>(Arc<TokioMutex<MutSession>>);
+struct Session<MutSession> {
+    mutex: Arc<TokioMutex<MutSession>>,
+    /// Shared by all clones
+    waiters: Arc<AtomicUsize>,
+}
+impl<MutSession> Session<MutSession> {
+    fn new(mutex: Arc<TokioMutex<MutSession>>) -> Self {
+        Self {
+            mutex,
+            waiters: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Take the lock right away if it is free, or queue for it unless `max_waiters` tasks are already queued
+    async fn lock(&self, max_waiters: Option<usize>) -> Option<OwnedMutexGuard<MutSession>> {
+        if let Ok(mut_session) = Arc::clone(&self.mutex).try_lock_owned() {
+            return Some(mut_session);
+        }
+        let _waiting = Waiting::enter(&self.waiters, max_waiters)?;
+        Some(Arc::clone(&self.mutex).lock_owned().await)
+    }
+
+    /// Identifies the session for [`detect_reentrancy`] as long as it is locked
+    fn id(&self) -> usize {
+        Arc::as_ptr(&self.mutex) as *const () as usize
+    }
+
+    fn check_reentrancy(&self) {
+        check_reentrancy(self.id());
+    }
+}
 impl<MutSession> Clone for Session<MutSession> {
     fn clone(&self) -> Self {
-        Self(Arc::clone(&self.0))
+        Self {
+            mutex: Arc::clone(&self.mutex),
+            waiters: Arc::clone(&self.waiters),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{future::Future, sync::atomic::AtomicUsize};
+
+    use super::*;
+    use crate::{session::tests::idle, time::ManualClock};
+
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    #[test]
+    fn get_mut_or_try_insert_with_inserts_nothing_on_error() {
+        block_on(async {
+            let layer = MutSessionLayer::<u32, u32>::new(TIMEOUT);
+            let made = layer
+                .get_mut_or_try_insert_with(1, || Err("unavailable"))
+                .await;
+            assert_eq!(made.err(), Some("unavailable"));
+            assert_eq!(layer.stats_snapshot().len, 0);
+            let mut guard = layer
+                .get_mut_or_try_insert_with(1, || Ok::<_, ()>(10))
+                .await
+                .unwrap();
+            *guard += 1;
+            drop(guard);
+            assert_eq!(*layer.get_mut(&1).await.unwrap(), 11);
+        });
+    }
+
+    #[test]
+    fn try_get_mut_tells_closed_from_missing() {
+        let layer = MutSessionLayer::<u32, u32>::new_unbounded();
+        layer.insert(1, 10).unwrap();
+        block_on(async {
+            assert!(matches!(layer.try_get_mut(&2).await, Ok(None)));
+            let guard = layer.get_mut(&1).await.unwrap();
+            layer.close();
+            assert!(layer.is_closed());
+            assert!(matches!(
+                layer.try_get_mut(&1).await,
+                Err(TryGetMutError::Closed)
+            ));
+            assert!(layer.get_mut(&1).await.is_none());
+            // Guards handed out before closing stay usable
+            assert_eq!(*guard, 10);
+        });
+    }
+
+    #[test]
+    fn concurrent_get_mut_or_init_runs_one_init() {
+        let layer = Arc::new(MutSessionLayer::<u32, u32>::new_unbounded());
+        let inits = Arc::new(AtomicUsize::new(0));
+        block_on(async {
+            let tasks = (0..4)
+                .map(|_| {
+                    let layer = Arc::clone(&layer);
+                    let inits = Arc::clone(&inits);
+                    tokio::spawn(async move {
+                        let mut guard = layer
+                            .get_mut_or_init(1, || async {
+                                inits.fetch_add(1, Ordering::Relaxed);
+                                tokio::time::sleep(Duration::from_millis(10)).await;
+                                0
+                            })
+                            .await;
+                        *guard += 1;
+                    })
+                })
+                .collect::<Vec<_>>();
+            for task in tasks {
+                task.await.unwrap();
+            }
+            assert_eq!(*layer.get_mut(&1).await.unwrap(), 4);
+        });
+        assert_eq!(inits.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn take_moves_the_value_out_unless_busy() {
+        let layer = MutSessionLayer::<u32, String>::new_unbounded();
+        layer.insert(1, "state".to_owned()).unwrap();
+        block_on(async {
+            let guard = layer.get_mut(&1).await.unwrap();
+            assert!(matches!(layer.take(&1), Err(SessionBusy)));
+            drop(guard);
+        });
+        assert_eq!(layer.take(&1), Ok(Some("state".to_owned())));
+        assert_eq!(layer.take(&1), Ok(None));
+        assert_eq!(layer.stats_snapshot().len, 0);
+    }
+
+    #[test]
+    fn blocking_get_mut_waits_for_async_guards() {
+        let layer = Arc::new(MutSessionLayer::<u32, Vec<&str>>::new_unbounded());
+        layer.insert(1, vec![]).unwrap();
+        block_on(async {
+            let mut guard = layer.get_mut(&1).await.unwrap();
+            let blocking = tokio::task::spawn_blocking({
+                let layer = Arc::clone(&layer);
+                move || {
+                    assert!(layer.blocking_get_mut(&2).is_none());
+                    layer.blocking_get_mut(&1).unwrap().push("blocking");
+                }
+            });
+            // The blocking side cannot get in before the guard is dropped
+            guard.push("async");
+            drop(guard);
+            blocking.await.unwrap();
+            assert_eq!(*layer.get_mut(&1).await.unwrap(), ["async", "blocking"]);
+        });
+    }
+
+    #[test]
+    fn shutdown_with_waits_for_held_guards() {
+        let layer = Arc::new(MutSessionLayer::<u32, Vec<&str>>::new_unbounded());
+        layer.insert(1, vec![]).unwrap();
+        block_on(async {
+            let mut guard = layer.get_mut(&1).await.unwrap();
+            let holder = tokio::spawn(async move {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                guard.push("held");
+            });
+            let closed = TokioMutex::new(vec![]);
+            layer
+                .shutdown_with(1, |key, guard| {
+                    let closed = &closed;
+                    async move { closed.lock().await.push((key, guard.clone())) }
+                })
+                .await;
+            holder.await.unwrap();
+            assert_eq!(closed.into_inner(), [(1, vec!["held"])]);
+            assert!(layer.is_closed());
+            assert!(layer.is_empty());
+        });
+    }
+
+    #[test]
+    fn max_waiters_fails_fast_once_the_queue_is_full() {
+        let layer = Arc::new(
+            MutSessionLayerBuilder::<u32, u32>::unbounded()
+                .max_waiters(1)
+                .build()
+                .unwrap(),
+        );
+        layer.insert(1, 0).unwrap();
+        block_on(async {
+            let guard = layer.get_mut(&1).await.unwrap();
+            let waiter = tokio::spawn({
+                let layer = Arc::clone(&layer);
+                async move { *layer.get_mut(&1).await.unwrap() += 1 }
+            });
+            tokio::task::yield_now().await;
+            assert!(matches!(
+                layer.try_get_mut(&1).await,
+                Err(TryGetMutError::Busy)
+            ));
+            drop(guard);
+            waiter.await.unwrap();
+            assert_eq!(*layer.get_mut(&1).await.unwrap(), 1);
+        });
+    }
+
+    #[test]
+    fn zero_max_waiters_only_fails_while_held() {
+        let layer = MutSessionLayerBuilder::<u32, u32>::unbounded()
+            .max_waiters(0)
+            .build()
+            .unwrap();
+        layer.insert(1, 0).unwrap();
+        block_on(async {
+            let guard = layer.get_mut(&1).await.unwrap();
+            assert!(layer.get_mut(&1).await.is_none());
+            drop(guard);
+            assert!(layer.get_mut(&1).await.is_some());
+        });
+    }
+
+    #[test]
+    fn shared_state_is_locked_by_both_sides() {
+        let layer = MutSessionLayer::<u32, u32>::new_unbounded();
+        let state = Arc::new(TokioMutex::new(0));
+        layer.insert_shared(1, Arc::clone(&state)).unwrap();
+        assert!(layer
+            .insert_shared(1, Arc::new(TokioMutex::new(1)))
+            .is_err());
+        assert!(Arc::ptr_eq(&layer.get_shared(&1).unwrap(), &state));
+        assert!(layer.get_shared(&2).is_none());
+        block_on(async {
+            *state.lock().await += 1;
+            *layer.get_mut(&1).await.unwrap() += 1;
+            let held = state.lock().await;
+            assert!(layer.get_shared(&1).unwrap().try_lock().is_err());
+            drop(held);
+            assert_eq!(*state.lock().await, 2);
+        });
+    }
+
+    #[test]
+    fn refresh_on_mutation_ignores_reads() {
+        let layer = block_on(async {
+            MutSessionLayer::<u32, u32>::builder(TIMEOUT)
+                .refresh_on_mutation()
+                .build()
+                .unwrap()
+        });
+        for key in 0..5 {
+            layer.insert(key, 0).unwrap();
+            idle(&layer.session, &key, TIMEOUT / 2);
+        }
+        block_on(async {
+            let _ = *layer.get_mut(&0).await.unwrap();
+            let _ = *layer.get_mut_tracked(&1).await.unwrap();
+            *layer.get_mut_tracked(&2).await.unwrap() += 1;
+            assert!(layer.modify(&3, |_| false).await);
+            assert!(
+                layer
+                    .modify(&4, |count| {
+                        *count += 1;
+                        true
+                    })
+                    .await
+            );
+        });
+        for key in 0..5 {
+            idle(&layer.session, &key, TIMEOUT / 2);
+        }
+        layer.sweep();
+        let kept = (0..5)
+            .filter(|key| layer.session.contains_key(key))
+            .collect::<Vec<_>>();
+        assert_eq!(kept, [2, 4]);
+    }
+
+    #[test]
+    fn remove_many_leaves_held_guards_valid() {
+        let layer = MutSessionLayer::<u32, u32>::new_unbounded();
+        layer.insert(1, 10).unwrap();
+        layer.insert(2, 20).unwrap();
+        block_on(async {
+            let mut guard = layer.get_mut(&1).await.unwrap();
+            let removed = layer.remove_many(&[1, 3]);
+            assert_eq!(removed.len(), 1);
+            *guard += 1;
+            drop(guard);
+            assert_eq!(*removed[0].1.lock().await, 11);
+            assert!(layer.get_mut(&1).await.is_none());
+            assert_eq!(layer.len(), 1);
+        });
+    }
+
+    #[test]
+    fn clone_sessions_skips_locked_sessions() {
+        let layer = MutSessionLayer::<u32, u32>::new_unbounded();
+        layer.insert(1, 10).unwrap();
+        layer.insert(2, 20).unwrap();
+        block_on(async {
+            let guard = layer.get_mut(&1).await.unwrap();
+            let snapshot = layer.clone_sessions();
+            assert_eq!(snapshot.cloned, [(2, 20)]);
+            assert_eq!(snapshot.skipped, [1]);
+            drop(guard);
+        });
+        assert_eq!(layer.clone_sessions().cloned.len(), 2);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn clone_sessions_within_waits_for_released_guards() {
+        block_on(async {
+            let layer = MutSessionLayer::<u32, u32>::new_unbounded();
+            layer.insert(1, 10).unwrap();
+            let guard = layer.get_mut(&1).await.unwrap();
+            let snapshot = layer.clone_sessions_within(Duration::from_millis(1)).await;
+            assert_eq!(snapshot.skipped, [1]);
+
+            let releaser = tokio::spawn(async move {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                drop(guard);
+            });
+            let snapshot = layer.clone_sessions_within(Duration::from_secs(5)).await;
+            assert_eq!(snapshot.cloned, [(1, 10)]);
+            releaser.await.unwrap();
+        });
+    }
+
+    #[test]
+    fn try_peek_projects_without_refreshing() {
+        let layer = block_on(async { MutSessionLayer::<u32, Vec<u32>>::new(TIMEOUT) });
+        layer.insert(1, vec![1, 2, 3]).unwrap();
+        assert!(layer.try_peek(&2, Vec::len).is_none());
+        block_on(async {
+            let _guard = layer.get_mut(&1).await.unwrap();
+            assert!(matches!(
+                layer.try_peek(&1, Vec::len),
+                Some(Err(SessionBusy))
+            ));
+        });
+        idle(&layer.session, &1, TIMEOUT / 2);
+        assert!(matches!(layer.try_peek(&1, Vec::len), Some(Ok(3))));
+        idle(&layer.session, &1, TIMEOUT / 2);
+        layer.sweep();
+        assert!(layer.try_peek(&1, Vec::len).is_none());
+    }
+
+    #[test]
+    fn waiters_counts_tasks_queued_on_a_held_session() {
+        let layer = Arc::new(MutSessionLayer::<u32, u32>::new_unbounded());
+        layer.insert(1, 0).unwrap();
+        assert_eq!(layer.waiters(&1), Some(0));
+        assert_eq!(layer.waiters(&2), None);
+        block_on(async {
+            let guard = layer.get_mut(&1).await.unwrap();
+            let tasks = (0..3)
+                .map(|_| {
+                    let layer = Arc::clone(&layer);
+                    tokio::spawn(async move { *layer.get_mut(&1).await.unwrap() += 1 })
+                })
+                .collect::<Vec<_>>();
+            tokio::task::yield_now().await;
+            assert_eq!(layer.waiters(&1), Some(3));
+            drop(guard);
+            for task in tasks {
+                task.await.unwrap();
+            }
+            assert_eq!(layer.waiters(&1), Some(0));
+            assert_eq!(*layer.get_mut(&1).await.unwrap(), 3);
+        });
+    }
+
+    #[test]
+    fn lookup_mut_reports_expired_sessions() {
+        let clock = ManualClock::new();
+        let layer = MutSessionLayerBuilder::<u32, u32>::new(TIMEOUT)
+            .manual_sweep()
+            .time_source(TimeSource::Manual(clock.clone()))
+            .tombstones(16, TIMEOUT)
+            .build()
+            .unwrap();
+        layer.insert(1, 10).unwrap();
+        block_on(async {
+            assert!(matches!(layer.lookup_mut(&1).await, Lookup::Found(guard) if *guard == 10));
+            clock.advance(TIMEOUT);
+            layer.sweep();
+            assert!(matches!(layer.lookup_mut(&1).await, Lookup::Expired { .. }));
+            assert!(matches!(layer.lookup_mut(&2).await, Lookup::Unknown));
+        });
+    }
+
+    /// A builder of layers that only sweep when told to and follow the returned clock
+    fn manual<K, M>() -> (MutSessionLayerBuilder<K, M>, ManualClock) {
+        let clock = ManualClock::new();
+        let builder = MutSessionLayerBuilder::new(TIMEOUT)
+            .manual_sweep()
+            .time_source(TimeSource::Manual(clock.clone()));
+        (builder, clock)
+    }
+
+    #[test]
+    fn resume_or_insert_resumes_until_the_session_expires() {
+        let (builder, clock) = manual::<u32, Vec<&str>>();
+        let layer = builder.build().unwrap();
+        block_on(async {
+            layer.resume_or_insert(1, Vec::new).await.push("first");
+            clock.advance(TIMEOUT / 2);
+            layer
+                .resume_or_insert(1, || unreachable!("still resumable"))
+                .await
+                .push("resumed");
+            // Resuming refreshed the session
+            clock.advance(TIMEOUT / 2);
+            layer.sweep();
+            assert_eq!(
+                *layer.resume_or_insert(1, Vec::new).await,
+                ["first", "resumed"]
+            );
+
+            clock.advance(TIMEOUT);
+            layer.sweep();
+            assert!(layer.resume_or_insert(1, Vec::new).await.is_empty());
+        });
+    }
+
+    #[test]
+    fn guards_touch_and_remove_their_own_session() {
+        let (builder, clock) = manual::<u32, u32>();
+        let layer = builder.build().unwrap();
+        layer.insert(1, 10).unwrap();
+        block_on(async {
+            let guard = layer.get_mut(&1).await.unwrap();
+            assert_eq!(*guard.key(), 1);
+            clock.advance(TIMEOUT / 2);
+            assert!(guard.touch());
+            clock.advance(TIMEOUT / 2);
+            layer.sweep();
+            assert!(guard.touch());
+            drop(guard);
+
+            let mut guard = layer.get_mut(&1).await.unwrap();
+            guard.remove_on_drop();
+            drop(guard);
+            assert!(layer.get_mut(&1).await.is_none());
+
+            // A key given to another session meanwhile is left alone
+            layer.insert(2, 20).unwrap();
+            let mut guard = layer.get_mut(&2).await.unwrap();
+            guard.remove_on_drop();
+            layer.remove_many(&[2]);
+            layer.insert(2, 21).unwrap();
+            drop(guard);
+            assert_eq!(*layer.get_mut(&2).await.unwrap(), 21);
+        });
+    }
+
+    #[test]
+    fn failed_loads_hand_over_to_the_next_waiter() {
+        let layer = Arc::new(MutSessionLayer::<u32, u32>::new_unbounded());
+        let attempts = Arc::new(AtomicUsize::new(0));
+        block_on(async {
+            let tasks = (0..3)
+                .map(|_| {
+                    let layer = Arc::clone(&layer);
+                    let attempts = Arc::clone(&attempts);
+                    tokio::spawn(async move {
+                        let loaded = layer
+                            .get_mut_or_try_insert(1, || async {
+                                let attempt = attempts.fetch_add(1, Ordering::Relaxed);
+                                tokio::time::sleep(Duration::from_millis(10)).await;
+                                match attempt {
+                                    0 => Err("upstream down"),
+                                    _ => Ok(10),
+                                }
+                            })
+                            .await;
+                        loaded.map(|guard| *guard)
+                    })
+                })
+                .collect::<Vec<_>>();
+            let mut outcomes = vec![];
+            for task in tasks {
+                outcomes.push(task.await.unwrap());
+            }
+            outcomes.sort();
+            assert_eq!(outcomes, [Ok(10), Ok(10), Err("upstream down")]);
+        });
+        // Only the failed attempt and one successful retry ran
+        assert_eq!(attempts.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn strict_layers_never_lock_stale_sessions() {
+        let (builder, clock) = manual::<u32, u32>();
+        let layer = builder
+            .resurrect_policy(ResurrectPolicy::Strict)
+            .build()
+            .unwrap();
+        layer.insert(1, 10).unwrap();
+        clock.advance(TIMEOUT);
+        block_on(async {
+            assert!(layer.get_mut(&1).await.is_none());
+        });
+        assert!(layer.is_empty());
+    }
+
+    #[test]
+    fn max_concurrent_loads_bounds_factories_across_keys() {
+        let (builder, _) = manual::<u32, u32>();
+        let layer = Arc::new(builder.max_concurrent_loads(2).build().unwrap());
+        let running = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+        block_on(async {
+            let tasks = (0..6)
+                .map(|key| {
+                    let (layer, running, peak) =
+                        (Arc::clone(&layer), Arc::clone(&running), Arc::clone(&peak));
+                    tokio::spawn(async move {
+                        let guard = layer
+                            .get_mut_or_init(key, || async {
+                                let now = running.fetch_add(1, Ordering::Relaxed) + 1;
+                                peak.fetch_max(now, Ordering::Relaxed);
+                                tokio::time::sleep(Duration::from_millis(5)).await;
+                                running.fetch_sub(1, Ordering::Relaxed);
+                                key
+                            })
+                            .await;
+                        assert_eq!(*guard, key);
+                    })
+                })
+                .collect::<Vec<_>>();
+            for task in tasks {
+                task.await.unwrap();
+            }
+        });
+        assert_eq!(peak.load(Ordering::Relaxed), 2);
+        assert_eq!(layer.len(), 6);
+
+        let (builder, _) = manual::<u32, u32>();
+        assert!(matches!(
+            builder.max_concurrent_loads(0).build(),
+            Err(NewSessionLayerError::ZeroConcurrentLoads)
+        ));
+    }
+
+    #[test]
+    fn get_mut_and_idle_refreshes_even_when_refreshing_on_mutation() {
+        let (builder, clock) = manual::<u32, u32>();
+        let layer = builder.refresh_on_mutation().build().unwrap();
+        block_on(async {
+            layer.insert(1, 0).unwrap();
+            clock.advance(Duration::from_secs(4));
+            let (guard, idle) = layer.get_mut_and_idle(&1).await.unwrap();
+            assert_eq!(idle, Duration::from_secs(4));
+            drop(guard);
+            let (_, idle) = layer.get_mut_and_idle(&1).await.unwrap();
+            assert_eq!(idle, Duration::ZERO);
+            assert!(layer.get_mut_and_idle(&2).await.is_none());
+        });
+    }
+
+    #[test]
+    fn admission_vets_inserted_sessions_but_not_shared_state() {
+        let (builder, _) = manual::<u32, u32>();
+        let layer = builder
+            .admission(|key, _| match key {
+                1 => Err(AdmissionDenied::new("reserved")),
+                _ => Ok(()),
+            })
+            .build()
+            .unwrap();
+        assert!(matches!(
+            layer.insert(1, 10),
+            Err(MutInsertError::Denied {
+                mut_session: 10,
+                ..
+            })
+        ));
+        assert!(matches!(
+            layer.insert_at(1, 11, Instant::now()),
+            Err(MutInsertError::Denied {
+                mut_session: 11,
+                ..
+            })
+        ));
+        layer.insert(2, 20).unwrap();
+        assert!(matches!(
+            layer.insert(2, 21),
+            Err(MutInsertError::Collision)
+        ));
+        layer
+            .insert_shared(1, Arc::new(TokioMutex::new(10)))
+            .unwrap();
+        block_on(async {
+            assert_eq!(*layer.get_mut(&1).await.unwrap(), 10);
+            assert_eq!(*layer.get_mut(&2).await.unwrap(), 20);
+        });
+    }
+
+    #[test]
+    fn insert_rate_limit_hands_the_session_back() {
+        let (builder, clock) = manual::<u32, u32>();
+        let layer = builder
+            .insert_rate_limit(1, Duration::from_secs(1))
+            .build()
+            .unwrap();
+        layer.insert(1, 10).unwrap();
+        assert!(matches!(
+            layer.insert(2, 20),
+            Err(MutInsertError::RateLimited(20))
+        ));
+        layer
+            .insert_shared(3, Arc::new(TokioMutex::new(30)))
+            .unwrap();
+        clock.advance(Duration::from_secs(1));
+        layer.insert_at(2, 21, Instant::now()).unwrap();
+        let (builder, _) = manual::<u32, u32>();
+        assert!(matches!(
+            builder.insert_rate_limit(0, Duration::from_secs(1)).build(),
+            Err(NewSessionLayerError::ZeroRateLimitCapacity)
+        ));
+    }
+
+    #[cfg(all(debug_assertions, feature = "tokio"))]
+    #[test]
+    #[should_panic(expected = "already locked by a guard held by this task")]
+    fn reentrant_get_mut_panics_within_detect_reentrancy() {
+        let (builder, _) = manual::<u32, u32>();
+        let layer = builder.build().unwrap();
+        layer.insert(1, 10).unwrap();
+        block_on(crate::detect_reentrancy(async {
+            let _guard = layer.get_mut(&1).await.unwrap();
+            layer.get_mut(&1).await
+        }));
+    }
+
+    #[cfg(all(debug_assertions, feature = "tokio"))]
+    #[test]
+    #[should_panic(expected = "already locked by a guard held by this task")]
+    fn guards_of_get_mut_or_init_are_registered_within_detect_reentrancy() {
+        let (builder, _) = manual::<u32, u32>();
+        let layer = builder.build().unwrap();
+        block_on(crate::detect_reentrancy(async {
+            let _guard = layer.get_mut_or_init(1, || async { 10 }).await;
+            layer.resume_or_insert(1, || 11).await
+        }));
+    }
+
+    #[test]
+    fn detect_reentrancy_allows_distinct_and_released_sessions() {
+        let (builder, _) = manual::<u32, u32>();
+        let layer = builder.build().unwrap();
+        layer.insert(1, 10).unwrap();
+        layer.insert(2, 20).unwrap();
+        block_on(crate::detect_reentrancy(async {
+            let first = layer.get_mut(&1).await.unwrap();
+            let second = layer.get_mut(&2).await.unwrap();
+            assert_eq!(*first + *second, 30);
+            drop(first);
+            assert_eq!(*layer.get_mut(&1).await.unwrap(), 10);
+        }));
+    }
+
+    #[test]
+    fn get_mut_if_fresh_refuses_sessions_about_to_expire() {
+        let (builder, clock) = manual::<u32, u32>();
+        let layer = builder.max_waiters(0).build().unwrap();
+        block_on(async {
+            layer.insert(1, 0).unwrap();
+            clock.advance(TIMEOUT - Duration::from_secs(2));
+            assert!(matches!(
+                layer.get_mut_if_fresh(&1, Duration::from_secs(3)).await,
+                Err(FreshnessError::Stale { remaining }) if remaining == Duration::from_secs(2)
+            ));
+            // A refused session is left untouched
+            clock.advance(Duration::from_secs(1));
+            assert!(matches!(
+                layer.get_mut_if_fresh(&1, Duration::from_secs(3)).await,
+                Err(FreshnessError::Stale { remaining }) if remaining == Duration::from_secs(1)
+            ));
+            let guard = layer
+                .get_mut_if_fresh(&1, Duration::from_secs(1))
+                .await
+                .unwrap();
+            // The session handed out was refreshed
+            assert!(matches!(
+                layer.get_mut_if_fresh(&1, TIMEOUT).await,
+                Err(FreshnessError::Busy)
+            ));
+            drop(guard);
+            assert!(matches!(
+                layer.get_mut_if_fresh(&2, Duration::ZERO).await,
+                Err(FreshnessError::NotFound)
+            ));
+            layer.close();
+            assert!(matches!(
+                layer.get_mut_if_fresh(&1, Duration::ZERO).await,
+                Err(FreshnessError::Closed)
+            ));
+        });
+    }
+
+    #[test]
+    fn soft_timeout_flags_sessions_until_they_are_accessed() {
+        let (builder, clock) = manual::<u32, u32>();
+        let layer = builder.soft_timeout(TIMEOUT / 2).build().unwrap();
+        block_on(async {
+            layer.insert(1, 0).unwrap();
+            assert_eq!(layer.is_expiring(&1), Some(false));
+            assert_eq!(layer.is_expiring(&2), None);
+            clock.advance(TIMEOUT / 2);
+            assert_eq!(layer.is_expiring(&1), Some(true));
+            let (_, expiring) = layer.get_mut_flagged(&1).await.unwrap();
+            assert!(expiring);
+            assert_eq!(layer.is_expiring(&1), Some(false));
+            let (_, expiring) = layer.get_mut_flagged(&1).await.unwrap();
+            assert!(!expiring);
+        });
+    }
+
+    #[test]
+    fn without_a_soft_timeout_nothing_is_flagged() {
+        let (builder, clock) = manual::<u32, u32>();
+        let layer = builder.refresh_on_mutation().build().unwrap();
+        block_on(async {
+            layer.insert(1, 0).unwrap();
+            clock.advance(TIMEOUT - Duration::from_millis(1));
+            assert_eq!(layer.is_expiring(&1), Some(false));
+            let (_, expiring) = layer.get_mut_flagged(&1).await.unwrap();
+            assert!(!expiring);
+        });
+    }
+
+    #[test]
+    fn lenient_layers_lock_and_save_stale_sessions() {
+        let (builder, clock) = manual::<u32, u32>();
+        let layer = builder
+            .resurrect_policy(ResurrectPolicy::Lenient)
+            .build()
+            .unwrap();
+        layer.insert(1, 10).unwrap();
+        layer.insert(2, 20).unwrap();
+        clock.advance(TIMEOUT);
+        block_on(async {
+            assert_eq!(*layer.get_mut(&1).await.unwrap(), 10);
+        });
+        layer.sweep();
+        assert_eq!(layer.len(), 1);
+        block_on(async {
+            assert!(layer.get_mut(&2).await.is_none());
+        });
+    }
+
+    #[test]
+    fn debug_summarizes_without_the_sessions() {
+        let (builder, _) = manual::<String, String>();
+        let layer = builder.build().unwrap();
+        layer
+            .insert("secret-key".to_owned(), "secret-state".to_owned())
+            .unwrap();
+        let debug = format!("{layer:?}");
+        assert!(debug.starts_with("MutSessionLayer {"), "{debug}");
+        assert!(debug.contains("len: 1"), "{debug}");
+        assert!(!debug.contains("secret"), "{debug}");
     }
 }
@@ -0,0 +1,74 @@
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+/// A token bucket that takes no lock
+///
+/// Tracked as the time at which the bucket would be full again, as in the generic cell rate algorithm, so that one atomic holds the whole state.
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+    origin: Instant,
+    /// The refill time of one token in nanoseconds
+    interval: u64,
+    /// The refill time of the whole bucket in nanoseconds
+    burst: u64,
+    /// Nanoseconds since `origin` at which the bucket is full again
+    full_at: AtomicU64,
+}
+impl RateLimiter {
+    /// `capacity` must be positive
+    pub fn new(capacity: u32, refill_interval: Duration, origin: Instant) -> Self {
+        let interval = u64::try_from(refill_interval.as_nanos()).unwrap_or(u64::MAX);
+        Self {
+            origin,
+            interval,
+            burst: interval.saturating_mul(u64::from(capacity)),
+            full_at: AtomicU64::new(0),
+        }
+    }
+
+    /// Take a token unless the bucket is empty at `now`
+    pub fn try_acquire(&self, now: Instant) -> bool {
+        let now = u64::try_from(now.saturating_duration_since(self.origin).as_nanos())
+            .unwrap_or(u64::MAX);
+        let mut full_at = self.full_at.load(Ordering::Relaxed);
+        loop {
+            let new_full_at = full_at.max(now).saturating_add(self.interval);
+            if self.burst < new_full_at - now {
+                return false;
+            }
+            match self.full_at.compare_exchange_weak(
+                full_at,
+                new_full_at,
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return true,
+                Err(actual) => full_at = actual,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bucket_starts_full_and_refills_one_token_per_interval() {
+        let origin = Instant::now();
+        let limiter = RateLimiter::new(2, Duration::from_secs(1), origin);
+        assert!(limiter.try_acquire(origin));
+        assert!(limiter.try_acquire(origin));
+        assert!(!limiter.try_acquire(origin));
+        assert!(!limiter.try_acquire(origin + Duration::from_millis(999)));
+        assert!(limiter.try_acquire(origin + Duration::from_secs(1)));
+        assert!(!limiter.try_acquire(origin + Duration::from_secs(1)));
+        // Idle time refills no more than the capacity
+        let later = origin + Duration::from_secs(60);
+        assert!(limiter.try_acquire(later));
+        assert!(limiter.try_acquire(later));
+        assert!(!limiter.try_acquire(later));
+    }
+}
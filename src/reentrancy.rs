@@ -0,0 +1,81 @@
+use std::future::Future;
+
+#[cfg(all(debug_assertions, feature = "tokio"))]
+use std::sync::Arc;
+
+#[cfg(all(debug_assertions, feature = "tokio"))]
+use crate::lock::Mutex;
+
+/// The sessions locked through [`crate::MutSessionGuard`]s taken by the current task
+///
+/// Shared with the guards so that a guard dropped by another task still unregisters itself.
+#[cfg(all(debug_assertions, feature = "tokio"))]
+type HeldSet = Arc<Mutex<Vec<usize>>>;
+
+#[cfg(all(debug_assertions, feature = "tokio"))]
+tokio::task_local! {
+    static HELD: HeldSet;
+}
+
+/// Run `fut` such that [`crate::MutSessionLayer::get_mut`] and its variants panic instead of deadlocking when the task already holds a guard of the same session
+///
+/// Only debug builds with the `tokio` feature check anything, and only within this scope, so release builds pay nothing.
+/// Wrap the body of each task, e.g. of each connection handler.
+pub async fn detect_reentrancy<F: Future>(fut: F) -> F::Output {
+    #[cfg(all(debug_assertions, feature = "tokio"))]
+    return HELD.scope(HeldSet::default(), fut).await;
+    #[cfg(not(all(debug_assertions, feature = "tokio")))]
+    fut.await
+}
+
+/// Panic if the current task holds a guard of the session at `id` within [`detect_reentrancy`]
+#[cfg_attr(not(all(debug_assertions, feature = "tokio")), allow(unused_variables))]
+pub(crate) fn check_reentrancy(id: usize) {
+    #[cfg(all(debug_assertions, feature = "tokio"))]
+    {
+        let held = HELD
+            .try_with(|held| held.lock().contains(&id))
+            .unwrap_or(false);
+        assert!(
+            !held,
+            "the session is already locked by a guard held by this task, so locking it again would deadlock"
+        );
+    }
+}
+
+/// Registers a locked session with the current task until dropped
+#[derive(Debug)]
+pub(crate) struct Held {
+    #[cfg(all(debug_assertions, feature = "tokio"))]
+    registration: Option<(HeldSet, usize)>,
+}
+impl Held {
+    #[cfg_attr(not(all(debug_assertions, feature = "tokio")), allow(unused_variables))]
+    pub fn new(id: usize) -> Self {
+        #[cfg(all(debug_assertions, feature = "tokio"))]
+        {
+            let registration = HELD
+                .try_with(|held| {
+                    held.lock().push(id);
+                    Arc::clone(held)
+                })
+                .ok()
+                .map(|held| (held, id));
+            Self { registration }
+        }
+        #[cfg(not(all(debug_assertions, feature = "tokio")))]
+        Self {}
+    }
+}
+#[cfg(all(debug_assertions, feature = "tokio"))]
+impl Drop for Held {
+    fn drop(&mut self) {
+        let Some((held, id)) = &self.registration else {
+            return;
+        };
+        let mut held = held.lock();
+        if let Some(i) = held.iter().position(|held| held == id) {
+            held.swap_remove(i);
+        }
+    }
+}
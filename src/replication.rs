@@ -0,0 +1,186 @@
+use std::sync::Arc;
+
+use crate::{hook::Hook, lock::Mutex};
+
+/// Mirror every mutation of a layer elsewhere, e.g. to a standby peer over your own transport
+///
+/// Registered by [`crate::SessionLayerBuilder::replication_sink`].
+/// Called outside the internal locks right after each mutation, including sweeps and replacements.
+/// Concurrent mutations of the same key may thus be reported out of order.
+/// Dropping the layer is not reported.
+pub trait ReplicationSink<SessionKey, SessionHandle>: Send + Sync + 'static {
+    /// The session was inserted or its handle was replaced
+    fn on_insert(&self, key: &SessionKey, session: &SessionHandle);
+
+    fn on_remove(&self, key: &SessionKey, cause: RemovalCause);
+
+    /// The session was refreshed by an access
+    ///
+    /// Called on every hit, so it does nothing by default.
+    fn on_touch(&self, key: &SessionKey) {
+        let _ = key;
+    }
+}
+impl<SessionKey, SessionHandle, S> ReplicationSink<SessionKey, SessionHandle> for Arc<S>
+where
+    S: ReplicationSink<SessionKey, SessionHandle> + ?Sized,
+{
+    fn on_insert(&self, key: &SessionKey, session: &SessionHandle) {
+        (**self).on_insert(key, session);
+    }
+
+    fn on_remove(&self, key: &SessionKey, cause: RemovalCause) {
+        (**self).on_remove(key, cause);
+    }
+
+    fn on_touch(&self, key: &SessionKey) {
+        (**self).on_touch(key);
+    }
+}
+
+/// Why a session left the layer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RemovalCause {
+    /// Idle for longer than the timeout
+    Expired,
+    /// Reported dead by the liveness check
+    Dead,
+    /// Removed, taken, or drained by the caller
+    Removed,
+    /// Least recently used when the weight budget was exceeded
+    Evicted,
+    /// Moved to another key, reported by [`ReplicationSink::on_insert`] next
+    Rekeyed,
+}
+
+/// A mutation recorded by [`BufferedSink`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReplicationEvent<SessionKey, SessionHandle> {
+    Insert {
+        key: SessionKey,
+        session: SessionHandle,
+    },
+    Remove {
+        key: SessionKey,
+        cause: RemovalCause,
+    },
+    Touch {
+        key: SessionKey,
+    },
+}
+
+/// Record mutations in memory until the caller flushes them in batches, e.g. to send one RPC per batch
+///
+/// Register it behind an [`Arc`] to keep a way to flush it.
+#[derive(Debug)]
+pub struct BufferedSink<SessionKey, SessionHandle> {
+    events: Mutex<Vec<ReplicationEvent<SessionKey, SessionHandle>>>,
+}
+impl<SessionKey, SessionHandle> BufferedSink<SessionKey, SessionHandle> {
+    pub fn new() -> Self {
+        Self {
+            events: Mutex::new(vec![]),
+        }
+    }
+
+    /// Take all mutations recorded so far in order
+    pub fn flush(&self) -> Vec<ReplicationEvent<SessionKey, SessionHandle>> {
+        std::mem::take(&mut *self.events.lock())
+    }
+}
+impl<SessionKey, SessionHandle> Default for BufferedSink<SessionKey, SessionHandle> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl<SessionKey, SessionHandle> ReplicationSink<SessionKey, SessionHandle>
+    for BufferedSink<SessionKey, SessionHandle>
+where
+    SessionKey: Clone + Send + 'static,
+    SessionHandle: Clone + Send + 'static,
+{
+    fn on_insert(&self, key: &SessionKey, session: &SessionHandle) {
+        self.events.lock().push(ReplicationEvent::Insert {
+            key: key.clone(),
+            session: session.clone(),
+        });
+    }
+
+    fn on_remove(&self, key: &SessionKey, cause: RemovalCause) {
+        self.events.lock().push(ReplicationEvent::Remove {
+            key: key.clone(),
+            cause,
+        });
+    }
+
+    fn on_touch(&self, key: &SessionKey) {
+        self.events
+            .lock()
+            .push(ReplicationEvent::Touch { key: key.clone() });
+    }
+}
+
+/// A sink along with a way to clone handles for it under the lock
+///
+/// Only layers of handles that can be cloned get a sink, which leaves the rest of the layer usable with unique handles.
+#[derive(Debug)]
+pub(crate) struct Replication<SessionKey, SessionHandle> {
+    pub sink: Hook<dyn ReplicationSink<SessionKey, SessionHandle>>,
+    pub clone_session: fn(&SessionHandle) -> SessionHandle,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{session::tests::idle, SessionLayerBuilder};
+
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    #[test]
+    fn buffered_sink_records_every_mutation_in_order() {
+        let sink = Arc::new(BufferedSink::new());
+        let layer = SessionLayerBuilder::<u32, u32>::new(TIMEOUT)
+            .manual_sweep()
+            .replication_sink(Arc::clone(&sink))
+            .build()
+            .unwrap();
+        layer.insert(1, 10).unwrap();
+        layer.insert(2, 20).unwrap();
+        layer.get(&1);
+        layer.insert_or_replace(1, 11).unwrap();
+        layer.remove(&1);
+        idle(&layer, &2, TIMEOUT);
+        layer.sweep();
+
+        use ReplicationEvent::*;
+        assert_eq!(
+            sink.flush(),
+            [
+                Insert {
+                    key: 1,
+                    session: 10
+                },
+                Insert {
+                    key: 2,
+                    session: 20
+                },
+                Touch { key: 1 },
+                Insert {
+                    key: 1,
+                    session: 11
+                },
+                Remove {
+                    key: 1,
+                    cause: RemovalCause::Removed
+                },
+                Remove {
+                    key: 2,
+                    cause: RemovalCause::Expired
+                },
+            ]
+        );
+        assert!(sink.flush().is_empty());
+    }
+}
@@ -0,0 +1,125 @@
+use std::{collections::HashMap, hash::Hash, sync::Arc, time::Duration};
+
+use crate::{
+    lock::Mutex,
+    session::{InsertError, SessionLayer},
+    time::Timestamp,
+};
+
+/// A key claimed by [`SessionLayer::reserve`] until its session is ready
+///
+/// The key collides with insertions but is not found by lookups.
+/// Dropping the reservation releases the key.
+#[derive(Debug)]
+pub struct Reservation<SessionKey, SessionHandle, Metadata = ()> {
+    layer: Arc<SessionLayer<SessionKey, SessionHandle, Metadata>>,
+    key: SessionKey,
+    id: u64,
+    /// Captured where the bounds of the layer hold, so they need not be repeated on the reservation
+    release: fn(&SessionLayer<SessionKey, SessionHandle, Metadata>, &SessionKey, u64),
+}
+impl<SessionKey, SessionHandle, Metadata> Reservation<SessionKey, SessionHandle, Metadata> {
+    pub(crate) fn new(
+        layer: Arc<SessionLayer<SessionKey, SessionHandle, Metadata>>,
+        key: SessionKey,
+        id: u64,
+        release: fn(&SessionLayer<SessionKey, SessionHandle, Metadata>, &SessionKey, u64),
+    ) -> Self {
+        Self {
+            layer,
+            key,
+            id,
+            release,
+        }
+    }
+
+    pub fn key(&self) -> &SessionKey {
+        &self.key
+    }
+}
+impl<SessionKey, SessionHandle, Metadata> Reservation<SessionKey, SessionHandle, Metadata>
+where
+    SessionKey: std::fmt::Debug + Hash + Eq + Clone + Sync + Send + 'static,
+    SessionHandle: std::fmt::Debug + Sync + Send + 'static,
+    Metadata: Default + Sync + Send + 'static,
+{
+    /// Insert the session under the reserved key
+    ///
+    /// Still succeeds after the reservation has expired unless the key has been taken since.
+    pub fn fulfill(self, session: SessionHandle) -> Result<(), InsertError<SessionHandle>> {
+        self.layer
+            .fulfill_reservation(self.key.clone(), self.id, session)
+    }
+}
+impl<SessionKey, SessionHandle, Metadata> Drop
+    for Reservation<SessionKey, SessionHandle, Metadata>
+{
+    fn drop(&mut self) {
+        // A no-op once fulfilled
+        (self.release)(&self.layer, &self.key, self.id);
+    }
+}
+
+/// The keys reserved but not yet fulfilled
+#[derive(Debug)]
+pub(crate) struct Reservations<SessionKey> {
+    /// [`None`] if reservations never expire
+    timeout: Option<Duration>,
+    claims: Mutex<HashMap<SessionKey, Claim>>,
+}
+#[derive(Debug, Clone, Copy)]
+struct Claim {
+    id: u64,
+    at: Timestamp,
+}
+impl<SessionKey: Eq + Hash> Reservations<SessionKey> {
+    pub fn new(timeout: Option<Duration>) -> Self {
+        Self {
+            timeout,
+            claims: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn is_live(&self, claim: &Claim, now: Timestamp) -> bool {
+        self.timeout
+            .is_none_or(|timeout| now.saturating_duration_since(claim.at) < timeout)
+    }
+
+    /// Whether a live reservation other than `own` holds the key
+    pub fn is_claimed_by_other(&self, key: &SessionKey, own: Option<u64>, now: Timestamp) -> bool {
+        let claims = self.claims.lock();
+        claims
+            .get(key)
+            .is_some_and(|claim| Some(claim.id) != own && self.is_live(claim, now))
+    }
+
+    /// Fail if a live reservation already holds the key
+    pub fn claim(&self, key: SessionKey, id: u64, now: Timestamp) -> bool {
+        let mut claims = self.claims.lock();
+        if claims
+            .get(&key)
+            .is_some_and(|claim| self.is_live(claim, now))
+        {
+            return false;
+        }
+        claims.insert(key, Claim { id, at: now });
+        true
+    }
+
+    /// Leave the key alone if it has been reserved again by someone else
+    pub fn release(&self, key: &SessionKey, id: u64) {
+        let mut claims = self.claims.lock();
+        if claims.get(key).is_some_and(|claim| claim.id == id) {
+            claims.remove(key);
+        }
+    }
+
+    /// Forget expired reservations
+    pub fn sweep(&self, now: Timestamp) {
+        if self.timeout.is_none() {
+            return;
+        }
+        let mut claims = self.claims.lock();
+        claims.retain(|_, claim| self.is_live(claim, now));
+    }
+}
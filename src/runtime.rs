@@ -0,0 +1,125 @@
+use std::{fmt::Debug, future::Future, pin::Pin, time::Duration};
+
+/// A future that the background task awaits or is made of
+pub type BoxFuture = Pin<Box<dyn Future<Output = ()> + Send + 'static>>;
+
+/// The executor hosting the background task of a [`crate::SessionLayer`]
+///
+/// Only spawning and sleeping are needed.
+/// The `tokio::sync` primitives used elsewhere do not depend on the tokio runtime, so they work on any executor.
+pub trait Runtime: Debug + Send + Sync + 'static {
+    fn spawn(&self, task: BoxFuture);
+    fn sleep(&self, duration: Duration) -> BoxFuture;
+}
+
+/// The runtime picked up when none is configured
+pub(crate) fn default_runtime() -> Option<Box<dyn Runtime>> {
+    #[cfg(feature = "tokio")]
+    if let Some(runtime) = TokioRuntime::try_current() {
+        return Some(Box::new(runtime));
+    }
+    None
+}
+
+#[cfg(feature = "tokio")]
+#[derive(Debug, Clone)]
+pub struct TokioRuntime(tokio::runtime::Handle);
+#[cfg(feature = "tokio")]
+impl TokioRuntime {
+    pub fn new(handle: tokio::runtime::Handle) -> Self {
+        Self(handle)
+    }
+
+    /// Return [`None`] outside of a tokio runtime
+    pub fn try_current() -> Option<Self> {
+        tokio::runtime::Handle::try_current().ok().map(Self)
+    }
+}
+#[cfg(feature = "tokio")]
+impl Runtime for TokioRuntime {
+    fn spawn(&self, task: BoxFuture) {
+        self.0.spawn(task);
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture {
+        Box::pin(tokio::time::sleep(duration))
+    }
+}
+
+/// Spawn onto the global `smol` executor
+#[cfg(feature = "smol")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SmolRuntime;
+#[cfg(feature = "smol")]
+impl Runtime for SmolRuntime {
+    fn spawn(&self, task: BoxFuture) {
+        smol::spawn(task).detach();
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture {
+        let timer = smol::Timer::after(duration);
+        Box::pin(async move {
+            timer.await;
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use super::*;
+    use crate::SessionLayerBuilder;
+
+    const TIMEOUT: Duration = Duration::from_millis(20);
+
+    /// Count the spawns and run each task on a thread of its own
+    #[derive(Debug)]
+    struct CountingRuntime {
+        spawned: Arc<AtomicUsize>,
+    }
+    impl Runtime for CountingRuntime {
+        fn spawn(&self, task: BoxFuture) {
+            self.spawned.fetch_add(1, Ordering::Relaxed);
+            std::thread::spawn(move || {
+                tokio::runtime::Builder::new_current_thread()
+                    .enable_time()
+                    .build()
+                    .unwrap()
+                    .block_on(task)
+            });
+        }
+
+        fn sleep(&self, duration: Duration) -> BoxFuture {
+            Box::pin(tokio::time::sleep(duration))
+        }
+    }
+
+    fn expires_on(runtime: impl Runtime) {
+        let layer = SessionLayerBuilder::new(TIMEOUT)
+            .runtime(runtime)
+            .build()
+            .unwrap();
+        layer.insert(1, 10).unwrap();
+        std::thread::sleep(TIMEOUT * 10);
+        assert_eq!(layer.get(&1), None);
+    }
+
+    #[test]
+    fn configured_runtime_hosts_the_sweeper_outside_of_any_runtime() {
+        let spawned = Arc::new(AtomicUsize::new(0));
+        expires_on(CountingRuntime {
+            spawned: Arc::clone(&spawned),
+        });
+        assert_eq!(spawned.load(Ordering::Relaxed), 1);
+    }
+
+    #[cfg(feature = "smol")]
+    #[test]
+    fn smol_runtime_hosts_the_sweeper() {
+        expires_on(SmolRuntime);
+    }
+}
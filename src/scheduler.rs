@@ -0,0 +1,185 @@
+use std::{
+    sync::{Arc, Weak},
+    time::{Duration, Instant},
+};
+
+use tokio::sync::Notify;
+
+use crate::{
+    lock::Mutex,
+    runtime::{default_runtime, Runtime},
+    session::SweepLoop,
+};
+
+/// One background task sweeping many layers, e.g. one layer per listening port, see [`crate::SessionLayerBuilder::scheduler`]
+///
+/// Clones share the task.
+/// Layers are held weakly and forgotten once dropped or shut down.
+/// The task ends once all clones and all layers registered to it are gone.
+#[derive(Clone)]
+pub struct SweepScheduler(Arc<SchedulerState>);
+struct SchedulerState {
+    registrations: Mutex<Vec<Registration>>,
+    /// Also the [`crate::SessionLayer`]s' request for an early sweep, see [`crate::SessionLayerBuilder::sweep_after_mutations`]
+    wake: Arc<Notify>,
+}
+struct Registration {
+    layer: Weak<dyn ScheduledSweep>,
+    interval: Duration,
+    due: Instant,
+    _sweep_loop: SweepLoop,
+}
+
+/// What the task needs of a layer regardless of its type parameters
+pub(crate) trait ScheduledSweep: Send + Sync {
+    /// Sweep as the own background task of the layer would, and return [`None`] once the layer is shut down
+    fn background_sweep(&self, interval: Duration) -> Option<bool>;
+    /// Whether enough writes have piled up since the last call, see [`crate::SessionLayerBuilder::sweep_after_mutations`]
+    fn take_sweep_request(&self) -> bool;
+}
+
+impl SweepScheduler {
+    /// Spawn the task on `runtime`
+    pub fn new(runtime: impl Runtime) -> Self {
+        Self::spawn(Arc::new(runtime))
+    }
+
+    /// Same as [`Self::new`] but on the runtime detected as when building a layer
+    ///
+    /// Return [`None`] if none is detected.
+    pub fn try_current() -> Option<Self> {
+        default_runtime().map(|runtime| Self::spawn(Arc::from(runtime)))
+    }
+
+    fn spawn(runtime: Arc<dyn Runtime>) -> Self {
+        let state = Arc::new(SchedulerState {
+            registrations: Mutex::new(vec![]),
+            wake: Arc::new(Notify::new()),
+        });
+        runtime.spawn(Box::pin(Self::run(
+            Arc::downgrade(&state),
+            Arc::clone(&state.wake),
+            Arc::clone(&runtime),
+        )));
+        Self(state)
+    }
+
+    /// The number of layers sweeping on this task
+    pub fn registered(&self) -> usize {
+        let registrations = self.0.registrations.lock();
+        registrations
+            .iter()
+            .filter(|registration| registration.layer.strong_count() != 0)
+            .count()
+    }
+
+    pub(crate) fn wake(&self) -> Arc<Notify> {
+        Arc::clone(&self.0.wake)
+    }
+
+    /// Sweep `layer` every `interval` from now on
+    pub(crate) fn register(
+        &self,
+        layer: Weak<dyn ScheduledSweep>,
+        interval: Duration,
+        sweep_loop: SweepLoop,
+    ) {
+        self.0.registrations.lock().push(Registration {
+            layer,
+            interval,
+            due: Instant::now() + interval,
+            _sweep_loop: sweep_loop,
+        });
+        self.0.wake.notify_one();
+    }
+
+    async fn run(state: Weak<SchedulerState>, wake: Arc<Notify>, runtime: Arc<dyn Runtime>) {
+        loop {
+            let Some(state) = state.upgrade() else {
+                return;
+            };
+            // Taken out so that the sweeps neither hold the lock nor block new layers
+            let mut registrations = std::mem::take(&mut *state.registrations.lock());
+            let now = Instant::now();
+            registrations.retain_mut(|registration| {
+                let Some(layer) = registration.layer.upgrade() else {
+                    return false;
+                };
+                if layer.take_sweep_request() {
+                    registration.due = now;
+                }
+                if now < registration.due {
+                    return true;
+                }
+                registration.due = now + registration.interval;
+                layer.background_sweep(registration.interval).is_some()
+            });
+            let next = registrations
+                .iter()
+                .map(|registration| registration.due)
+                .min();
+            state.registrations.lock().append(&mut registrations);
+            drop(state);
+
+            match next {
+                Some(next) => {
+                    let sleep = runtime.sleep(next.saturating_duration_since(Instant::now()));
+                    crate::concurrent::timeout(sleep, wake.notified()).await;
+                }
+                // Nothing to sweep until a layer registers
+                None => wake.notified().await,
+            }
+        }
+    }
+}
+impl Drop for SchedulerState {
+    fn drop(&mut self) {
+        // Let the task notice when it waits for no layer
+        self.wake.notify_one();
+    }
+}
+impl std::fmt::Debug for SweepScheduler {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SweepScheduler")
+            .field("registered", &self.registered())
+            .finish()
+    }
+}
+
+#[cfg(all(test, feature = "tokio"))]
+mod tests {
+    use super::*;
+    use crate::{ManualClock, SessionLayerBuilder, TimeSource};
+
+    #[test]
+    fn one_task_sweeps_every_registered_layer() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let scheduler = SweepScheduler::try_current().unwrap();
+            let clock = ManualClock::new();
+            let layer = |timeout| {
+                SessionLayerBuilder::<u32, u32>::new(timeout)
+                    .time_source(TimeSource::Manual(clock.clone()))
+                    .scheduler(&scheduler)
+                    .build()
+                    .unwrap()
+            };
+            let short = layer(Duration::from_millis(20));
+            let long = layer(Duration::from_secs(3600));
+            assert_eq!(scheduler.registered(), 2);
+            short.insert(1, 10).unwrap();
+            long.insert(1, 10).unwrap();
+            clock.advance(Duration::from_millis(20));
+            // Well past the sweep interval of the short layer only
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            assert!(short.is_empty());
+            assert_eq!(long.len(), 1);
+
+            drop(short);
+            assert_eq!(scheduler.registered(), 1);
+        });
+    }
+}
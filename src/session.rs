@@ -1,86 +1,5381 @@
 use std::{
-    borrow::Borrow,
+    borrow::{Borrow, Cow},
     collections::HashMap,
-    sync::{Arc, Mutex, RwLock},
+    convert::Infallible,
+    future::Future,
+    hash::{BuildHasher, RandomState},
+    ops::{Deref, DerefMut},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicU8, AtomicUsize, Ordering},
+        Arc, Weak,
+    },
     time::{Duration, Instant},
 };
 
+use tokio::sync::{broadcast, watch, Notify};
+
+#[cfg(feature = "stream")]
+use crate::stream::SessionStream;
+use crate::{
+    audit::{AuditLog, AuditOp, AuditRecord},
+    builder::{
+        Admission, ExpiringHook, HandleExpiry, KeyLabel, KeyNormalizer, LivenessCheck,
+        NewSessionLayerError, SessionLayerBuilder, SweepHook, Weigher,
+    },
+    concurrent::for_each_concurrent,
+    event::SessionEvent,
+    eviction::EvictionQueue,
+    filter::KeyFilter,
+    hook::Hook,
+    label::short_hash,
+    lock::{Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard},
+    map::{IntKey, KeyHashing, SessionMap, Weighted},
+    miss::MissTracker,
+    rate_limit::RateLimiter,
+    replication::{RemovalCause, Replication, ReplicationSink},
+    reservation::{Reservation, Reservations},
+    runtime::{default_runtime, Runtime},
+    scheduler::{ScheduledSweep, SweepScheduler},
+    stats::{Occupancy, PopulationStats, SweepReport, SweeperStatus},
+    time::{ManualClock, TimeSource, Timestamp},
+    tombstone::Tombstones,
+    view::ReadOnlyView,
+    wheel::ExpiryWheel,
+};
+
+/// Timeouts at least this long are treated as never expiring
+pub const NEVER_EXPIRE_THRESHOLD: Duration = Duration::from_secs(100 * 365 * 24 * 60 * 60);
+
 /// The one state that a backend instance needs during its lifetime
-#[derive(Debug)]
-pub struct SessionLayer<SessionKey, SessionHandle> {
+pub struct SessionLayer<SessionKey, SessionHandle, Metadata = ()> {
     /// Mapping from a key to the session
-    key_to_session: RwLock<HashMap<SessionKey, (SessionHandle, Mutex<Instant>)>>,
+    key_to_session: RwLock<SessionMap<SessionKey, Entry<SessionKey, SessionHandle, Metadata>>>,
+    /// Shared with the map, see [`SessionLayerBuilder::negative_lookup_filter`]
+    key_filter: Option<Arc<KeyFilter>>,
+    /// See [`SessionLayerBuilder::expiry_wheel`]
+    expiry_wheel: Option<ExpiryWheel<SessionKey>>,
+    /// Alias to primary key, see [`Self::insert_aliases`]
+    ///
+    /// Always locked after the map, and an alias only counts while the entry under its primary key lists it.
+    aliases: RwLock<HashMap<SessionKey, SessionKey>>,
     /// Used to clean up the map and avoid memory leak
-    timeout: Duration,
+    ///
+    /// [`None`] if sessions never expire.
+    ///
+    /// The timeout of [`TimeoutClass::DEFAULT`].
+    timeout: Option<Duration>,
+    /// Indexed by [`TimeoutClass`], with undefined classes following the default one
+    class_timeouts: Vec<Option<Duration>>,
+    /// The shortest of [`Self::class_timeouts`], which paces the background task
+    shortest_timeout: Option<Duration>,
+    /// Lifecycle events for subscribers
+    events: broadcast::Sender<SessionEvent<SessionKey>>,
+    /// How [`Self::insert`] resolves an occupied key
+    collision_policy: CollisionPolicy,
+    /// Whether to publish [`SessionEvent::Accessed`]
+    access_events: bool,
+    /// Whether lookups check the timeout themselves instead of leaving it to the sweep
+    resurrect_policy: ResurrectPolicy,
+    /// Tells if a session is still usable
+    is_alive: Option<Hook<LivenessCheck<SessionHandle>>>,
+    /// See [`SessionLayerBuilder::respect_handle_expiry`]
+    handle_expiry: Option<Hook<HandleExpiry<SessionHandle>>>,
+    /// Warned of sessions about to expire
+    on_expiring: Option<(Duration, Hook<ExpiringHook<SessionKey, SessionHandle>>)>,
+    /// Called after every sweep
+    on_sweep: Option<Hook<SweepHook>>,
+    /// Read-locked by [`Self::with_sweep_paused`] to hold off the background task
+    sweep_pause: RwLock<()>,
+    /// For maps made anew, e.g. by [`Self::swap_contents`]
+    key_hashing: KeyHashing,
+    /// The budget for the total weight of all sessions and how to weigh each one
+    weight_budget: Option<(usize, Hook<Weigher<SessionHandle>>)>,
+    /// Vets every session before [`Self::insert`] and its variants take it
+    admission: Option<Hook<Admission<SessionKey, SessionHandle>>>,
+    /// Throttles [`Self::insert`] and its variants
+    insert_rate_limit: Option<RateLimiter>,
+    /// Keys claimed by [`Self::reserve`]
+    reservations: Reservations<SessionKey>,
+    /// Recently removed keys for [`Self::lookup`]
+    tombstones: Option<Tombstones>,
+    /// Lookups of unknown keys for [`Self::top_missed`]
+    misses: Option<MissTracker>,
+    /// Runs the `on_evict` callbacks off the sweep, see [`SessionLayerBuilder::eviction_worker`]
+    eviction_queue: Option<Arc<EvictionQueue>>,
+    /// Remembers the latest operations
+    audit: Option<AuditLog>,
+    /// [`None`] for [`crate::label::short_hash`]
+    key_label: Option<Hook<KeyLabel<SessionKey>>>,
+    /// Mirrors every mutation
+    replication: Option<Replication<SessionKey, SessionHandle>>,
+    /// Called after every background sweep that took longer than its interval
+    on_overrun: Option<Hook<SweepHook>>,
+    sweep_overruns: AtomicU64,
+    last_sweep: Mutex<Option<SweepReport>>,
+    sweep_timing: Mutex<SweepTiming>,
+    /// The number of sweep loops not yet ended, shared with them
+    sweep_loops: Arc<AtomicUsize>,
+    /// What idle times are measured against
+    time_source: TimeSource,
+    /// Canonicalizes owned keys
+    normalize_key: Option<Hook<KeyNormalizer<SessionKey>>>,
+    /// The number of sessions as of the last write to the map
+    len: AtomicUsize,
+    /// Publishes the number of sessions once per write to the map
+    len_watch: watch::Sender<usize>,
+    /// Observes the number of sessions once per write to the map
+    occupancy: Occupancy,
+    /// Orders sessions for [`Self::scan`] independently of the map layout
+    scan_hasher: RandomState,
+    /// Resume the background task parked on an empty map
+    ///
+    /// Signaled on every insertion.
+    sweeper_wake: Arc<Notify>,
+    /// Cut short the sleep of the background task, see [`SessionLayerBuilder::sweep_after_mutations`]
+    ///
+    /// That of the scheduler if any.
+    sweep_now: Arc<Notify>,
+    /// Tells a scheduler which of its layers signaled [`Self::sweep_now`]
+    sweep_requested: AtomicBool,
+    /// Kept alive for as long as the layer, see [`SessionLayerBuilder::scheduler`]
+    scheduler: Option<SweepScheduler>,
+    /// Zero if writes do not trigger sweeps
+    sweep_after_mutations: u64,
+    /// Writes to the map so far
+    mutations: AtomicU64,
+    /// Source of [`Entry::seq`]
+    access_seq: AtomicU64,
+    /// Set by [`Self::shutdown_with`] to let the background task exit
+    sweeper_stopped: AtomicBool,
+    /// Configured or detected when built
+    runtime: Option<Arc<dyn Runtime>>,
 }
 impl<SessionKey, SessionHandle> SessionLayer<SessionKey, SessionHandle>
 where
-    SessionKey: Sync + Send + 'static,
+    SessionKey: Eq + std::hash::Hash + Clone + Sync + Send + 'static,
     SessionHandle: Sync + Send + 'static,
 {
+    /// # Panics
+    ///
+    /// Panics if [`Self::try_new`] fails.
     pub fn new(timeout: Duration) -> Arc<Self> {
+        match Self::try_new(timeout) {
+            Ok(this) => this,
+            Err(e) => panic!("{e}"),
+        }
+    }
+
+    /// A timeout of at least [`NEVER_EXPIRE_THRESHOLD`] is the same as [`Self::new_unbounded`].
+    pub fn try_new(timeout: Duration) -> Result<Arc<Self>, NewSessionLayerError> {
+        Self::builder(timeout).build()
+    }
+
+    /// Same as [`Self::new`] but hash integer keys without SipHash, see [`SessionLayerBuilder::integer_keys`]
+    ///
+    /// Only the hashing changes, so lookups by any borrowed form of the key still work.
+    pub fn new_integer_keyed(timeout: Duration) -> Arc<Self>
+    where
+        SessionKey: IntKey,
+    {
+        match Self::builder(timeout).integer_keys().build() {
+            Ok(this) => this,
+            Err(e) => panic!("{e}"),
+        }
+    }
+
+    /// Sessions never expire, so the layer is just a concurrent registry
+    ///
+    /// No background task is spawned, so this works without a runtime.
+    pub fn new_unbounded() -> Arc<Self> {
+        SessionLayerBuilder::unbounded()
+            .build()
+            .expect("unbounded layer with default config")
+    }
+
+    /// No background task is spawned, see [`SessionLayerBuilder::manual_sweep`]
+    pub fn new_manual(timeout: Duration) -> Result<Arc<Self>, NewSessionLayerError> {
+        Self::builder(timeout).manual_sweep().build()
+    }
+
+    /// A layer for deterministic tests: no background task is spawned, and idle times only advance with the returned clock
+    ///
+    /// Meant for tests only.
+    /// Advance the clock, then call [`Self::sweep`] to expire sessions without any real sleep.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `timeout` is zero.
+    pub fn new_test(timeout: Duration) -> (Arc<Self>, ManualClock) {
+        let clock = ManualClock::new();
+        let layer = Self::builder(timeout)
+            .manual_sweep()
+            .time_source(TimeSource::Manual(clock.clone()))
+            .build();
+        match layer {
+            Ok(layer) => (layer, clock),
+            Err(e) => panic!("{e}"),
+        }
+    }
+
+    pub fn builder(timeout: Duration) -> SessionLayerBuilder<SessionKey, SessionHandle> {
+        SessionLayerBuilder::new(timeout)
+    }
+}
+impl<SessionKey, SessionHandle, Metadata> SessionLayer<SessionKey, SessionHandle, Metadata>
+where
+    SessionKey: Eq + std::hash::Hash + Clone + Sync + Send + 'static,
+    SessionHandle: Sync + Send + 'static,
+    Metadata: Sync + Send + 'static,
+{
+    pub(crate) fn from_builder(
+        builder: SessionLayerBuilder<SessionKey, SessionHandle, Metadata>,
+    ) -> Result<Arc<Self>, NewSessionLayerError> {
+        if builder.timeout.is_some_and(|timeout| timeout.is_zero()) {
+            return Err(NewSessionLayerError::ZeroTimeout);
+        }
+        if builder.event_capacity == 0 {
+            return Err(NewSessionLayerError::ZeroEventCapacity);
+        }
+        if builder
+            .insert_rate_limit
+            .is_some_and(|(capacity, _)| capacity == 0)
+        {
+            return Err(NewSessionLayerError::ZeroRateLimitCapacity);
+        }
+        if builder
+            .expiry_wheel
+            .is_some_and(|granularity| granularity.is_zero())
+        {
+            return Err(NewSessionLayerError::ZeroExpiryGranularity);
+        }
+        if builder
+            .timeout_classes
+            .iter()
+            .any(|(_, timeout)| timeout.is_zero())
+        {
+            return Err(NewSessionLayerError::ZeroTimeout);
+        }
+        let bounded = |timeout: Duration| Some(timeout).filter(|t| *t < NEVER_EXPIRE_THRESHOLD);
+        let timeout = builder
+            .timeout_classes
+            .iter()
+            .rev()
+            .find(|(class, _)| *class == TimeoutClass::DEFAULT)
+            .map_or(builder.timeout.and_then(bounded), |&(_, timeout)| {
+                bounded(timeout)
+            });
+        let classes = builder
+            .timeout_classes
+            .iter()
+            .map(|(class, _)| usize::from(class.0) + 1)
+            .max()
+            .unwrap_or(1);
+        let mut class_timeouts = vec![timeout; classes];
+        for &(class, timeout) in &builder.timeout_classes {
+            class_timeouts[usize::from(class.0)] = bounded(timeout);
+        }
+        let shortest_timeout = class_timeouts.iter().flatten().min().copied();
+        let runtime = builder
+            .runtime
+            .map(Arc::<dyn Runtime>::from)
+            .or_else(|| default_runtime().map(Arc::from));
+        if shortest_timeout.is_none() && builder.handle_expiry.is_some() && !builder.manual_sweep {
+            return Err(NewSessionLayerError::UnsweptHandleExpiry);
+        }
+        if shortest_timeout.is_some()
+            && !builder.manual_sweep
+            && builder.scheduler.is_none()
+            && runtime.is_none()
+        {
+            return Err(NewSessionLayerError::NoRuntime);
+        }
+        let eviction_queue = match builder.eviction_worker {
+            Some((capacity, policy)) => Some(Arc::new(
+                EvictionQueue::spawn(capacity, policy)
+                    .map_err(|_| NewSessionLayerError::EvictionWorker)?,
+            )),
+            None => None,
+        };
+        let key_filter = (builder.negative_lookup_filter != 0)
+            .then(|| Arc::new(KeyFilter::new(builder.negative_lookup_filter)));
+        let mut key_to_session = SessionMap::new(builder.key_hashing);
+        if let Some(key_filter) = &key_filter {
+            key_to_session = key_to_session.with_filter(Arc::clone(key_filter));
+        }
+        let (events, _) = broadcast::channel(builder.event_capacity);
         let this = Arc::new(Self {
-            key_to_session: RwLock::new(HashMap::new()),
+            key_to_session: RwLock::new(key_to_session),
+            key_filter,
+            expiry_wheel: builder
+                .expiry_wheel
+                .map(|granularity| ExpiryWheel::new(builder.time_source.now(), granularity)),
+            aliases: RwLock::new(HashMap::new()),
+            key_hashing: builder.key_hashing,
             timeout,
+            class_timeouts,
+            shortest_timeout,
+            events,
+            access_events: builder.access_events,
+            resurrect_policy: builder.resurrect_policy,
+            collision_policy: builder.collision_policy,
+            is_alive: builder.is_alive,
+            handle_expiry: builder.handle_expiry,
+            on_expiring: builder.on_expiring,
+            on_sweep: builder.on_sweep,
+            on_overrun: builder.on_overrun,
+            replication: builder.replication,
+            audit: builder.audit,
+            key_label: builder.key_label,
+            tombstones: builder.tombstones,
+            misses: builder.misses,
+            eviction_queue,
+            admission: builder.admission,
+            insert_rate_limit: builder
+                .insert_rate_limit
+                .map(|(capacity, refill_interval)| {
+                    RateLimiter::new(
+                        capacity,
+                        refill_interval,
+                        builder.time_source.now().instant(),
+                    )
+                }),
+            reservations: Reservations::new(builder.reservation_timeout.or(timeout)),
+            weight_budget: builder.weight_budget,
+            sweep_pause: RwLock::new(()),
+            sweep_overruns: AtomicU64::new(0),
+            last_sweep: Mutex::new(None),
+            sweep_timing: Mutex::new(SweepTiming::default()),
+            sweep_loops: Arc::new(AtomicUsize::new(0)),
+            time_source: builder.time_source,
+            normalize_key: builder.normalize_key,
+            len: AtomicUsize::new(0),
+            len_watch: watch::channel(0).0,
+            occupancy: Occupancy::new(builder.thresholds),
+            scan_hasher: RandomState::new(),
+            sweeper_wake: Arc::new(Notify::new()),
+            sweep_now: builder
+                .scheduler
+                .as_ref()
+                .map_or_else(|| Arc::new(Notify::new()), SweepScheduler::wake),
+            sweep_requested: AtomicBool::new(false),
+            scheduler: builder.scheduler,
+            sweep_after_mutations: builder.sweep_after_mutations,
+            mutations: AtomicU64::new(0),
+            access_seq: AtomicU64::new(0),
+            sweeper_stopped: AtomicBool::new(false),
+            runtime,
         });
 
+        let (Some(timeout), false) = (shortest_timeout, builder.manual_sweep) else {
+            return Ok(this);
+        };
+
         // Clean the map routinely
-        let weak_this = Arc::downgrade(&this);
         let check = timeout.div_f64(2.0);
-        tokio::spawn(async move {
-            loop {
-                tokio::time::sleep(check).await;
-                let this = weak_this.upgrade()?;
-                this.remove_outdated();
+        if let Some(scheduler) = &this.scheduler {
+            let layer: Weak<dyn ScheduledSweep> = Arc::downgrade(&this) as _;
+            scheduler.register(layer, check, SweepLoop::enter(&this.sweep_loops));
+            return Ok(this);
+        }
+        let Some(runtime) = &this.runtime else {
+            return Ok(this);
+        };
+        runtime.spawn(Box::pin(Self::sweep_loop(
+            Arc::downgrade(&this),
+            Arc::clone(&this.sweep_now),
+            check,
+            Arc::clone(runtime),
+            SweepLoop::enter(&this.sweep_loops),
+        )));
+
+        Ok(this)
+    }
+
+    /// The loop of the background task, for layers built with [`SessionLayerBuilder::manual_sweep`] to be driven by the caller
+    ///
+    /// The layer is only held weakly, so the future ends once all other [`Arc`]s are dropped, or after [`Self::shutdown_with`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if no runtime to sleep on was configured or detected when the layer was built.
+    pub fn purge_loop(self: Arc<Self>, interval: Duration) -> impl Future<Output = ()> + Send {
+        let runtime = self
+            .runtime
+            .clone()
+            .expect("purge loop needs a runtime to sleep on");
+        let sweep_loop = SweepLoop::enter(&self.sweep_loops);
+        Self::sweep_loop(
+            Arc::downgrade(&self),
+            Arc::clone(&self.sweep_now),
+            interval,
+            runtime,
+            sweep_loop,
+        )
+    }
+
+    /// `_sweep_loop` marks the loop as alive until the future is done or dropped
+    async fn sweep_loop(
+        this: Weak<Self>,
+        sweep_now: Arc<Notify>,
+        interval: Duration,
+        runtime: Arc<dyn Runtime>,
+        _sweep_loop: SweepLoop,
+    ) {
+        loop {
+            // Either the interval has passed or enough writes have piled up
+            crate::concurrent::timeout(runtime.sleep(interval), sweep_now.notified()).await;
+            let Some(this) = this.upgrade() else {
+                return;
+            };
+            match this.background_sweep(interval) {
+                None => return,
+                Some(false) => continue,
+                Some(true) => (),
             }
-            #[allow(unreachable_code)]
-            Some(())
-        });
 
-        this
+            // Nothing can expire while the map is empty, so park until the next insertion
+            let wake = Arc::clone(&this.sweeper_wake);
+            drop(this);
+            wake.notified().await;
+        }
+    }
+
+    /// One round of the background task, shared with [`SweepScheduler`]
+    ///
+    /// Return [`None`] once the layer is shut down, or whether the map is left empty.
+    /// Rounds run into by [`Self::with_sweep_paused`] are skipped as if the map were not empty.
+    fn background_sweep(&self, interval: Duration) -> Option<bool> {
+        if self.sweeper_stopped.load(Ordering::Acquire) {
+            return None;
+        }
+        let Some(pause) = self.sweep_pause.try_write() else {
+            return Some(false);
+        };
+        let report = self.sweep();
+        drop(pause);
+        self.shrink_if_sparse();
+        if interval < report.duration {
+            self.sweep_overruns.fetch_add(1, Ordering::Relaxed);
+            if let Some(on_overrun) = &self.on_overrun {
+                on_overrun(report);
+            }
+        }
+        Some(self.key_to_session.read().is_empty())
+    }
+
+    /// Lock the map for writing and keep [`Self::approx_len`] and [`Self::len_watch`] in sync when done
+    fn write_map(&self) -> MapWriteGuard<'_, SessionKey, SessionHandle, Metadata> {
+        if self.sweep_after_mutations != 0 {
+            let mutations = self.mutations.fetch_add(1, Ordering::Relaxed) + 1;
+            if mutations.is_multiple_of(self.sweep_after_mutations) {
+                self.sweep_requested.store(true, Ordering::Relaxed);
+                self.sweep_now.notify_one();
+            }
+        }
+        MapWriteGuard {
+            map: Some(self.key_to_session.write()),
+            len: &self.len,
+            len_watch: &self.len_watch,
+            occupancy: &self.occupancy,
+        }
+    }
+
+    /// Configured or detected when built
+    pub(crate) fn runtime(&self) -> Option<&Arc<dyn Runtime>> {
+        self.runtime.as_ref()
+    }
+
+    pub(crate) fn now(&self) -> Timestamp {
+        self.time_source.now()
+    }
+
+    fn next_seq(&self) -> u64 {
+        self.access_seq.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// The number of sessions without taking any lock
+    ///
+    /// It might lag behind concurrent writes but is exact when the layer is quiescent.
+    pub fn approx_len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    /// The peak number of sessions since the layer was built or since [`Self::reset_high_water_mark`], e.g. for capacity planning
+    pub fn high_water_mark(&self) -> usize {
+        self.occupancy.high_water()
+    }
+
+    /// Restart [`Self::high_water_mark`] from the current number of sessions
+    pub fn reset_high_water_mark(&self) {
+        self.occupancy.reset_high_water(self.approx_len());
+    }
+
+    /// Await changes to the number of sessions instead of polling [`Self::len`], e.g. for autoscaling
+    ///
+    /// Updated at most once per operation and once per sweep, and only if the number actually changed.
+    pub fn len_watch(&self) -> watch::Receiver<usize> {
+        self.len_watch.subscribe()
+    }
+
+    /// The exact number of sessions under the read lock
+    pub fn len(&self) -> usize {
+        self.key_to_session.read().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// How many sessions the map can hold without reallocating
+    pub fn capacity(&self) -> usize {
+        self.key_to_session.read().capacity()
+    }
+
+    /// Release the memory the map holds beyond its current sessions, e.g. after a spike of sessions has expired
+    ///
+    /// This rehashes every session under the write lock, so it is O(n) and blocks all access meanwhile.
+    /// The background task already does it whenever fewer than a quarter of the capacity is in use after a sweep.
+    pub fn shrink_to_fit(&self) {
+        self.write_map().shrink_to_fit();
+    }
+
+    fn shrink_if_sparse(&self) {
+        let sparse = self.key_to_session.read().is_sparse();
+        if sparse {
+            self.shrink_to_fit();
+        }
+    }
+
+    /// Remove idle sessions and dead sessions now instead of waiting for the background task
+    ///
+    /// The report is also passed to the `on_sweep` hook and kept for [`Self::last_sweep`].
+    pub fn sweep(&self) -> SweepReport {
+        let started = Instant::now();
+        self.sweep_timing.lock().started = Some(started);
+        let now = self.now();
+        if let Some(tombstones) = &self.tombstones {
+            tombstones.sweep(now);
+        }
+        self.reservations.sweep(now);
+        let (removed, remaining) = self.remove_outdated(now);
+        let report = SweepReport {
+            removed,
+            remaining,
+            duration: started.elapsed(),
+            at: now.instant(),
+        };
+        *self.last_sweep.lock() = Some(report);
+        {
+            let mut timing = self.sweep_timing.lock();
+            timing.finished = Some(Instant::now());
+            timing.completed += 1;
+        }
+        if let Some(on_sweep) = &self.on_sweep {
+            on_sweep(report);
+        }
+        report
+    }
+
+    /// Hold off the background task while `f` runs, e.g. so that sessions do not vanish from a long analytical pass over them
+    ///
+    /// Waits for a background sweep in progress to finish first.
+    /// A background sweep due meanwhile is skipped, so pausing for long delays the reclamation of idle sessions.
+    /// Explicit calls to [`Self::sweep`] and removals by lookups of dead sessions still go through.
+    pub fn with_sweep_paused<R>(&self, f: impl FnOnce(&Self) -> R) -> R {
+        let _pause = self.sweep_pause.read();
+        f(self)
+    }
+
+    /// How many background sweeps took longer than their interval, a sign that the background task cannot keep up
+    ///
+    /// Manual calls to [`Self::sweep`] are not counted.
+    pub fn sweep_overruns(&self) -> u64 {
+        self.sweep_overruns.load(Ordering::Relaxed)
+    }
+
+    /// When sweeps ran, how many did, and whether anything is left to run them
+    ///
+    /// A background task exits once the layer is dropped or shut down, and stays parked without sweeping while the layer is empty.
+    pub fn sweeper_status(&self) -> SweeperStatus {
+        let timing = *self.sweep_timing.lock();
+        SweeperStatus {
+            last_sweep_started: timing.started,
+            last_sweep_finished: timing.finished,
+            sweeps_completed: timing.completed,
+            task_alive: self.sweep_loops.load(Ordering::Acquire) != 0,
+        }
+    }
+
+    /// The report of the latest sweep, by the background task or by [`Self::sweep`]
+    pub fn last_sweep(&self) -> Option<SweepReport> {
+        *self.last_sweep.lock()
     }
 
-    fn remove_outdated(&self) {
-        let now = Instant::now();
-        let mut key_to_session = self.key_to_session.write().unwrap();
-        key_to_session.retain(|_k, (_, time)| {
-            let time = time.get_mut().unwrap();
-            now - *time < self.timeout
+    /// Remove idle sessions and sessions reported dead by the liveness check
+    ///
+    /// Return the number of removed sessions and of remaining ones.
+    fn remove_outdated(&self, now: Timestamp) -> (usize, usize) {
+        if self.shortest_timeout.is_none()
+            && self.handle_expiry.is_none()
+            && self.is_alive.is_none()
+            && self.key_filter.is_none()
+            && self.aliases.read().is_empty()
+        {
+            return (0, self.approx_len());
+        }
+        let mut key_to_session = self.write_map();
+        let is_expired =
+            |entry: &Entry<SessionKey, SessionHandle, Metadata>| self.is_expired(entry, now);
+        // Only the keys found by the wheel are due for the hook, otherwise all sessions are visited
+        let mut expiring = None;
+        let mut removed = match &self.expiry_wheel {
+            // Dead sessions are only found by visiting all of them
+            Some(expiry_wheel) if self.is_alive.is_none() => self.extract_due(
+                &mut key_to_session,
+                expiry_wheel,
+                now,
+                expiring.insert(vec![]),
+            ),
+            _ => key_to_session
+                .extract_if(|_k, entry| is_expired(entry) || !self.is_alive(&entry.session)),
+        };
+        self.forget_aliases(removed.iter().map(|(key, entry)| (key, entry)));
+        self.prune_aliases(&key_to_session);
+        key_to_session.rebuild_filter(self.aliases.read().keys());
+        let remaining = key_to_session.len();
+        let key_to_session = key_to_session.downgrade();
+        self.notify_expiring(&key_to_session, expiring, now);
+        drop(key_to_session);
+        let counts = (removed.len(), remaining);
+        // Least recently used first, even if accessed within the same clock tick
+        removed.sort_unstable_by_key(|(_, entry)| entry.seq.load(Ordering::Relaxed));
+
+        self.replicate(|sink| {
+            for (key, entry) in &removed {
+                let cause = match is_expired(entry) {
+                    true => RemovalCause::Expired,
+                    false => RemovalCause::Dead,
+                };
+                sink.on_remove(key, cause);
+            }
+        });
+        self.publish_with(|| {
+            removed
+                .iter()
+                .map(|(key, entry)| match is_expired(entry) {
+                    true => SessionEvent::Expired {
+                        key: key.clone(),
+                        at: now.instant(),
+                    },
+                    false => SessionEvent::Removed {
+                        key: key.clone(),
+                        at: now.instant(),
+                    },
+                })
+                .collect()
         });
+        self.bury(removed.iter().map(|(key, _)| key));
+        // Handles are dropped outside the lock as well
+        for (key, entry) in removed {
+            entry.evict(key);
+        }
+        counts
     }
-}
-impl<SessionKey, SessionHandle> SessionLayer<SessionKey, SessionHandle>
-where
-    SessionKey: std::fmt::Debug + std::hash::Hash + Eq + Clone,
-    SessionHandle: std::fmt::Debug + Clone,
-{
-    /// Clone out the session handle
-    pub fn get<Q: ?Sized>(&self, key: &Q) -> Option<SessionHandle>
+
+    /// The entry under `key` or under the primary key that `key` is a live alias of
+    fn resolve<'a, Q>(
+        &self,
+        key_to_session: &'a SessionMap<SessionKey, Entry<SessionKey, SessionHandle, Metadata>>,
+        key: &Q,
+    ) -> Option<(
+        &'a SessionKey,
+        &'a Entry<SessionKey, SessionHandle, Metadata>,
+    )>
     where
         SessionKey: Borrow<Q>,
-        Q: Eq + std::hash::Hash,
+        Q: ?Sized + Eq + std::hash::Hash,
     {
-        let key_to_session = self.key_to_session.read().unwrap();
-        let (session, time) = key_to_session.get(key)?;
-        let mut time = time.lock().unwrap();
-        *time = Instant::now();
-        Some(session.clone())
+        if let Some(found) = key_to_session.get_key_value(key) {
+            return Some(found);
+        }
+        let aliases = self.aliases.read();
+        let found = key_to_session.get_key_value::<SessionKey>(aliases.get(key)?)?;
+        found
+            .1
+            .aliases
+            .iter()
+            .any(|alias| alias.borrow() == key)
+            .then_some(found)
     }
 
-    pub fn insert(
+    /// Whether `key` is a live alias rather than a primary key
+    fn is_alias<Q>(
+        &self,
+        key_to_session: &SessionMap<SessionKey, Entry<SessionKey, SessionHandle, Metadata>>,
+        key: &Q,
+    ) -> bool
+    where
+        SessionKey: Borrow<Q>,
+        Q: ?Sized + Eq + std::hash::Hash,
+    {
+        !key_to_session.contains_key(key) && self.resolve(key_to_session, key).is_some()
+    }
+
+    /// Same as [`SessionMap::remove_entry`] but follow a live alias and forget the aliases of the removed session
+    fn remove_resolved<Q>(
+        &self,
+        key_to_session: &mut SessionMap<SessionKey, Entry<SessionKey, SessionHandle, Metadata>>,
+        key: &Q,
+    ) -> Option<(SessionKey, Entry<SessionKey, SessionHandle, Metadata>)>
+    where
+        SessionKey: Borrow<Q>,
+        Q: ?Sized + Eq + std::hash::Hash,
+    {
+        let removed = match key_to_session.contains_key(key) {
+            true => key_to_session.remove_entry(key),
+            false => {
+                let primary = self.resolve(key_to_session, key)?.0.clone();
+                key_to_session.remove_entry::<SessionKey>(&primary)
+            }
+        };
+        self.forget_aliases(removed.iter().map(|(key, entry)| (key, entry)));
+        removed
+    }
+
+    /// Forget the aliases of sessions taken out of the map, which every path removing sessions goes through
+    ///
+    /// Must be called with the map write-locked, so that no alias is added under the same primary key in between.
+    fn forget_aliases<'a>(
+        &self,
+        removed: impl IntoIterator<
+            Item = (
+                &'a SessionKey,
+                &'a Entry<SessionKey, SessionHandle, Metadata>,
+            ),
+        >,
+    ) where
+        SessionKey: 'a,
+        SessionHandle: 'a,
+        Metadata: 'a,
+    {
+        let mut removed = removed
+            .into_iter()
+            .filter(|(_, entry)| !entry.aliases.is_empty())
+            .peekable();
+        if removed.peek().is_none() {
+            return;
+        }
+        let mut aliases = self.aliases.write();
+        for (primary, entry) in removed {
+            for alias in &entry.aliases {
+                if aliases.get(alias) == Some(primary) {
+                    aliases.remove(alias);
+                }
+            }
+        }
+    }
+
+    /// Point `aliases` to the session under `primary`
+    ///
+    /// Aliases are recorded in the key filter as well, so that lookups rule out misses without the alias lock.
+    fn register_aliases<'a>(
+        &self,
+        aliases: impl IntoIterator<Item = &'a SessionKey>,
+        primary: &SessionKey,
+    ) where
+        SessionKey: 'a,
+    {
+        let mut aliases = aliases.into_iter().peekable();
+        if aliases.peek().is_none() {
+            return;
+        }
+        let mut alias_to_primary = self.aliases.write();
+        for alias in aliases {
+            if let Some(key_filter) = &self.key_filter {
+                key_filter.insert(alias);
+            }
+            alias_to_primary.insert(alias.clone(), primary.clone());
+        }
+    }
+
+    /// Forget the aliases of sessions removed without [`Self::remove_resolved`], or shadowed by a session under the alias itself
+    fn prune_aliases(
+        &self,
+        key_to_session: &SessionMap<SessionKey, Entry<SessionKey, SessionHandle, Metadata>>,
+    ) {
+        let mut aliases = self.aliases.write();
+        if aliases.is_empty() {
+            return;
+        }
+        aliases.retain(|alias, primary| {
+            !key_to_session.contains_key(alias)
+                && key_to_session
+                    .get(primary)
+                    .is_some_and(|entry| entry.aliases.contains(alias))
+        });
+    }
+
+    /// Remove the expired sessions among the due keys of the wheel and reschedule the others
+    ///
+    /// Also collect the keys due for the `on_expiring` hook into `expiring`.
+    fn extract_due(
+        &self,
+        key_to_session: &mut SessionMap<SessionKey, Entry<SessionKey, SessionHandle, Metadata>>,
+        expiry_wheel: &ExpiryWheel<SessionKey>,
+        now: Timestamp,
+        expiring: &mut Vec<SessionKey>,
+    ) -> Vec<(SessionKey, Entry<SessionKey, SessionHandle, Metadata>)> {
+        let mut removed = vec![];
+        for (tick, keys) in expiry_wheel.take_due(now) {
+            for key in keys {
+                let Some(entry) = key_to_session.get(&key) else {
+                    continue;
+                };
+                // Otherwise rescheduled since, or another session under the same key
+                if entry.wheel_tick.load(Ordering::Relaxed) == tick {
+                    if self.is_expired(entry, now) {
+                        removed.extend(key_to_session.remove_entry(&key));
+                        continue;
+                    }
+                    self.schedule_expiry(&key, entry, now);
+                }
+                if entry.expiring_tick.load(Ordering::Relaxed) == tick {
+                    match self.expiring_in(entry, now) {
+                        Some(expiring_in) if !expiring_in.is_zero() => {
+                            self.schedule_expiry(&key, entry, now)
+                        }
+                        due => {
+                            entry.expiring_tick.store(u64::MAX, Ordering::Relaxed);
+                            expiring.extend(due.map(|_| key));
+                        }
+                    }
+                }
+            }
+        }
+        removed
+    }
+
+    /// How long until the session enters the window of the `on_expiring` hook, or [`None`] if it never does or the hook is not set
+    fn expiring_in(
+        &self,
+        entry: &Entry<SessionKey, SessionHandle, Metadata>,
+        now: Timestamp,
+    ) -> Option<Duration> {
+        let (window, _) = self.on_expiring.as_ref()?;
+        Some(self.remaining(entry, now)?.saturating_sub(*window))
+    }
+
+    /// The tick of the wheel that the session is due to expire at if it ever does
+    fn expiry_tick(
+        &self,
+        entry: &Entry<SessionKey, SessionHandle, Metadata>,
+        now: Timestamp,
+    ) -> Option<u64> {
+        let expiry_wheel = self.expiry_wheel.as_ref()?;
+        Some(expiry_wheel.tick_of(now, self.remaining(entry, now)?))
+    }
+
+    /// Put the key in the bucket of the wheel that its session is due to expire at, and in the one it is due for the `on_expiring` hook at
+    ///
+    /// Called whenever an entry is put into the map, since the sweep finds sessions only through the wheel.
+    fn schedule_expiry(
+        &self,
+        key: &SessionKey,
+        entry: &Entry<SessionKey, SessionHandle, Metadata>,
+        now: Timestamp,
+    ) {
+        let (Some(expiry_wheel), Some(tick)) = (&self.expiry_wheel, self.expiry_tick(entry, now))
+        else {
+            return;
+        };
+        entry.wheel_tick.store(tick, Ordering::Relaxed);
+        expiry_wheel.schedule(key.clone(), tick);
+        if let Some(expiring_in) = self.expiring_in(entry, now) {
+            let tick = expiry_wheel.tick_of(now, expiring_in);
+            if entry.expiring_tick.swap(tick, Ordering::Relaxed) != tick {
+                expiry_wheel.schedule(key.clone(), tick);
+            }
+        }
+    }
+
+    fn release_reservation(&self, key: &SessionKey, id: u64) {
+        self.reservations.release(key, id);
+    }
+
+    /// Run the `on_expiring` hook on sessions newly close to their timeout
+    ///
+    /// Only the `due` keys are visited if the wheel found them, otherwise all sessions are.
+    fn notify_expiring(
+        &self,
+        key_to_session: &SessionMap<SessionKey, Entry<SessionKey, SessionHandle, Metadata>>,
+        due: Option<Vec<SessionKey>>,
+        now: Timestamp,
+    ) {
+        let (Some(_), Some((window, on_expiring))) = (self.shortest_timeout, &self.on_expiring)
+        else {
+            return;
+        };
+        if let Some(due) = due {
+            for key in due {
+                let Some(entry) = key_to_session.get(&key) else {
+                    continue;
+                };
+                if !entry.expiring_notified.swap(true, Ordering::Relaxed) {
+                    on_expiring(&key, &entry.session);
+                }
+            }
+            return;
+        }
+        for (key, entry) in key_to_session.iter() {
+            let Some(remaining) = self.remaining(entry, now) else {
+                continue;
+            };
+            if remaining >= *window {
+                continue;
+            }
+            // Once per episode until the session is accessed again
+            if !entry.expiring_notified.swap(true, Ordering::Relaxed) {
+                on_expiring(key, &entry.session);
+            }
+        }
+    }
+
+    fn is_alive(&self, session: &SessionHandle) -> bool {
+        self.is_alive
+            .as_ref()
+            .is_none_or(|is_alive| is_alive(session))
+    }
+
+    /// Idle past the timeout under [`ResurrectPolicy::Strict`], or past the lifetime declared by the handle under any policy
+    fn is_stale(&self, entry: &Entry<SessionKey, SessionHandle, Metadata>, now: Timestamp) -> bool {
+        match self.resurrect_policy {
+            ResurrectPolicy::Strict => self.is_expired(entry, now),
+            ResurrectPolicy::Lenient => entry
+                .lifetime
+                .is_some_and(|lifetime| lifetime <= entry.age(now)),
+        }
+    }
+
+    /// Due for removal by the sweep
+    fn is_expired(
+        &self,
+        entry: &Entry<SessionKey, SessionHandle, Metadata>,
+        now: Timestamp,
+    ) -> bool {
+        self.remaining(entry, now)
+            .is_some_and(|remaining| remaining.is_zero())
+    }
+
+    /// The time left before the timeout or the lifetime declared by the handle runs out, whichever comes first, or [`None`] if neither applies
+    fn remaining(
+        &self,
+        entry: &Entry<SessionKey, SessionHandle, Metadata>,
+        now: Timestamp,
+    ) -> Option<Duration> {
+        let idle = self
+            .timeout_of(entry)
+            .map(|timeout| timeout.saturating_sub(entry.idle(now)));
+        let lifetime = entry
+            .lifetime
+            .map(|lifetime| lifetime.saturating_sub(entry.age(now)));
+        idle.into_iter().chain(lifetime).min()
+    }
+
+    /// How long after its creation the session expires by the lifetime its handle declares from `now` on, see [`SessionLayerBuilder::respect_handle_expiry`]
+    fn lifetime_of(
+        &self,
+        entry: &Entry<SessionKey, SessionHandle, Metadata>,
+        now: Timestamp,
+    ) -> Option<Duration> {
+        let expires_in = self.handle_expiry.as_ref()?(&entry.session)?;
+        Some(entry.age(now).saturating_add(expires_in))
+    }
+
+    fn timeout_of(&self, entry: &Entry<SessionKey, SessionHandle, Metadata>) -> Option<Duration> {
+        self.class_timeouts
+            .get(usize::from(entry.class.load(Ordering::Relaxed)))
+            .copied()
+            .unwrap_or(self.timeout)
+    }
+
+    /// Summarize the idle times of all sessions without refreshing any of them
+    ///
+    /// This is O(n) under the read lock.
+    ///
+    /// If sessions never expire, the histogram spans up to the oldest idle time instead of the timeout.
+    pub fn stats_snapshot(&self) -> PopulationStats {
+        let now = self.now();
+        let key_to_session = self.key_to_session.read();
+        let idle_times = key_to_session.values().map(|entry| entry.idle(now));
+        match self.timeout {
+            Some(timeout) => PopulationStats::from_idle_times(timeout, idle_times),
+            None => {
+                let idle_times = idle_times.collect::<Vec<_>>();
+                drop(key_to_session);
+                let span = idle_times.iter().max().copied().unwrap_or_default();
+                PopulationStats::from_idle_times(span, idle_times.into_iter())
+            }
+        }
+    }
+
+    /// Apply the key normalizer configured on the builder if any
+    pub fn normalize_key(&self, key: &SessionKey) -> SessionKey {
+        match &self.normalize_key {
+            Some(normalize) => normalize(key),
+            None => key.clone(),
+        }
+    }
+
+    fn normalized(&self, key: SessionKey) -> SessionKey {
+        match &self.normalize_key {
+            Some(normalize) => normalize(&key),
+            None => key,
+        }
+    }
+
+    /// The summed weights of all sessions, or zero without a weight budget
+    pub fn total_weight(&self) -> usize {
+        self.key_to_session.read().total_weight()
+    }
+
+    fn weigh(&self, session: &SessionHandle) -> usize {
+        self.weight_budget
+            .as_ref()
+            .map_or(0, |(_, weigh)| weigh(session))
+    }
+
+    /// Evict the least recently used sessions other than `keep` until the map fits the weight budget
+    ///
+    /// Sorts all sessions by recency, so the cost is only paid when over budget.
+    fn evict_overweight(
+        &self,
+        key_to_session: &mut SessionMap<SessionKey, Entry<SessionKey, SessionHandle, Metadata>>,
+        keep: Option<&SessionKey>,
+    ) -> Vec<(SessionKey, Entry<SessionKey, SessionHandle, Metadata>)> {
+        let Some((max_total_weight, _)) = &self.weight_budget else {
+            return vec![];
+        };
+        if key_to_session.total_weight() <= *max_total_weight {
+            return vec![];
+        }
+        let mut by_recency = key_to_session
+            .iter()
+            .filter(|(key, _)| keep != Some(*key))
+            .map(|(key, entry)| (entry.seq.load(Ordering::Relaxed), key.clone()))
+            .collect::<Vec<_>>();
+        by_recency.sort_unstable_by_key(|(seq, _)| *seq);
+        let mut evicted = vec![];
+        for (_, key) in by_recency {
+            if key_to_session.total_weight() <= *max_total_weight {
+                break;
+            }
+            evicted.extend(key_to_session.remove_entry(&key));
+        }
+        self.forget_aliases(evicted.iter().map(|(key, entry)| (key, entry)));
+        evicted
+    }
+
+    /// Report and drop what [`Self::evict_overweight`] took out
+    ///
+    /// Must not be called with the map locked.
+    fn finish_eviction(
+        &self,
+        evicted: Vec<(SessionKey, Entry<SessionKey, SessionHandle, Metadata>)>,
+    ) {
+        if evicted.is_empty() {
+            return;
+        }
+        self.replicate(|sink| {
+            for (key, _) in &evicted {
+                sink.on_remove(key, RemovalCause::Evicted);
+            }
+        });
+        self.bury(evicted.iter().map(|(key, _)| key));
+        let at = self.now().instant();
+        self.publish_with(|| {
+            evicted
+                .iter()
+                .map(|(key, _)| SessionEvent::Removed {
+                    key: key.clone(),
+                    at,
+                })
+                .collect()
+        });
+        for (key, entry) in evicted {
+            entry.evict(key);
+        }
+    }
+
+    fn new_entry(
         &self,
-        key: SessionKey,
         session: SessionHandle,
-    ) -> Result<(), SessionCollision<SessionHandle>> {
-        let mut key_to_session = self.key_to_session.write().unwrap();
-        if key_to_session.get(&key).is_some() {
-            return Err(SessionCollision(session));
+        last_access: Timestamp,
+    ) -> Entry<SessionKey, SessionHandle, Metadata>
+    where
+        Metadata: Default,
+    {
+        let mut entry = Entry::new(session, last_access, self.next_seq());
+        entry.weight = self.weigh(&entry.session);
+        entry.lifetime = self.lifetime_of(&entry, self.now());
+        entry
+    }
+
+    /// Only allocate if a key normalizer is configured
+    fn normalized_ref<'a>(&self, key: &'a SessionKey) -> Cow<'a, SessionKey> {
+        match &self.normalize_key {
+            Some(normalize) => Cow::Owned(normalize(key)),
+            None => Cow::Borrowed(key),
         }
-        key_to_session.insert(key, (session, Mutex::new(Instant::now())));
-        Ok(())
     }
-}
 
-#[derive(Debug, Clone, thiserror::Error)]
-#[error("session collision: {0}")]
-pub struct SessionCollision<SessionHandle: std::fmt::Debug>(pub SessionHandle);
+    /// Receive lifecycle events of all sessions from now on
+    ///
+    /// A subscriber that falls behind by more than the event capacity misses the oldest events.
+    pub fn subscribe(&self) -> broadcast::Receiver<SessionEvent<SessionKey>> {
+        self.events.subscribe()
+    }
+
+    /// Whether events are needed, by subscribers or by the audit log
+    fn has_subscribers(&self) -> bool {
+        self.events.receiver_count() != 0 || self.audit.is_some()
+    }
+
+    /// Only build the events if anyone is listening
+    ///
+    /// Must not be called with the map locked.
+    fn publish_with(&self, events: impl FnOnce() -> Vec<SessionEvent<SessionKey>>) {
+        if !self.has_subscribers() {
+            return;
+        }
+        for event in events() {
+            if let Some(audit) = &self.audit {
+                audit.record_event(&event, |key| self.key_label(key));
+            }
+            let _ = self.events.send(event);
+        }
+    }
+
+    /// The latest operations, oldest first, if enabled by [`SessionLayerBuilder::audit_capacity`]
+    pub fn audit_log(&self) -> Vec<AuditRecord> {
+        self.audit
+            .as_ref()
+            .map(|audit| audit.records())
+            .unwrap_or_default()
+    }
+
+    /// Must not be called with the map locked.
+    fn audit_collision(&self, key: &SessionKey) {
+        if let Some(audit) = &self.audit {
+            audit.record(
+                self.now().instant(),
+                AuditOp::Collision,
+                self.key_label(key),
+            );
+        }
+    }
+
+    /// The identifier of the key wherever the layer exposes keys as text, see [`SessionLayerBuilder::key_label`]
+    pub fn key_label(&self, key: &SessionKey) -> String {
+        match &self.key_label {
+            Some(label) => label(key),
+            None => short_hash(key),
+        }
+    }
+
+    /// Clone the handle for the replication sink if any, cheap enough to do under the lock
+    fn replica(&self, session: &SessionHandle) -> Option<SessionHandle> {
+        let replication = self.replication.as_ref()?;
+        Some((replication.clone_session)(session))
+    }
+
+    /// Report sessions taken out by the caller to the replication sink and the tombstones
+    fn report_removal<'a>(&self, keys: impl Iterator<Item = &'a SessionKey> + Clone)
+    where
+        SessionKey: 'a,
+    {
+        self.replicate(|sink| {
+            for key in keys.clone() {
+                sink.on_remove(key, RemovalCause::Removed);
+            }
+        });
+        self.bury(keys);
+    }
+
+    /// Remember the keys for [`SessionLayer::lookup`] if tombstones are enabled
+    fn bury<'a>(&self, keys: impl Iterator<Item = &'a SessionKey>)
+    where
+        SessionKey: 'a,
+    {
+        if let Some(tombstones) = &self.tombstones {
+            let hashes = keys.map(|key| self.scan_hasher.hash_one(key));
+            tombstones.bury(hashes, self.now());
+        }
+    }
+
+    fn record_miss<Q>(&self, key: &Q)
+    where
+        Q: ?Sized + std::hash::Hash,
+    {
+        if let Some(misses) = &self.misses {
+            misses.record(self.scan_hasher.hash_one(key), self.now().instant());
+        }
+    }
+
+    /// How many `on_evict` callbacks were skipped under [`crate::FullQueuePolicy::Drop`]
+    pub fn dropped_evictions(&self) -> u64 {
+        self.eviction_queue
+            .as_ref()
+            .map_or(0, |queue| queue.dropped())
+    }
+
+    /// The `n` key hashes missed the most within the window of [`SessionLayerBuilder::track_misses`], most first
+    ///
+    /// Compare them against [`Self::misses_for`] of suspected keys.
+    /// Empty if misses are not tracked.
+    pub fn top_missed(&self, n: usize) -> Vec<(u64, u32)> {
+        match &self.misses {
+            Some(misses) => misses.top(n, self.now().instant()),
+            None => vec![],
+        }
+    }
+
+    /// How many lookups missed the key within the window of [`SessionLayerBuilder::track_misses`]
+    ///
+    /// Zero if misses are not tracked, or if the key was forgotten to make room for others.
+    pub fn misses_for<Q>(&self, key: &Q) -> u32
+    where
+        SessionKey: Borrow<Q>,
+        Q: ?Sized + Eq + std::hash::Hash,
+    {
+        match &self.misses {
+            Some(misses) => misses.misses(self.scan_hasher.hash_one(key), self.now().instant()),
+            None => 0,
+        }
+    }
+
+    /// Classify a key missing from the map by the tombstones
+    pub(crate) fn missing<Q, T>(&self, key: &Q) -> Lookup<T>
+    where
+        SessionKey: Borrow<Q>,
+        Q: ?Sized + Eq + std::hash::Hash,
+    {
+        let since = self
+            .tombstones
+            .as_ref()
+            .and_then(|tombstones| tombstones.since(self.scan_hasher.hash_one(key), self.now()));
+        match since {
+            Some(since) => Lookup::Expired { since },
+            None => Lookup::Unknown,
+        }
+    }
+
+    /// Must not be called with the map locked.
+    fn replicate(&self, f: impl FnOnce(&dyn ReplicationSink<SessionKey, SessionHandle>)) {
+        if let Some(replication) = &self.replication {
+            f(&*replication.sink);
+        }
+    }
+}
+impl<SessionKey, SessionHandle, Metadata> SessionLayer<SessionKey, SessionHandle, Metadata>
+where
+    SessionKey: std::fmt::Debug + std::hash::Hash + Eq + Clone + Sync + Send + 'static,
+    SessionHandle: std::fmt::Debug + Sync + Send + 'static,
+    Metadata: Default + Sync + Send + 'static,
+{
+    /// Same as [`Self::get`] but run `f` on the stored handle instead of cloning it out
+    ///
+    /// Only this and the other methods cloning out handles are unavailable to handles that cannot be cloned, so such a layer is read through here.
+    /// `f` runs under the read lock, so it must not call back into the layer.
+    pub fn get_ref<Q, R>(&self, key: &Q, f: impl FnOnce(&SessionHandle) -> R) -> Option<R>
+    where
+        SessionKey: Borrow<Q>,
+        Q: ?Sized + Eq + std::hash::Hash,
+    {
+        self.get_entry(key, |_, entry, _| f(&entry.session))
+    }
+
+    /// Same as [`Self::get_ref`] but also pass the stored key and the idle time just ended
+    pub(crate) fn get_with<Q, R>(
+        &self,
+        key: &Q,
+        f: impl FnOnce(&SessionKey, &SessionHandle, Duration) -> R,
+    ) -> Option<R>
+    where
+        SessionKey: Borrow<Q>,
+        Q: ?Sized + Eq + std::hash::Hash,
+    {
+        self.get_entry(key, |stored_key, entry, idle| {
+            f(stored_key, &entry.session, idle)
+        })
+    }
+
+    /// Same as [`Self::get_with`] but leave the session untouched and return the time left before it expires instead if that is less than `min_remaining`
+    pub(crate) fn get_with_if_fresh<Q, R>(
+        &self,
+        key: &Q,
+        min_remaining: Duration,
+        f: impl FnOnce(&SessionKey, &SessionHandle, Duration) -> R,
+    ) -> Option<Result<R, Duration>>
+    where
+        SessionKey: Borrow<Q>,
+        Q: ?Sized + Eq + std::hash::Hash,
+    {
+        let check = |entry: &Entry<SessionKey, SessionHandle, Metadata>, now| {
+            let remaining = self.remaining(entry, now).unwrap_or(Duration::MAX);
+            match remaining < min_remaining {
+                true => Err(remaining),
+                false => Ok(()),
+            }
+        };
+        self.get_entry_if(key, check, |stored_key, entry, idle| {
+            f(stored_key, &entry.session, idle)
+        })
+    }
+
+    /// Refresh the session and return what `f` makes of its stored key, its entry, and the idle time just ended under the read lock
+    fn get_entry<Q, R>(
+        &self,
+        key: &Q,
+        f: impl FnOnce(&SessionKey, &Entry<SessionKey, SessionHandle, Metadata>, Duration) -> R,
+    ) -> Option<R>
+    where
+        SessionKey: Borrow<Q>,
+        Q: ?Sized + Eq + std::hash::Hash,
+    {
+        let check = |_: &_, _| Ok::<_, Infallible>(());
+        match self.get_entry_if(key, check, f)? {
+            Ok(res) => Some(res),
+        }
+    }
+
+    /// Same as [`Self::get_entry`] but leave the session untouched and return the error instead if `check` fails under the same read lock
+    fn get_entry_if<Q, R, E>(
+        &self,
+        key: &Q,
+        check: impl FnOnce(&Entry<SessionKey, SessionHandle, Metadata>, Timestamp) -> Result<(), E>,
+        f: impl FnOnce(&SessionKey, &Entry<SessionKey, SessionHandle, Metadata>, Duration) -> R,
+    ) -> Option<Result<R, E>>
+    where
+        SessionKey: Borrow<Q>,
+        Q: ?Sized + Eq + std::hash::Hash,
+    {
+        // Aliases are in the filter too
+        if self
+            .key_filter
+            .as_ref()
+            .is_some_and(|key_filter| key_filter.rules_out(key))
+        {
+            self.record_miss(key);
+            return None;
+        }
+        let key_to_session = self.key_to_session.read();
+        let Some((stored_key, entry)) = self.resolve(&key_to_session, key) else {
+            drop(key_to_session);
+            self.record_miss(key);
+            return None;
+        };
+        if !self.is_alive(&entry.session) {
+            drop(key_to_session);
+            // The session might have been replaced in between
+            self.remove_entry_if(
+                key,
+                |entry| !self.is_alive(&entry.session),
+                RemovalCause::Dead,
+            );
+            return None;
+        }
+        let now = self.now();
+        if self.is_stale(entry, now) {
+            drop(key_to_session);
+            self.expire_stale(key, now);
+            return None;
+        }
+        if let Err(e) = check(entry, now) {
+            return Some(Err(e));
+        }
+        let idle = entry.touch(now, self.next_seq());
+        let res = f(stored_key, entry, idle);
+        let key = (self.access_events || self.replication.is_some()).then(|| stored_key.clone());
+        drop(key_to_session);
+
+        if let Some(key) = key {
+            self.replicate(|sink| sink.on_touch(&key));
+            if self.access_events {
+                self.publish_with(|| {
+                    vec![SessionEvent::Accessed {
+                        key,
+                        at: now.instant(),
+                    }]
+                });
+            }
+        }
+        Some(Ok(res))
+    }
+
+    /// Remove the session as the sweep would have if it is still stale, see [`ResurrectPolicy::Strict`]
+    fn expire_stale<Q>(&self, key: &Q, now: Timestamp)
+    where
+        SessionKey: Borrow<Q>,
+        Q: ?Sized + Eq + std::hash::Hash,
+    {
+        let mut key_to_session = self.write_map();
+        // The session might have been refreshed or replaced in between
+        if !self
+            .resolve(&key_to_session, key)
+            .is_some_and(|(_, entry)| self.is_stale(entry, now))
+        {
+            return;
+        }
+        let Some((key, entry)) = self.remove_resolved(&mut key_to_session, key) else {
+            return;
+        };
+        drop(key_to_session);
+
+        self.replicate(|sink| sink.on_remove(&key, RemovalCause::Expired));
+        self.bury(std::iter::once(&key));
+        self.publish_with(|| {
+            vec![SessionEvent::Expired {
+                key: key.clone(),
+                at: now.instant(),
+            }]
+        });
+        entry.evict(key);
+    }
+
+    /// Hand the session back if it exceeds the rate limit
+    fn throttle(
+        &self,
+        session: SessionHandle,
+    ) -> Result<SessionHandle, InsertError<SessionHandle>> {
+        match &self.insert_rate_limit {
+            Some(limiter) if !limiter.try_acquire(self.now().instant()) => {
+                Err(InsertError::RateLimited(session))
+            }
+            _ => Ok(session),
+        }
+    }
+
+    /// Hand the session back if the admission callback denies it
+    fn admit(
+        &self,
+        key: &SessionKey,
+        session: SessionHandle,
+    ) -> Result<SessionHandle, InsertError<SessionHandle>> {
+        let Some(admission) = &self.admission else {
+            return Ok(session);
+        };
+        match admission(key, &session) {
+            Ok(()) => Ok(session),
+            Err(denied) => Err(InsertError::Denied { session, denied }),
+        }
+    }
+
+    /// Fail on a collision only under [`CollisionPolicy::Reject`], the default
+    ///
+    /// [`Self::insert_or_replace`] and [`Self::get_or_insert_with`] are unaffected by the policy.
+    /// Fail as well if the admission callback denies the session, see [`SessionLayerBuilder::admission`], or past the rate limit, see [`SessionLayerBuilder::insert_rate_limit`].
+    pub fn insert(
+        &self,
+        key: SessionKey,
+        session: SessionHandle,
+    ) -> Result<(), InsertError<SessionHandle>> {
+        self.insert_entry(key, session, EntryOptions::default(), |_| ())
+    }
+
+    /// Same as [`Self::insert`] but derive the key from the handle, e.g. from its ID field, and return the key
+    ///
+    /// Failures are the same [`InsertError`] as those of [`Self::insert`], which hands the session back and from which the caller can derive the key again.
+    pub fn insert_derived(
+        &self,
+        session: SessionHandle,
+        key_of: impl FnOnce(&SessionHandle) -> SessionKey,
+    ) -> Result<SessionKey, InsertError<SessionHandle>> {
+        let key = key_of(&session);
+        self.insert(key.clone(), session)?;
+        Ok(key)
+    }
+
+    /// Same as [`Self::insert`] but store `metadata` alongside the handle
+    ///
+    /// Sessions inserted any other way get the default metadata.
+    pub fn insert_with_metadata(
+        &self,
+        key: SessionKey,
+        session: SessionHandle,
+        metadata: Metadata,
+    ) -> Result<(), InsertError<SessionHandle>> {
+        let options = EntryOptions {
+            metadata,
+            ..Default::default()
+        };
+        self.insert_entry(key, session, options, |_| ())
+    }
+
+    /// Same as [`Self::insert`] but as if the session had been created and last accessed at `last_access`, e.g. to replay recorded traffic or restore persisted sessions
+    ///
+    /// A session already idle for longer than the timeout is removed by the next sweep, and missed by lookups until then under [`ResurrectPolicy::Strict`].
+    pub fn insert_at(
+        &self,
+        key: SessionKey,
+        session: SessionHandle,
+        last_access: Instant,
+    ) -> Result<(), InsertError<SessionHandle>> {
+        let options = EntryOptions {
+            last_access: Some(last_access),
+            ..Default::default()
+        };
+        self.insert_entry(key, session, options, |_| ())
+    }
+
+    /// Same as [`Self::insert`] but expire the session on the timeout of `class` instead of the default one
+    pub fn insert_in_class(
+        &self,
+        key: SessionKey,
+        session: SessionHandle,
+        class: TimeoutClass,
+    ) -> Result<(), InsertError<SessionHandle>> {
+        let options = EntryOptions {
+            class,
+            ..Default::default()
+        };
+        self.insert_entry(key, session, options, |_| ())
+    }
+
+    /// Same as [`Self::insert`] but also make the session reachable under each of `aliases`, e.g. under every connection ID of a QUIC connection
+    ///
+    /// The keys share one session: an access through any of them refreshes it, and removing or expiring it drops all of them together.
+    /// Lookups, [`Self::peek`], [`Self::metadata`], [`Self::set_metadata`], [`Self::age`], [`Self::idle_time`], [`Self::contains_key`], [`Self::contains_and_touch`], [`Self::get_or_insert_with`] and its variants, and removals such as [`Self::remove`], [`Self::remove_many`], and [`Self::remove_if_version`] follow aliases, while other methods take the primary key.
+    /// An alias already taken or given twice is a collision regardless of the collision policy.
+    /// Aliases are not replicated.
+    pub fn insert_aliases(
+        &self,
+        primary: SessionKey,
+        aliases: Vec<SessionKey>,
+        session: SessionHandle,
+    ) -> Result<(), InsertError<SessionHandle>> {
+        let options = EntryOptions {
+            aliases: aliases
+                .into_iter()
+                .map(|alias| self.normalized(alias))
+                .collect(),
+            ..Default::default()
+        };
+        self.insert_entry(primary, session, options, |_| ())
+    }
+
+    /// Make the session under `existing`, a primary key or an alias, reachable under `alias` as well, see [`Self::insert_aliases`]
+    pub fn add_alias<Q>(&self, existing: &Q, alias: SessionKey) -> Result<(), AliasError>
+    where
+        SessionKey: Borrow<Q>,
+        Q: ?Sized + Eq + std::hash::Hash,
+    {
+        let alias = self.normalized(alias);
+        let mut key_to_session = self.key_to_session.write();
+        if self
+            .resolve::<SessionKey>(&key_to_session, &alias)
+            .is_some()
+            || self
+                .reservations
+                .is_claimed_by_other(&alias, None, self.now())
+        {
+            drop(key_to_session);
+            self.audit_collision(&alias);
+            return Err(AliasError::Collision);
+        }
+        let primary = self
+            .resolve(&key_to_session, existing)
+            .ok_or(AliasError::NotFound)?
+            .0
+            .clone();
+        self.register_aliases(std::iter::once(&alias), &primary);
+        key_to_session
+            .get_mut::<SessionKey>(&primary)
+            .expect("found under the same lock")
+            .aliases
+            .push(alias);
+        Ok(())
+    }
+
+    /// Whether any of the aliases of a new session under `key` is taken or repeated
+    fn aliases_taken(
+        &self,
+        key_to_session: &SessionMap<SessionKey, Entry<SessionKey, SessionHandle, Metadata>>,
+        key: &SessionKey,
+        aliases: &[SessionKey],
+        now: Timestamp,
+    ) -> bool {
+        aliases.iter().enumerate().any(|(i, alias)| {
+            alias == key
+                || aliases[..i].contains(alias)
+                || self.resolve(key_to_session, alias).is_some()
+                || self.reservations.is_claimed_by_other(alias, None, now)
+        })
+    }
+
+    /// Move the session to `class`, e.g. from a handshake class to an established one, and refresh it
+    ///
+    /// Return whether the session exists.
+    /// Only the read lock is taken, as in [`Self::contains_and_touch`].
+    pub fn promote<Q>(&self, key: &Q, class: TimeoutClass) -> bool
+    where
+        SessionKey: Borrow<Q>,
+        Q: ?Sized + Eq + std::hash::Hash,
+    {
+        let key_to_session = self.key_to_session.read();
+        let Some((stored_key, entry)) = key_to_session.get_key_value(key) else {
+            return false;
+        };
+        let now = self.now();
+        if !self.is_alive(&entry.session) {
+            return false;
+        }
+        if self.is_stale(entry, now) {
+            drop(key_to_session);
+            self.expire_stale(key, now);
+            return false;
+        }
+        entry.class.store(class.0, Ordering::Relaxed);
+        entry.touch(now, self.next_seq());
+        // A later deadline is picked up once the scheduled one is due, but an earlier one would be missed
+        if self
+            .expiry_tick(entry, now)
+            .is_some_and(|tick| tick < entry.wheel_tick.load(Ordering::Relaxed))
+        {
+            self.schedule_expiry(stored_key, entry, now);
+        }
+        let key = self.replication.is_some().then(|| stored_key.clone());
+        drop(key_to_session);
+
+        if let Some(key) = key {
+            self.replicate(|sink| sink.on_touch(&key));
+        }
+        true
+    }
+
+    /// Claim the key before its session exists, e.g. early in a handshake that takes several round trips
+    ///
+    /// The key then collides with [`Self::insert`] and its variants, and with other reservations, but lookups do not find it.
+    /// [`Self::insert_or_replace`], [`Self::get_or_insert_with`], [`Self::get_or_try_insert_with`], and bulk operations other than [`Self::insert_many_lenient`] ignore reservations, while [`Self::try_get_or_insert_with`] collides with them.
+    /// A reservation left unfulfilled for longer than [`SessionLayerBuilder::reservation_timeout`] no longer holds the key.
+    pub fn reserve(
+        self: &Arc<Self>,
+        key: SessionKey,
+    ) -> Result<Reservation<SessionKey, SessionHandle, Metadata>, SessionCollision<()>> {
+        let key = self.normalized(key);
+        // Held so that no insertion slips in between the two checks
+        let key_to_session = self.key_to_session.read();
+        let id = self.next_seq();
+        if key_to_session.contains_key(&key)
+            || !self.reservations.claim(key.clone(), id, self.now())
+        {
+            drop(key_to_session);
+            self.audit_collision(&key);
+            return Err(SessionCollision(()));
+        }
+        drop(key_to_session);
+        Ok(Reservation::new(
+            Arc::clone(self),
+            key,
+            id,
+            Self::release_reservation,
+        ))
+    }
+
+    pub(crate) fn fulfill_reservation(
+        &self,
+        key: SessionKey,
+        id: u64,
+        session: SessionHandle,
+    ) -> Result<(), InsertError<SessionHandle>> {
+        let options = EntryOptions {
+            reservation: Some(id),
+            ..Default::default()
+        };
+        self.insert_entry(key, session, options, |_| ())
+    }
+
+    /// Return what `resident` makes of the session left under the key
+    fn insert_entry<R>(
+        &self,
+        key: SessionKey,
+        session: SessionHandle,
+        options: EntryOptions<SessionKey, SessionHandle, Metadata>,
+        resident: impl FnOnce(&SessionHandle) -> R,
+    ) -> Result<R, InsertError<SessionHandle>> {
+        let key = self.normalized(key);
+        let session = self.throttle(session)?;
+        let session = self.admit(&key, session)?;
+        let mut key_to_session = self.write_map();
+        let now = self.now();
+        // Reserved keys and aliases collide regardless of the policy
+        if self
+            .reservations
+            .is_claimed_by_other(&key, options.reservation, now)
+            || self.is_alias(&key_to_session, &key)
+            || self.aliases_taken(&key_to_session, &key, &options.aliases, now)
+        {
+            drop(key_to_session);
+            self.audit_collision(&key);
+            return Err(InsertError::Collision(session));
+        }
+        if let Some(existing) = key_to_session.get(&key) {
+            match self.collision_policy {
+                CollisionPolicy::Reject => {
+                    drop(key_to_session);
+                    self.audit_collision(&key);
+                    return Err(InsertError::Collision(session));
+                }
+                CollisionPolicy::KeepExisting => {
+                    let res = resident(&existing.session);
+                    drop(key_to_session);
+                    // The new handle is dropped outside the lock
+                    drop(session);
+                    return Ok(res);
+                }
+                CollisionPolicy::Overwrite => (),
+            }
+        }
+        let last_access = options
+            .last_access
+            .map_or(now, |at| self.time_source.at(at));
+        let mut entry = self.new_entry(session, last_access);
+        entry.on_evict = options.on_evict;
+        entry.metadata = options.metadata;
+        *entry.class.get_mut() = options.class.0;
+        let res = resident(&entry.session);
+        let replica = self.replica(&entry.session);
+        self.schedule_expiry(&key, &entry, now);
+        self.register_aliases(&options.aliases, &key);
+        entry.aliases = options.aliases;
+        let old = key_to_session.insert(key.clone(), entry);
+        self.forget_aliases(old.iter().map(|old| (&key, old)));
+        if let Some(id) = options.reservation {
+            self.reservations.release(&key, id);
+        }
+        let evicted = self.evict_overweight(&mut key_to_session, Some(&key));
+        drop(key_to_session);
+        self.sweeper_wake.notify_one();
+        self.finish_eviction(evicted);
+        let replaced = old.is_some();
+        if let Some(old) = old {
+            old.evict(key.clone());
+        }
+
+        if let Some(replica) = replica {
+            self.replicate(|sink| sink.on_insert(&key, &replica));
+        }
+        self.publish_with(|| {
+            let at = now.instant();
+            vec![match replaced {
+                true => SessionEvent::Replaced { key, at },
+                false => SessionEvent::Inserted { key, at },
+            }]
+        });
+        Ok(res)
+    }
+
+    pub fn remove<Q>(&self, key: &Q) -> Option<SessionHandle>
+    where
+        SessionKey: Borrow<Q>,
+        Q: ?Sized + Eq + std::hash::Hash,
+    {
+        self.remove_if(key, |_| true).flatten()
+    }
+
+    /// Remove the session only if `pred` approves of it under the write lock
+    ///
+    /// Return [`None`] if the key is not found, or `Some(None)` if the session is kept.
+    pub(crate) fn remove_if<Q>(
+        &self,
+        key: &Q,
+        pred: impl FnOnce(&SessionHandle) -> bool,
+    ) -> Option<Option<SessionHandle>>
+    where
+        SessionKey: Borrow<Q>,
+        Q: ?Sized + Eq + std::hash::Hash,
+    {
+        self.remove_entry_if(key, |entry| pred(&entry.session), RemovalCause::Removed)
+    }
+
+    fn remove_entry_if<Q>(
+        &self,
+        key: &Q,
+        pred: impl FnOnce(&Entry<SessionKey, SessionHandle, Metadata>) -> bool,
+        cause: RemovalCause,
+    ) -> Option<Option<SessionHandle>>
+    where
+        SessionKey: Borrow<Q>,
+        Q: ?Sized + Eq + std::hash::Hash,
+    {
+        let mut key_to_session = self.write_map();
+        if !pred(self.resolve(&key_to_session, key)?.1) {
+            return Some(None);
+        }
+        let (key, entry) = self.remove_resolved(&mut key_to_session, key)?;
+        drop(key_to_session);
+        let session = entry.into_session(&key);
+
+        self.replicate(|sink| sink.on_remove(&key, cause));
+        self.bury(std::iter::once(&key));
+        self.publish_with(|| {
+            vec![SessionEvent::Removed {
+                key,
+                at: self.now().instant(),
+            }]
+        });
+        Some(Some(session))
+    }
+
+    /// Same as [`Self::contains_key`] but normalize the key first, see [`SessionLayerBuilder::normalize_key`]
+    pub fn contains_key_normalized(&self, key: &SessionKey) -> bool {
+        self.contains_key(&*self.normalized_ref(key))
+    }
+
+    /// Same as [`Self::remove`] but normalize the key first, see [`SessionLayerBuilder::normalize_key`]
+    pub fn remove_normalized(&self, key: &SessionKey) -> Option<SessionHandle> {
+        self.remove(&*self.normalized_ref(key))
+    }
+
+    /// Tell if the session exists without refreshing it
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        SessionKey: Borrow<Q>,
+        Q: ?Sized + Eq + std::hash::Hash,
+    {
+        let key_to_session = self.key_to_session.read();
+        self.resolve(&key_to_session, key)
+            .is_some_and(|(_, entry)| {
+                self.is_alive(&entry.session) && !self.is_stale(entry, self.now())
+            })
+    }
+
+    /// All keys at this moment
+    pub fn keys(&self) -> Vec<SessionKey> {
+        self.key_to_session.read().keys().cloned().collect()
+    }
+
+    /// Refresh the session if it exists and return whether it does
+    ///
+    /// The cheapest keepalive: only the read lock is taken, and neither the key nor the handle is cloned unless for a replication sink.
+    /// No [`SessionEvent::Accessed`] is published.
+    pub fn contains_and_touch<Q>(&self, key: &Q) -> bool
+    where
+        SessionKey: Borrow<Q>,
+        Q: ?Sized + Eq + std::hash::Hash,
+    {
+        let key_to_session = self.key_to_session.read();
+        let Some((stored_key, entry)) = self.resolve(&key_to_session, key) else {
+            return false;
+        };
+        let now = self.now();
+        if !self.is_alive(&entry.session) {
+            return false;
+        }
+        if self.is_stale(entry, now) {
+            drop(key_to_session);
+            self.expire_stale(key, now);
+            return false;
+        }
+        entry.touch(now, self.next_seq());
+        let key = self.replication.is_some().then(|| stored_key.clone());
+        drop(key_to_session);
+
+        if let Some(key) = key {
+            self.replicate(|sink| sink.on_touch(&key));
+        }
+        true
+    }
+
+    /// Clone out the metadata of the session without touching the handle or refreshing the session
+    pub fn metadata<Q>(&self, key: &Q) -> Option<Metadata>
+    where
+        SessionKey: Borrow<Q>,
+        Q: ?Sized + Eq + std::hash::Hash,
+        Metadata: Clone,
+    {
+        let key_to_session = self.key_to_session.read();
+        Some(self.resolve(&key_to_session, key)?.1.metadata.clone())
+    }
+
+    /// Overwrite the metadata of the session without touching the handle or refreshing the session and return whether the session exists
+    pub fn set_metadata<Q>(&self, key: &Q, metadata: Metadata) -> bool
+    where
+        SessionKey: Borrow<Q>,
+        Q: ?Sized + Eq + std::hash::Hash,
+    {
+        let mut key_to_session = self.write_map();
+        let Some((primary, _)) = self.resolve(&key_to_session, key) else {
+            return false;
+        };
+        let primary = primary.clone();
+        let entry = key_to_session
+            .get_mut::<SessionKey>(&primary)
+            .expect("found under the same lock");
+        let old = std::mem::replace(&mut entry.metadata, metadata);
+        drop(key_to_session);
+        // Dropped outside the lock
+        drop(old);
+        true
+    }
+
+    /// Keys of the sessions whose metadata satisfies `pred`
+    pub fn find_by_metadata(&self, pred: impl Fn(&Metadata) -> bool) -> Vec<SessionKey> {
+        let key_to_session = self.key_to_session.read();
+        key_to_session
+            .iter()
+            .filter(|(_, entry)| pred(&entry.metadata))
+            .map(|(key, _)| key.clone())
+            .collect()
+    }
+
+    /// Insert each session independently under one write lock
+    ///
+    /// The outcomes are in the same order as `entries`.
+    /// Collisions are resolved by the [`CollisionPolicy`] as in [`Self::insert`], and reserved keys and aliases collide regardless of it as well.
+    pub fn insert_many_lenient(
+        &self,
+        entries: impl IntoIterator<Item = (SessionKey, SessionHandle)>,
+    ) -> Vec<(SessionKey, Result<(), InsertError<SessionHandle>>)> {
+        // Vetted before taking the lock
+        let entries = entries
+            .into_iter()
+            .map(|(key, session)| {
+                let key = self.normalized(key);
+                let session = self
+                    .throttle(session)
+                    .and_then(|session| self.admit(&key, session));
+                (key, session)
+            })
+            .collect::<Vec<_>>();
+        let has_subscribers = self.has_subscribers();
+        let mut outcomes = vec![];
+        let mut events = vec![];
+        let mut discarded = vec![];
+        let mut replaced = vec![];
+        let mut replicas = vec![];
+        let mut key_to_session = self.write_map();
+        let now = self.now();
+        for (key, session) in entries {
+            let session = match session {
+                Ok(session) => session,
+                Err(denied) => {
+                    outcomes.push((key, Err(denied)));
+                    continue;
+                }
+            };
+            // Reserved keys and aliases collide regardless of the policy, as in `insert_entry`
+            if self.reservations.is_claimed_by_other(&key, None, now)
+                || self.is_alias(&key_to_session, &key)
+            {
+                outcomes.push((key, Err(InsertError::Collision(session))));
+                continue;
+            }
+            let exists = key_to_session.contains_key(&key);
+            match (exists, self.collision_policy) {
+                (true, CollisionPolicy::Reject) => {
+                    outcomes.push((key, Err(InsertError::Collision(session))));
+                    continue;
+                }
+                (true, CollisionPolicy::KeepExisting) => {
+                    discarded.push(session);
+                    outcomes.push((key, Ok(())));
+                    continue;
+                }
+                (false, _) | (true, CollisionPolicy::Overwrite) => (),
+            }
+            if let Some(replica) = self.replica(&session) {
+                replicas.push((key.clone(), replica));
+            }
+            let entry = self.new_entry(session, now);
+            self.schedule_expiry(&key, &entry, now);
+            if let Some(old) = key_to_session.insert(key.clone(), entry) {
+                self.forget_aliases(std::iter::once((&key, &old)));
+                replaced.push((key.clone(), old));
+            }
+            if has_subscribers {
+                let key = key.clone();
+                let at = now.instant();
+                events.push(match exists {
+                    true => SessionEvent::Replaced { key, at },
+                    false => SessionEvent::Inserted { key, at },
+                });
+            }
+            outcomes.push((key, Ok(())));
+        }
+        let evicted = self.evict_overweight(&mut key_to_session, None);
+        drop(key_to_session);
+        self.sweeper_wake.notify_one();
+        self.finish_eviction(evicted);
+        drop(discarded);
+        for (key, outcome) in &outcomes {
+            if let Err(InsertError::Collision(_)) = outcome {
+                self.audit_collision(key);
+            }
+        }
+        for (key, entry) in replaced {
+            entry.evict(key);
+        }
+
+        self.replicate(|sink| {
+            for (key, replica) in &replicas {
+                sink.on_insert(key, replica);
+            }
+        });
+        self.publish_with(|| events);
+        outcomes
+    }
+
+    /// Insert the session and return the one it replaced if any
+    ///
+    /// A replaced session counts as a new one, so its age starts over.
+    /// Fail only if the admission callback denies the session or it exceeds the rate limit, see [`SessionLayerBuilder::insert_rate_limit`].
+    pub fn insert_or_replace(
+        &self,
+        key: SessionKey,
+        session: SessionHandle,
+    ) -> Result<Option<SessionHandle>, InsertError<SessionHandle>> {
+        let key = self.normalized(key);
+        let session = self.throttle(session)?;
+        let session = self.admit(&key, session)?;
+        let replica = self.replica(&session);
+        let mut key_to_session = self.write_map();
+        let now = self.now();
+        let entry = self.new_entry(session, now);
+        self.schedule_expiry(&key, &entry, now);
+        let old = key_to_session.insert(key.clone(), entry);
+        self.forget_aliases(old.iter().map(|old| (&key, old)));
+        let evicted = self.evict_overweight(&mut key_to_session, Some(&key));
+        drop(key_to_session);
+        self.sweeper_wake.notify_one();
+        self.finish_eviction(evicted);
+
+        let old = old.map(|entry| entry.into_session(&key));
+        if let Some(replica) = replica {
+            self.replicate(|sink| sink.on_insert(&key, &replica));
+        }
+        self.publish_with(|| {
+            vec![match old {
+                Some(_) => SessionEvent::Replaced {
+                    key,
+                    at: now.instant(),
+                },
+                None => SessionEvent::Inserted {
+                    key,
+                    at: now.instant(),
+                },
+            }]
+        });
+        Ok(old)
+    }
+
+    /// Remove all sessions under `keys` at once, skipping missing ones
+    ///
+    /// The write lock is taken only once, which beats a loop of [`Self::remove`] for large batches.
+    /// Aliases are followed as by [`Self::remove`].
+    pub fn remove_many<'a, Q>(
+        &self,
+        keys: impl IntoIterator<Item = &'a Q>,
+    ) -> Vec<(SessionKey, SessionHandle)>
+    where
+        SessionKey: Borrow<Q>,
+        Q: ?Sized + Eq + std::hash::Hash + 'a,
+    {
+        let mut key_to_session = self.write_map();
+        let removed = keys
+            .into_iter()
+            .filter_map(|key| self.remove_resolved(&mut key_to_session, key))
+            .collect::<Vec<_>>();
+        drop(key_to_session);
+
+        self.report_removal(removed.iter().map(|(key, _)| key));
+        let at = self.now().instant();
+        self.publish_with(|| {
+            removed
+                .iter()
+                .map(|(key, _)| SessionEvent::Removed {
+                    key: key.clone(),
+                    at,
+                })
+                .collect()
+        });
+        removed
+            .into_iter()
+            .map(|(key, entry)| {
+                let session = entry.into_session(&key);
+                (key, session)
+            })
+            .collect()
+    }
+
+    /// Remove and return all sessions matching `pred` at once, e.g. to migrate the sessions of a shard
+    ///
+    /// `pred` sees a consistent view since it runs under the write lock, so it must not call back into the layer.
+    /// Sessions that are kept are not refreshed.
+    pub fn extract_if(
+        &self,
+        mut pred: impl FnMut(&SessionKey, &SessionHandle) -> bool,
+    ) -> Vec<(SessionKey, SessionHandle)> {
+        let mut key_to_session = self.write_map();
+        let extracted = key_to_session.extract_if(|key, entry| pred(key, &entry.session));
+        self.forget_aliases(extracted.iter().map(|(key, entry)| (key, entry)));
+        drop(key_to_session);
+
+        self.report_removal(extracted.iter().map(|(key, _)| key));
+        let at = self.now().instant();
+        self.publish_with(|| {
+            extracted
+                .iter()
+                .map(|(key, _)| SessionEvent::Removed {
+                    key: key.clone(),
+                    at,
+                })
+                .collect()
+        });
+        extracted
+            .into_iter()
+            .map(|(key, entry)| {
+                let session = entry.into_session(&key);
+                (key, session)
+            })
+            .collect()
+    }
+
+    /// Remove all sessions whose keys start with `prefix` at once, e.g. all sessions of a tenant under keys like `"tenant:123:..."`, and return how many were removed
+    ///
+    /// Keys are compared as bytes, so a prefix of a [`String`] key need not end on a character boundary.
+    pub fn remove_prefix(&self, prefix: &[u8]) -> usize
+    where
+        SessionKey: AsRef<[u8]>,
+    {
+        self.extract_if(|key, _| key.as_ref().starts_with(prefix))
+            .len()
+    }
+
+    /// Replace the handle only if it is still at `expected_version` and return its new version
+    ///
+    /// The session is refreshed but keeps its age, metadata, and eviction callback.
+    pub fn replace_if_version<Q>(
+        &self,
+        key: &Q,
+        expected_version: u64,
+        session: SessionHandle,
+    ) -> Result<u64, VersionConflict>
+    where
+        SessionKey: Borrow<Q>,
+        Q: ?Sized + Eq + std::hash::Hash,
+    {
+        let mut key_to_session = self.write_map();
+        let current = key_to_session.get(key).map(|entry| entry.version);
+        if current != Some(expected_version) {
+            return Err(VersionConflict { current });
+        }
+        let now = self.now();
+        let seq = self.next_seq();
+        // Taken out and put back to account for the weight of the new handle
+        let (key, mut entry) = key_to_session
+            .remove_entry(key)
+            .expect("found under the same lock");
+        entry.touch(now, seq);
+        entry.version = seq;
+        let old = std::mem::replace(&mut entry.session, session);
+        entry.weight = self.weigh(&entry.session);
+        entry.lifetime = self.lifetime_of(&entry, now);
+        let replica = self.replica(&entry.session);
+        key_to_session.insert(key.clone(), entry);
+        let evicted = self.evict_overweight(&mut key_to_session, Some(&key));
+        let key = (self.has_subscribers() || replica.is_some()).then_some(key);
+        drop(key_to_session);
+        self.finish_eviction(evicted);
+        // The old handle is dropped outside the lock as well
+        drop(old);
+
+        if let Some(key) = key {
+            if let Some(replica) = replica {
+                self.replicate(|sink| sink.on_insert(&key, &replica));
+            }
+            self.publish_with(|| {
+                vec![SessionEvent::Replaced {
+                    key,
+                    at: now.instant(),
+                }]
+            });
+        }
+        Ok(seq)
+    }
+
+    /// Remove the session only if its handle is still at `expected_version`
+    pub fn remove_if_version<Q>(
+        &self,
+        key: &Q,
+        expected_version: u64,
+    ) -> Result<SessionHandle, VersionConflict>
+    where
+        SessionKey: Borrow<Q>,
+        Q: ?Sized + Eq + std::hash::Hash,
+    {
+        let mut current = None;
+        let removed = self.remove_entry_if(
+            key,
+            |entry| {
+                current = Some(entry.version);
+                entry.version == expected_version
+            },
+            RemovalCause::Removed,
+        );
+        match removed {
+            Some(Some(session)) => Ok(session),
+            _ => Err(VersionConflict { current }),
+        }
+    }
+
+    /// Remove all sessions and return them
+    ///
+    /// Their eviction callbacks run once each on clones of the handles, as with [`Self::remove`].
+    pub fn drain(&self) -> Vec<(SessionKey, SessionHandle)> {
+        self.drain_entries()
+            .into_iter()
+            .map(|(key, entry)| {
+                let session = entry.into_session(&key);
+                (key, session)
+            })
+            .collect()
+    }
+
+    /// Same as [`Self::drain`] but also return the idle time each session had left, soonest to expire first, e.g. to hand all sessions over to another node
+    ///
+    /// The time left is [`Duration::MAX`] if sessions never expire.
+    pub fn take_all(&self) -> Vec<(SessionKey, SessionHandle, Duration)> {
+        let now = self.now();
+        let mut taken = self
+            .drain_entries()
+            .into_iter()
+            .map(|(key, entry)| {
+                let remaining = self.remaining(&entry, now).unwrap_or(Duration::MAX);
+                let session = entry.into_session(&key);
+                (key, session, remaining)
+            })
+            .collect::<Vec<_>>();
+        taken.sort_by_key(|(_, _, remaining)| *remaining);
+        taken
+    }
+
+    fn drain_entries(&self) -> Vec<(SessionKey, Entry<SessionKey, SessionHandle, Metadata>)> {
+        let mut key_to_session = self.write_map();
+        let drained = key_to_session.drain();
+        self.forget_aliases(drained.iter().map(|(key, entry)| (key, entry)));
+        drop(key_to_session);
+        self.report_removal(drained.iter().map(|(key, _)| key));
+        let at = self.now().instant();
+        self.publish_with(|| {
+            drained
+                .iter()
+                .map(|(key, _)| SessionEvent::Removed {
+                    key: key.clone(),
+                    at,
+                })
+                .collect()
+        });
+        drained
+    }
+
+    /// Stop the background task, then [`Self::drain`] all sessions and run `close` on each with at most `concurrency` of them at once
+    ///
+    /// A `concurrency` of zero is treated as one.
+    /// The layer stays usable afterwards, but nothing expires anymore unless [`Self::sweep`] is called manually.
+    pub async fn shutdown_with<F, Fut>(&self, concurrency: usize, close: F)
+    where
+        F: Fn(SessionKey, SessionHandle) -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        self.sweeper_stopped.store(true, Ordering::Release);
+        self.sweeper_wake.notify_one();
+        let sessions = self.drain();
+        for_each_concurrent(sessions, concurrency, |(key, session)| close(key, session)).await;
+    }
+
+    /// Replace all sessions at once and return the previous ones
+    ///
+    /// Readers see either the old set or the new set, never a mix.
+    /// The new sessions start fresh.
+    /// Handles cloned out of the old set stay valid until dropped.
+    /// The eviction callbacks of the old sessions run once each, as with [`Self::drain`].
+    pub fn swap_contents(
+        &self,
+        new: HashMap<SessionKey, SessionHandle>,
+    ) -> HashMap<SessionKey, SessionHandle> {
+        self.replace_all(new).into_iter().collect()
+    }
+
+    /// Same as [`Self::swap_contents`] but take the new sessions from any iterator, e.g. to keep only the allowlisted ones in a panic
+    ///
+    /// The new map is built before taking the write lock, so the swap itself holds it briefly.
+    /// A key given more than once keeps its last session.
+    pub fn replace_all(
+        &self,
+        entries: impl IntoIterator<Item = (SessionKey, SessionHandle)>,
+    ) -> Vec<(SessionKey, SessionHandle)> {
+        let now = self.now();
+        let mut new_map = SessionMap::new(self.key_hashing);
+        let mut replicas = vec![];
+        new_map.extend(entries.into_iter().map(|(key, session)| {
+            let key = self.normalized(key);
+            if let Some(replica) = self.replica(&session) {
+                replicas.push((key.clone(), replica));
+            }
+            let entry = self.new_entry(session, now);
+            self.schedule_expiry(&key, &entry, now);
+            (key, entry)
+        }));
+        let new_keys = self
+            .has_subscribers()
+            .then(|| new_map.keys().cloned().collect::<Vec<_>>());
+
+        let mut key_to_session = self.write_map();
+        let mut old = std::mem::replace(&mut *key_to_session, new_map);
+        self.forget_aliases(old.iter());
+        key_to_session.adopt_filter(&mut old);
+        let evicted = self.evict_overweight(&mut key_to_session, None);
+        drop(key_to_session);
+        self.sweeper_wake.notify_one();
+        self.finish_eviction(evicted);
+
+        let old = old
+            .drain()
+            .into_iter()
+            .map(|(key, entry)| {
+                let session = entry.into_session(&key);
+                (key, session)
+            })
+            .collect::<Vec<_>>();
+        self.report_removal(old.iter().map(|(key, _)| key));
+        self.replicate(|sink| {
+            for (key, replica) in &replicas {
+                sink.on_insert(key, replica);
+            }
+        });
+        self.publish_with(|| {
+            let removed = old.iter().map(|(key, _)| SessionEvent::Removed {
+                key: key.clone(),
+                at: now.instant(),
+            });
+            let inserted = new_keys
+                .into_iter()
+                .flatten()
+                .map(|key| SessionEvent::Inserted {
+                    key,
+                    at: now.instant(),
+                });
+            removed.chain(inserted).collect()
+        });
+        old
+    }
+
+    /// Move all sessions out of `other` into this layer
+    ///
+    /// Each session keeps the idle budget it had left in `other`, translated to this layer's timeout.
+    /// Aliases move along unless they are already taken here.
+    ///
+    /// Both maps are write-locked in address order, so absorbing concurrently in both directions cannot deadlock.
+    /// With [`MergePolicy::Error`], the conflicting sessions are never taken out of `other`.
+    pub fn absorb(
+        &self,
+        other: &SessionLayer<SessionKey, SessionHandle, Metadata>,
+        policy: MergePolicy,
+    ) -> Result<(), MergeConflict<SessionKey>> {
+        if std::ptr::eq(self, other) {
+            return Ok(());
+        }
+
+        let now = self.now();
+        let (mut key_to_session, mut other_map) =
+            if std::ptr::from_ref(self) < std::ptr::from_ref(other) {
+                let key_to_session = self.write_map();
+                (key_to_session, other.write_map())
+            } else {
+                let other_map = other.write_map();
+                (self.write_map(), other_map)
+            };
+        let conflicts = match policy {
+            MergePolicy::Error => other_map
+                .keys()
+                .filter(|key| key_to_session.contains_key(&self.normalized((*key).clone())))
+                .cloned()
+                .collect::<Vec<_>>(),
+            MergePolicy::KeepExisting | MergePolicy::TakeIncoming => vec![],
+        };
+        let mut incoming = other_map.extract_if(|key, _| !conflicts.contains(key));
+        other.forget_aliases(incoming.iter().map(|(key, entry)| (key, entry)));
+        drop(other_map);
+        // Keep their recency order relative to each other
+        incoming.sort_unstable_by_key(|(_, entry)| entry.seq.load(Ordering::Relaxed));
+
+        let mut events = vec![];
+        let mut moved = vec![];
+        let mut evicted = vec![];
+        let mut replicas = vec![];
+        for (key, mut entry) in incoming {
+            moved.push(key.clone());
+            let key = self.normalized(key);
+            let (other_timeout, timeout) = (other.timeout_of(&entry), self.timeout_of(&entry));
+            let last_access = entry.last_access.get_mut();
+            let remaining = other_timeout
+                .map(|timeout| timeout.saturating_sub(now.saturating_duration_since(*last_access)));
+            let idle = match (timeout, remaining) {
+                (Some(timeout), Some(remaining)) => timeout.saturating_sub(remaining),
+                (Some(_), None) => Duration::ZERO,
+                (None, _) => now.saturating_duration_since(*last_access),
+            };
+            *last_access = now.checked_sub(idle).unwrap_or(now);
+            *entry.seq.get_mut() = self.next_seq();
+            entry.version = *entry.seq.get_mut();
+            entry.weight = self.weigh(&entry.session);
+
+            let event = match (key_to_session.contains_key(&key), policy) {
+                (false, _) => SessionEvent::Inserted {
+                    key: key.clone(),
+                    at: now.instant(),
+                },
+                (true, MergePolicy::KeepExisting) => {
+                    evicted.push((key, entry));
+                    continue;
+                }
+                (true, MergePolicy::TakeIncoming) => SessionEvent::Replaced {
+                    key: key.clone(),
+                    at: now.instant(),
+                },
+                // Conflicts under `MergePolicy::Error` never left `other`
+                (true, MergePolicy::Error) => continue,
+            };
+            if let Some(replica) = self.replica(&entry.session) {
+                replicas.push((key.clone(), replica));
+            }
+            self.schedule_expiry(&key, &entry, now);
+            // Aliases taken in this layer stay with their current sessions
+            let mut aliases = std::mem::take(&mut entry.aliases);
+            aliases.retain(|alias| {
+                !key_to_session.contains_key(alias) && !self.is_alias(&key_to_session, alias)
+            });
+            self.register_aliases(&aliases, &key);
+            entry.aliases = aliases;
+            if let Some(old) = key_to_session.insert(key, entry) {
+                self.forget_aliases(std::iter::once((event.key(), &old)));
+                evicted.push((event.key().clone(), old));
+            }
+            events.push(event);
+        }
+        let overweight = self.evict_overweight(&mut key_to_session, None);
+        drop(key_to_session);
+        self.sweeper_wake.notify_one();
+        self.finish_eviction(overweight);
+        other.report_removal(moved.iter());
+        other.publish_with(|| {
+            moved
+                .into_iter()
+                .map(|key| SessionEvent::Removed {
+                    key,
+                    at: now.instant(),
+                })
+                .collect()
+        });
+        self.replicate(|sink| {
+            for (key, replica) in &replicas {
+                sink.on_insert(key, replica);
+            }
+        });
+        self.publish_with(|| events);
+        for (key, entry) in evicted {
+            entry.evict(key);
+        }
+
+        if conflicts.is_empty() {
+            return Ok(());
+        }
+        Err(MergeConflict(conflicts))
+    }
+
+    /// Move the session to another key
+    ///
+    /// The session keeps its last access time and its age since it is still the same session.
+    pub fn rekey<Q>(&self, from: &Q, to: SessionKey) -> Result<(), RekeyError>
+    where
+        SessionKey: Borrow<Q>,
+        Q: ?Sized + Eq + std::hash::Hash,
+    {
+        let to = self.normalized(to);
+        let mut key_to_session = self.write_map();
+        if key_to_session.contains_key::<SessionKey>(&to)
+            || self.is_alias::<SessionKey>(&key_to_session, &to)
+        {
+            return Err(RekeyError::Collision);
+        }
+        let (from, entry) = key_to_session
+            .remove_entry(from)
+            .ok_or(RekeyError::NotFound)?;
+        self.register_aliases(&entry.aliases, &to);
+        let replica = self.replica(&entry.session);
+        self.schedule_expiry(&to, &entry, self.now());
+        key_to_session.insert(to.clone(), entry);
+        drop(key_to_session);
+
+        if let Some(replica) = replica {
+            self.replicate(|sink| {
+                sink.on_remove(&from, RemovalCause::Rekeyed);
+                sink.on_insert(&to, &replica);
+            });
+        }
+        self.publish_with(|| {
+            vec![SessionEvent::Rekeyed {
+                from,
+                key: to,
+                at: self.now().instant(),
+            }]
+        });
+        Ok(())
+    }
+
+    /// How long ago the session was created regardless of when it was last accessed
+    pub fn age<Q>(&self, key: &Q) -> Option<Duration>
+    where
+        SessionKey: Borrow<Q>,
+        Q: ?Sized + Eq + std::hash::Hash,
+    {
+        let key_to_session = self.key_to_session.read();
+        let (_, entry) = self.resolve(&key_to_session, key)?;
+        Some(self.now().saturating_duration_since(entry.created_at))
+    }
+
+    /// How long the session has gone without an access, without refreshing it
+    pub fn idle_time<Q>(&self, key: &Q) -> Option<Duration>
+    where
+        SessionKey: Borrow<Q>,
+        Q: ?Sized + Eq + std::hash::Hash,
+    {
+        let key_to_session = self.key_to_session.read();
+        let (_, entry) = self.resolve(&key_to_session, key)?;
+        Some(entry.idle(self.now()))
+    }
+}
+impl<SessionKey, SessionHandle, Metadata> SessionLayer<SessionKey, SessionHandle, Metadata>
+where
+    SessionKey: std::fmt::Debug + std::hash::Hash + Eq + Clone + Sync + Send + 'static,
+    SessionHandle: std::fmt::Debug + Clone + Sync + Send + 'static,
+    Metadata: Default + Sync + Send + 'static,
+{
+    /// Clone out the session handle
+    ///
+    /// A session reported dead by the liveness check is removed instead.
+    pub fn get<Q>(&self, key: &Q) -> Option<SessionHandle>
+    where
+        SessionKey: Borrow<Q>,
+        Q: ?Sized + Eq + std::hash::Hash,
+    {
+        self.get_versioned(key).map(|(session, _)| session)
+    }
+
+    /// Same as [`Self::get`] but also return the version of the handle for [`Self::replace_if_version`] and [`Self::remove_if_version`]
+    pub fn get_versioned<Q>(&self, key: &Q) -> Option<(SessionHandle, u64)>
+    where
+        SessionKey: Borrow<Q>,
+        Q: ?Sized + Eq + std::hash::Hash,
+    {
+        self.get_entry(key, |_, entry, _| (entry.session.clone(), entry.version))
+    }
+
+    /// Same as [`Self::get`] but also clone out the metadata of the session
+    pub fn get_with_metadata<Q>(&self, key: &Q) -> Option<(SessionHandle, Metadata)>
+    where
+        SessionKey: Borrow<Q>,
+        Q: ?Sized + Eq + std::hash::Hash,
+        Metadata: Clone,
+    {
+        self.get_entry(key, |_, entry, _| {
+            (entry.session.clone(), entry.metadata.clone())
+        })
+    }
+
+    /// Same as [`Self::get`] but also return how long the session had been idle before this access, e.g. to bill for silent gaps
+    ///
+    /// The idle time is read and reset together, so concurrent accesses never both count the same gap.
+    pub fn get_and_idle<Q>(&self, key: &Q) -> Option<(SessionHandle, Duration)>
+    where
+        SessionKey: Borrow<Q>,
+        Q: ?Sized + Eq + std::hash::Hash,
+    {
+        self.get_entry(key, |_, entry, idle| (entry.session.clone(), idle))
+    }
+
+    /// Same as [`Self::get`] but also return the time left before the session expires, e.g. for a keepalive endpoint to report
+    ///
+    /// The time left is that of the refresh just applied, so it is the full timeout of the class of the session unless the lifetime declared by the handle runs out first, or [`Duration::MAX`] if it never expires.
+    pub fn refresh<Q>(&self, key: &Q) -> Option<(SessionHandle, Duration)>
+    where
+        SessionKey: Borrow<Q>,
+        Q: ?Sized + Eq + std::hash::Hash,
+    {
+        self.get_entry(key, |_, entry, _| {
+            let ttl = self.remaining(entry, self.now()).unwrap_or(Duration::MAX);
+            (entry.session.clone(), ttl)
+        })
+    }
+
+    /// Same as [`Self::get`] but also clone out the stored key, e.g. to own it given only a borrowed form
+    pub fn get_key_value<Q>(&self, key: &Q) -> Option<(SessionKey, SessionHandle)>
+    where
+        SessionKey: Borrow<Q>,
+        Q: ?Sized + Eq + std::hash::Hash,
+    {
+        self.get_entry(key, |stored_key, entry, _| {
+            (stored_key.clone(), entry.session.clone())
+        })
+    }
+
+    /// Same as [`Self::get`] but tell a key that was valid recently from one that never was, e.g. to ask the client to handshake again instead of flagging an attack
+    ///
+    /// Keys are remembered by hash, so a bogus key colliding with a recently removed one is rarely taken for it.
+    pub fn lookup<Q>(&self, key: &Q) -> Lookup<SessionHandle>
+    where
+        SessionKey: Borrow<Q>,
+        Q: ?Sized + Eq + std::hash::Hash,
+    {
+        match self.get(key) {
+            Some(session) => Lookup::Found(session),
+            None => self.missing(key),
+        }
+    }
+
+    /// Same as [`Self::get`] but normalize the key first, see [`SessionLayerBuilder::normalize_key`]
+    pub fn get_normalized(&self, key: &SessionKey) -> Option<SessionHandle> {
+        self.get(&*self.normalized_ref(key))
+    }
+
+    /// Clone out the session handle without refreshing it
+    pub fn peek<Q>(&self, key: &Q) -> Option<SessionHandle>
+    where
+        SessionKey: Borrow<Q>,
+        Q: ?Sized + Eq + std::hash::Hash,
+    {
+        let key_to_session = self.key_to_session.read();
+        let (_, entry) = self.resolve(&key_to_session, key)?;
+        if !self.is_alive(&entry.session) || self.is_stale(entry, self.now()) {
+            return None;
+        }
+        Some(entry.session.clone())
+    }
+
+    /// Same as [`Self::peek`] but also clone out the stored key
+    pub fn peek_key_value<Q>(&self, key: &Q) -> Option<(SessionKey, SessionHandle)>
+    where
+        SessionKey: Borrow<Q>,
+        Q: ?Sized + Eq + std::hash::Hash,
+    {
+        let key_to_session = self.key_to_session.read();
+        let (stored_key, entry) = self.resolve(&key_to_session, key)?;
+        if !self.is_alive(&entry.session) || self.is_stale(entry, self.now()) {
+            return None;
+        }
+        Some((stored_key.clone(), entry.session.clone()))
+    }
+
+    /// Clone out all sessions without refreshing them
+    pub(crate) fn entries(&self) -> Vec<(SessionKey, SessionHandle)> {
+        let key_to_session = self.key_to_session.read();
+        key_to_session
+            .iter()
+            .map(|(key, entry)| (key.clone(), entry.session.clone()))
+            .collect()
+    }
+
+    /// Clone out the handles that `pred` matches without refreshing any session, e.g. to broadcast a drain notice to the sessions of one backend
+    ///
+    /// Visits every session under the read lock, which writers wait for, so it costs O(n) and `pred` must be cheap and must not call back into the layer.
+    /// Only the matches are cloned, and sessions that [`Self::peek`] would miss are skipped.
+    pub fn collect_filtered(
+        &self,
+        pred: impl Fn(&SessionKey, &SessionHandle) -> bool,
+    ) -> Vec<SessionHandle> {
+        self.filter_entries(pred, |_, session| session.clone())
+    }
+
+    /// Same as [`Self::collect_filtered`] but also clone out the keys
+    pub fn collect_filtered_key_values(
+        &self,
+        pred: impl Fn(&SessionKey, &SessionHandle) -> bool,
+    ) -> Vec<(SessionKey, SessionHandle)> {
+        self.filter_entries(pred, |key, session| (key.clone(), session.clone()))
+    }
+
+    /// Format up to `max_entries` keys and handles, for when the summary printed by [`Debug`] is not enough
+    ///
+    /// Keys and handles may be secrets, so keep the dump out of regular logs.
+    /// Formats under the read lock, which writers wait for, so keep `max_entries` small.
+    pub fn debug_dump(&self, max_entries: usize) -> String
+    where
+        SessionKey: std::fmt::Debug,
+        SessionHandle: std::fmt::Debug,
+    {
+        use std::fmt::Write;
+
+        let key_to_session = self.key_to_session.read();
+        let mut dump = String::from("{");
+        let mut sep = "";
+        for (key, entry) in key_to_session.iter().take(max_entries) {
+            let _ = write!(dump, "{sep} {key:?}: {:?}", entry.session);
+            sep = ",";
+        }
+        let rest = key_to_session.len().saturating_sub(max_entries);
+        if rest != 0 {
+            let _ = write!(dump, "{sep} .. {rest} more");
+        }
+        dump.push_str(" }");
+        dump
+    }
+
+    fn filter_entries<R>(
+        &self,
+        pred: impl Fn(&SessionKey, &SessionHandle) -> bool,
+        f: impl Fn(&SessionKey, &SessionHandle) -> R,
+    ) -> Vec<R> {
+        let now = self.now();
+        let key_to_session = self.key_to_session.read();
+        key_to_session
+            .iter()
+            .filter(|(key, entry)| {
+                self.is_alive(&entry.session)
+                    && !self.is_stale(entry, now)
+                    && pred(key, &entry.session)
+            })
+            .map(|(key, entry)| f(key, &entry.session))
+            .collect()
+    }
+
+    /// Sessions whose remaining time before the timeout or the lifetime declared by their handle has fallen below `window`
+    ///
+    /// Empty if sessions never expire.
+    pub fn expiring_within(&self, window: Duration) -> Vec<(SessionKey, SessionHandle)> {
+        if self.shortest_timeout.is_none() && self.handle_expiry.is_none() {
+            return vec![];
+        }
+        let now = self.now();
+        let key_to_session = self.key_to_session.read();
+        key_to_session
+            .iter()
+            .filter(|(_, entry)| {
+                self.remaining(entry, now)
+                    .is_some_and(|remaining| !remaining.is_zero() && remaining < window)
+            })
+            .map(|(key, entry)| (key.clone(), entry.session.clone()))
+            .collect()
+    }
+
+    /// Hand out access that can only read sessions
+    pub fn read_only(self: &Arc<Self>) -> ReadOnlyView<SessionKey, SessionHandle, Metadata> {
+        ReadOnlyView::new(Arc::clone(self))
+    }
+
+    /// Same as [`Self::insert`] but also return a clone of the session now under the key, saving the lookup of a following [`Self::get`]
+    ///
+    /// Under [`CollisionPolicy::KeepExisting`], that is the existing session on a collision.
+    pub fn insert_and_get(
+        &self,
+        key: SessionKey,
+        session: SessionHandle,
+    ) -> Result<SessionHandle, InsertError<SessionHandle>> {
+        self.insert_entry(key, session, EntryOptions::default(), SessionHandle::clone)
+    }
+
+    /// Same as [`Self::insert`] but also run `on_evict` once the session leaves the layer
+    ///
+    /// `on_evict` receives the key and the handle when the session expires, is found dead, is removed or replaced, or when the layer is dropped, whichever comes first.
+    /// It runs outside the lock and at most once.
+    /// It does not run if the session is handed back by [`Self::swap_contents`], and it moves along with the session absorbed by another layer.
+    pub fn insert_with_on_evict(
+        &self,
+        key: SessionKey,
+        session: SessionHandle,
+        on_evict: Box<EvictHook<SessionKey, SessionHandle>>,
+    ) -> Result<(), InsertError<SessionHandle>> {
+        let options = EntryOptions {
+            on_evict: Some(OnEvict {
+                hook: Mutex::new(Hook::new(on_evict)),
+                clone_session: SessionHandle::clone,
+                queue: self
+                    .eviction_queue
+                    .clone()
+                    .map(|queue| (queue, enqueue_eviction as EnqueueEviction<_, _>)),
+            }),
+            ..Default::default()
+        };
+        self.insert_entry(key, session, options, |_| ())
+    }
+
+    /// Clone out a page of sessions, starting from `cursor`, without refreshing them
+    ///
+    /// Returns the next cursor, or [`None`] if the scan is complete.
+    ///
+    /// Sessions are visited in the order of a hash of their keys, so a session present for the whole scan is returned exactly once no matter how the map changes between pages.
+    /// Sessions inserted or removed during the scan may or may not be returned.
+    ///
+    /// Each page holds the read lock for O(`limit` + log n) and returns up to `limit` sessions, plus any sharing the last key hash of the page.
+    /// The first scan of the layer indexes the keys by their hash under the write lock, and the index then costs a clone of every key inserted.
+    pub fn scan(
+        &self,
+        cursor: ScanCursor,
+        limit: usize,
+    ) -> (Vec<(SessionKey, SessionHandle)>, Option<ScanCursor>) {
+        let limit = limit.max(1);
+        loop {
+            let key_to_session = self.key_to_session.read();
+            // The map may have been swapped for a new one since it was indexed
+            let Some(mut hashes) = key_to_session.scan_after(cursor.0) else {
+                drop(key_to_session);
+                self.key_to_session
+                    .write()
+                    .index_for_scan(&self.scan_hasher);
+                continue;
+            };
+            let mut page = vec![];
+            let mut last = None;
+            for (hash, keys) in hashes.by_ref() {
+                page.extend(keys.iter().filter_map(|key| {
+                    let entry = key_to_session.get(key)?;
+                    Some((key.clone(), entry.session.clone()))
+                }));
+                last = Some(hash);
+                if limit <= page.len() {
+                    break;
+                }
+            }
+            let more = hashes.next().is_some();
+            let next = last.filter(|_| more).map(|last| ScanCursor(Some(last)));
+            return (page, next);
+        }
+    }
+
+    /// Yield all sessions chunk by chunk without materializing them at once, see [`SessionStream`]
+    #[cfg(feature = "stream")]
+    pub fn stream(
+        &self,
+    ) -> SessionStream<'_, SessionKey, SessionHandle, Metadata, (SessionKey, SessionHandle)> {
+        SessionStream::new(self, |key, session| (key, session))
+    }
+
+    /// Clone out the session handle or insert a new one made by `make`
+    ///
+    /// After an insertion the write lock is downgraded to a read lock, so readers are only blocked by the insertion itself and not while the new handle is cloned out.
+    /// This matters for handles that are costly to clone: in `benches/contention.rs`, with clones spinning for 20µs, a read racing the insertions took about 630ns on average instead of about 740ns without the downgrade.
+    /// This never fails, so the admission callback is not consulted; see [`Self::try_get_or_insert_with`].
+    pub fn get_or_insert_with<F: FnOnce() -> SessionHandle>(
+        &self,
+        key: SessionKey,
+        make: F,
+    ) -> SessionHandle {
+        self.get_or_insert_with_status(key, make).0
+    }
+
+    /// Same as [`Self::get_or_insert_with`] but also tell if the session was just created
+    pub fn get_or_insert_with_status<F: FnOnce() -> SessionHandle>(
+        &self,
+        key: SessionKey,
+        make: F,
+    ) -> (SessionHandle, bool) {
+        let make = || Ok::<_, Infallible>(make());
+        match self.get_or_try_insert_with_status(key, make, None::<fn(&SessionKey, _) -> _>, None) {
+            Ok(res) => res,
+        }
+    }
+
+    /// Same as [`Self::get_or_insert_with`] but fail with [`InsertError::Denied`] if the admission callback denies the new session, see [`SessionLayerBuilder::admission`]
+    ///
+    /// A key held by [`Self::reserve`] fails with [`InsertError::Collision`] instead of being taken.
+    /// `make` runs under the write lock, while the admission callback runs outside of it, so the key is looked up again afterwards and a session inserted in between is returned instead.
+    pub fn try_get_or_insert_with<F: FnOnce() -> SessionHandle>(
+        &self,
+        key: SessionKey,
+        make: F,
+    ) -> Result<SessionHandle, InsertError<SessionHandle>> {
+        self.try_get_or_insert_with_status(key, make)
+            .map(|(session, _)| session)
+    }
+
+    /// Same as [`Self::try_get_or_insert_with`] but also tell if the session was just created
+    pub fn try_get_or_insert_with_status<F: FnOnce() -> SessionHandle>(
+        &self,
+        key: SessionKey,
+        make: F,
+    ) -> Result<(SessionHandle, bool), InsertError<SessionHandle>> {
+        self.get_or_try_insert_with_status(
+            key,
+            || Ok(make()),
+            Some(|key: &SessionKey, session| self.admit(key, session)),
+            Some(InsertError::Collision),
+        )
+    }
+
+    /// Clone out the session handle or insert a new one made by `make`
+    ///
+    /// Nothing is inserted if `make` fails.
+    /// `make` already has the final say, so the admission callback is not consulted.
+    pub fn get_or_try_insert_with<E>(
+        &self,
+        key: SessionKey,
+        make: impl FnOnce() -> Result<SessionHandle, E>,
+    ) -> Result<SessionHandle, E> {
+        self.get_or_try_insert_with_status(key, make, None::<fn(&SessionKey, _) -> _>, None)
+            .map(|(session, _)| session)
+    }
+
+    /// Refresh and clone out the session under `key` or the alias `key` if it is usable, or else tell if a dead or stale one is in the way
+    fn touch_live(
+        &self,
+        key_to_session: &EntryMap<SessionKey, SessionHandle, Metadata>,
+        key: &SessionKey,
+        now: Timestamp,
+    ) -> Result<SessionHandle, bool> {
+        let Some((_, entry)) = self.resolve(key_to_session, key) else {
+            return Err(false);
+        };
+        if !self.is_alive(&entry.session) || self.is_stale(entry, now) {
+            return Err(true);
+        }
+        entry.touch(now, self.next_seq());
+        Ok(entry.session.clone())
+    }
+
+    /// Must not be called with the map locked.
+    fn report_access(&self, key: SessionKey, now: Timestamp) {
+        self.replicate(|sink| sink.on_touch(&key));
+        if self.access_events {
+            self.publish_with(|| {
+                vec![SessionEvent::Accessed {
+                    key,
+                    at: now.instant(),
+                }]
+            });
+        }
+    }
+
+    /// `admit` vets the new session outside the write lock if given, and `collide` hands it back if the key is reserved, see [`Self::reserve`]
+    ///
+    /// Reservations are ignored without `collide`.
+    fn get_or_try_insert_with_status<E>(
+        &self,
+        key: SessionKey,
+        make: impl FnOnce() -> Result<SessionHandle, E>,
+        admit: Option<impl FnOnce(&SessionKey, SessionHandle) -> Result<SessionHandle, E>>,
+        collide: Option<fn(SessionHandle) -> E>,
+    ) -> Result<(SessionHandle, bool), E> {
+        let key = self.normalized(key);
+        let mut key_to_session = self.write_map();
+        let mut now = self.now();
+        let mut dead = match self.touch_live(&key_to_session, &key, now) {
+            Ok(session) => {
+                drop(key_to_session);
+                self.report_access(key, now);
+                return Ok((session, false));
+            }
+            Err(dead) => dead,
+        };
+
+        // A dead or stale session is replaced as if it were missing
+        let mut session = make()?;
+        if let (Some(admit), Some(_)) = (admit, &self.admission) {
+            drop(key_to_session);
+            session = admit(&key, session)?;
+            // Another caller may have inserted in between
+            key_to_session = self.write_map();
+            now = self.now();
+            dead = match self.touch_live(&key_to_session, &key, now) {
+                Ok(session) => {
+                    drop(key_to_session);
+                    self.report_access(key, now);
+                    return Ok((session, false));
+                }
+                Err(dead) => dead,
+            };
+        }
+        if let Some(collide) = collide {
+            if self.reservations.is_claimed_by_other(&key, None, now) {
+                drop(key_to_session);
+                self.audit_collision(&key);
+                return Err(collide(session));
+            }
+        }
+        let entry = self.new_entry(session, now);
+        self.schedule_expiry(&key, &entry, now);
+        // Also takes the aliases of the session out if `key` is one of them
+        let old = match dead {
+            true => self.remove_resolved(&mut key_to_session, &key),
+            false => None,
+        };
+        key_to_session.insert(key.clone(), entry);
+        let evicted = self.evict_overweight(&mut key_to_session, Some(&key));
+        // Readers may proceed while the handle is cloned out
+        let key_to_session = key_to_session.downgrade();
+        let session = key_to_session
+            .get(&key)
+            .expect("just inserted under the same lock")
+            .session
+            .clone();
+        drop(key_to_session);
+        self.sweeper_wake.notify_one();
+        self.finish_eviction(evicted);
+        if let Some((old_key, old)) = old {
+            old.evict(old_key);
+        }
+
+        self.replicate(|sink| sink.on_insert(&key, &session));
+        self.publish_with(|| {
+            vec![match dead {
+                true => SessionEvent::Replaced {
+                    key,
+                    at: now.instant(),
+                },
+                false => SessionEvent::Inserted {
+                    key,
+                    at: now.instant(),
+                },
+            }]
+        });
+        Ok((session, true))
+    }
+}
+impl<SessionKey, SessionHandle, Metadata> SessionLayer<SessionKey, SessionHandle, Metadata>
+where
+    SessionKey: std::hash::Hash + Eq,
+    SessionHandle: HasStrongCount,
+{
+    /// How many clones of the handle exist beyond the one kept in the layer
+    ///
+    /// A count that stays positive long after requests finish hints at handles escaping their intended lifetime.
+    pub fn outstanding_clones<Q>(&self, key: &Q) -> Option<usize>
+    where
+        SessionKey: Borrow<Q>,
+        Q: ?Sized + Eq + std::hash::Hash,
+    {
+        let key_to_session = self.key_to_session.read();
+        let entry = key_to_session.get(key)?;
+        Some(entry.session.strong_count().saturating_sub(1))
+    }
+}
+
+/// Handles that know their own lifetime, e.g. a token with an `exp` claim or a lease granted for a duration, see [`SessionLayerBuilder::respect_handle_expiry`]
+pub trait Expiry {
+    /// How long from now the session stays valid, or [`None`] if only the timeout applies
+    fn expires_in(&self) -> Option<Duration>;
+}
+
+/// Handles that can tell how many clones of them exist, for [`SessionLayer::outstanding_clones`]
+pub trait HasStrongCount {
+    fn strong_count(&self) -> usize;
+}
+impl<T: ?Sized> HasStrongCount for Arc<T> {
+    fn strong_count(&self) -> usize {
+        Arc::strong_count(self)
+    }
+}
+
+#[derive(Debug, Clone, thiserror::Error)]
+#[error("session collision: {0}")]
+pub struct SessionCollision<SessionHandle: std::fmt::Debug>(pub SessionHandle);
+
+/// Why [`SessionLayer::insert`] and its variants failed, handing the session back
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum InsertError<SessionHandle: std::fmt::Debug> {
+    #[error("session collision: {0}")]
+    Collision(SessionHandle),
+    #[error("{denied}")]
+    Denied {
+        session: SessionHandle,
+        denied: AdmissionDenied,
+    },
+    /// See [`SessionLayerBuilder::insert_rate_limit`]
+    #[error("insert rate limited")]
+    RateLimited(SessionHandle),
+}
+impl<SessionHandle: std::fmt::Debug> InsertError<SessionHandle> {
+    pub fn into_session(self) -> SessionHandle {
+        match self {
+            Self::Collision(session)
+            | Self::Denied { session, .. }
+            | Self::RateLimited(session) => session,
+        }
+    }
+}
+
+/// Returned by the admission callback to keep a session out, see [`SessionLayerBuilder::admission`]
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("admission denied: {reason}")]
+pub struct AdmissionDenied {
+    pub reason: String,
+}
+impl AdmissionDenied {
+    pub fn new(reason: impl Into<String>) -> Self {
+        Self {
+            reason: reason.into(),
+        }
+    }
+}
+
+/// Where [`SessionLayer::scan`] resumes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ScanCursor(Option<u64>);
+impl ScanCursor {
+    /// Scan from the beginning
+    pub fn start() -> Self {
+        Self(None)
+    }
+}
+
+/// The outcome of [`SessionLayer::lookup`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Lookup<SessionHandle> {
+    Found(SessionHandle),
+    /// The key was valid but expired, was found dead, or was removed `since` ago
+    ///
+    /// Only reported within the retention set by [`SessionLayerBuilder::tombstones`].
+    Expired {
+        since: Duration,
+    },
+    /// The key was never valid or was removed too long ago to remember
+    Unknown,
+}
+
+/// How [`SessionLayer::insert`] and its variants resolve a key that is already taken
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CollisionPolicy {
+    /// Fail with [`InsertError::Collision`]
+    #[default]
+    Reject,
+    /// First write wins: keep the existing session and drop the new one
+    KeepExisting,
+    /// Last write wins: replace the existing session as [`SessionLayer::insert_or_replace`] does
+    Overwrite,
+}
+
+/// A named idle timeout for sessions that share a lifecycle stage, e.g. a short one for handshakes and a long one for established connections
+///
+/// Defined by [`SessionLayerBuilder::timeout_class`] and picked by [`SessionLayer::insert_in_class`] and [`SessionLayer::promote`].
+/// A class that was never defined follows [`Self::DEFAULT`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct TimeoutClass(u8);
+impl TimeoutClass {
+    /// The timeout the builder was created with
+    pub const DEFAULT: Self = Self(0);
+
+    pub const fn new(index: u8) -> Self {
+        Self(index)
+    }
+}
+
+/// How lookups treat a session idle past the timeout that no sweep has removed yet, see [`SessionLayerBuilder::resurrect_policy`]
+///
+/// Sweeps run only every half timeout, so such a session may linger for up to half a timeout.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ResurrectPolicy {
+    /// Treat the session as expired: lookups miss it, and those that would refresh it remove it instead
+    ///
+    /// No session idle for the timeout is ever handed out.
+    Strict,
+    /// Treat the session as still alive until swept: lookups return it, and those that refresh it save it from the next sweep
+    ///
+    /// Saves the sessions of clients that come back just late, at the cost of a timeout that is only approximate.
+    #[default]
+    Lenient,
+}
+
+/// How [`SessionLayer::absorb`] resolves a key present in both layers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MergePolicy {
+    KeepExisting,
+    TakeIncoming,
+    /// Leave the conflicting sessions in the other layer and report their keys
+    Error,
+}
+
+#[derive(Debug, Clone, thiserror::Error)]
+#[error("merge conflicts on keys: {0:?}")]
+pub struct MergeConflict<SessionKey: std::fmt::Debug>(pub Vec<SessionKey>);
+
+/// The handle changed since it was read
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+#[error("version conflict, now at {current:?}")]
+pub struct VersionConflict {
+    /// [`None`] if the session is gone
+    pub current: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum AliasError {
+    #[error("no session under the existing key")]
+    NotFound,
+    #[error("another session already under the alias")]
+    Collision,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum RekeyError {
+    #[error("no session under the old key")]
+    NotFound,
+    #[error("another session already under the new key")]
+    Collision,
+}
+
+/// Leaves out keys and handles, which may be secrets, and never waits for the lock, e.g. when formatted in a panic with the map locked
+///
+/// The output stays small however many sessions there are; see [`SessionLayer::debug_dump`] for the contents.
+impl<SessionKey, SessionHandle, Metadata> std::fmt::Debug
+    for SessionLayer<SessionKey, SessionHandle, Metadata>
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut debug = f.debug_struct("SessionLayer");
+        debug
+            .field(
+                "key",
+                &format_args!("{}", std::any::type_name::<SessionKey>()),
+            )
+            .field(
+                "handle",
+                &format_args!("{}", std::any::type_name::<SessionHandle>()),
+            );
+        match self.key_to_session.try_read() {
+            Some(key_to_session) => debug.field("len", &key_to_session.len()),
+            None => debug.field("len", &format_args!("locked")),
+        };
+        debug
+            .field("timeout", &self.timeout)
+            .field("shortest_timeout", &self.shortest_timeout)
+            .field("collision_policy", &self.collision_policy)
+            .field("resurrect_policy", &self.resurrect_policy)
+            .field(
+                "sweeper_stopped",
+                &self.sweeper_stopped.load(Ordering::Relaxed),
+            )
+            .finish_non_exhaustive()
+    }
+}
+impl<SessionKey, SessionHandle, Metadata> ScheduledSweep
+    for SessionLayer<SessionKey, SessionHandle, Metadata>
+where
+    SessionKey: Eq + std::hash::Hash + Clone + Sync + Send + 'static,
+    SessionHandle: Sync + Send + 'static,
+    Metadata: Sync + Send + 'static,
+{
+    fn background_sweep(&self, interval: Duration) -> Option<bool> {
+        SessionLayer::background_sweep(self, interval)
+    }
+
+    fn take_sweep_request(&self) -> bool {
+        self.sweep_requested.swap(false, Ordering::Relaxed)
+    }
+}
+
+impl<SessionKey, SessionHandle, Metadata> Drop
+    for SessionLayer<SessionKey, SessionHandle, Metadata>
+{
+    fn drop(&mut self) {
+        // Let a parked background task see that the layer is gone
+        self.sweeper_wake.notify_one();
+        for (key, entry) in self.key_to_session.write().drain() {
+            entry.evict(key);
+        }
+    }
+}
+
+type EntryMap<SessionKey, SessionHandle, Metadata> =
+    SessionMap<SessionKey, Entry<SessionKey, SessionHandle, Metadata>>;
+
+/// Write access to the map that refreshes the length counter on release
+struct MapWriteGuard<'a, SessionKey, SessionHandle, Metadata> {
+    /// Only taken out by [`Self::downgrade`]
+    map: Option<RwLockWriteGuard<'a, EntryMap<SessionKey, SessionHandle, Metadata>>>,
+    len: &'a AtomicUsize,
+    len_watch: &'a watch::Sender<usize>,
+    occupancy: &'a Occupancy,
+}
+impl<'a, SessionKey, SessionHandle, Metadata>
+    MapWriteGuard<'a, SessionKey, SessionHandle, Metadata>
+{
+    /// Keep reading the map without letting any writer in between
+    fn downgrade(mut self) -> RwLockReadGuard<'a, EntryMap<SessionKey, SessionHandle, Metadata>> {
+        self.publish_len();
+        let map = self.map.take().expect("not downgraded yet");
+        RwLockWriteGuard::downgrade(map)
+    }
+
+    /// Called once per guard, so a batch of writes wakes the watchers once
+    fn publish_len(&self) {
+        let map = &**self;
+        let len = map.len();
+        self.len.store(len, Ordering::Relaxed);
+        self.occupancy.observe(len, map.total_weight());
+        self.len_watch.send_if_modified(|watched| {
+            let modified = *watched != len;
+            *watched = len;
+            modified
+        });
+    }
+}
+impl<SessionKey, SessionHandle, Metadata> Deref
+    for MapWriteGuard<'_, SessionKey, SessionHandle, Metadata>
+{
+    type Target = SessionMap<SessionKey, Entry<SessionKey, SessionHandle, Metadata>>;
+
+    fn deref(&self) -> &Self::Target {
+        self.map.as_ref().expect("not downgraded yet")
+    }
+}
+impl<SessionKey, SessionHandle, Metadata> DerefMut
+    for MapWriteGuard<'_, SessionKey, SessionHandle, Metadata>
+{
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.map.as_mut().expect("not downgraded yet")
+    }
+}
+impl<SessionKey, SessionHandle, Metadata> Drop
+    for MapWriteGuard<'_, SessionKey, SessionHandle, Metadata>
+{
+    fn drop(&mut self) {
+        if self.map.is_some() {
+            self.publish_len();
+        }
+    }
+}
+
+pub(crate) type EvictHook<SessionKey, SessionHandle> = dyn FnOnce(SessionKey, SessionHandle) + Send;
+
+impl<SessionKey, SessionHandle, Metadata> Weighted for Entry<SessionKey, SessionHandle, Metadata> {
+    fn weight(&self) -> usize {
+        self.weight
+    }
+}
+
+/// What [`SessionLayer::insert_entry`] sets up besides the handle
+struct EntryOptions<SessionKey, SessionHandle, Metadata> {
+    on_evict: Option<OnEvict<SessionKey, SessionHandle>>,
+    /// Now if [`None`]
+    last_access: Option<Instant>,
+    metadata: Metadata,
+    /// The reservation being fulfilled, which is not a collision
+    reservation: Option<u64>,
+    class: TimeoutClass,
+    /// See [`SessionLayer::insert_aliases`]
+    aliases: Vec<SessionKey>,
+}
+impl<SessionKey, SessionHandle, Metadata: Default> Default
+    for EntryOptions<SessionKey, SessionHandle, Metadata>
+{
+    fn default() -> Self {
+        Self {
+            on_evict: None,
+            last_access: None,
+            metadata: Metadata::default(),
+            reservation: None,
+            class: TimeoutClass::DEFAULT,
+            aliases: vec![],
+        }
+    }
+}
+
+/// See [`SweeperStatus`]
+#[derive(Debug, Clone, Copy, Default)]
+struct SweepTiming {
+    started: Option<Instant>,
+    finished: Option<Instant>,
+    completed: u64,
+}
+
+/// Counts a sweep loop as alive until dropped
+#[derive(Debug)]
+pub(crate) struct SweepLoop(Arc<AtomicUsize>);
+impl SweepLoop {
+    fn enter(sweep_loops: &Arc<AtomicUsize>) -> Self {
+        sweep_loops.fetch_add(1, Ordering::AcqRel);
+        Self(Arc::clone(sweep_loops))
+    }
+}
+impl Drop for SweepLoop {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// An eviction callback along with a way to clone the handle for it when the handle is also handed back
+///
+/// Only handles that can be cloned get a callback, which leaves the rest of the layer usable with unique handles.
+#[derive(Debug)]
+struct OnEvict<SessionKey, SessionHandle> {
+    /// Locked only to make the entry [`Sync`]
+    hook: Mutex<Hook<EvictHook<SessionKey, SessionHandle>>>,
+    clone_session: fn(&SessionHandle) -> SessionHandle,
+    /// Runs the hook inline if [`None`]
+    ///
+    /// Along with a way to send the hook to the queue, captured where the key and the handle are known to be [`Send`].
+    queue: Option<(
+        Arc<EvictionQueue>,
+        EnqueueEviction<SessionKey, SessionHandle>,
+    )>,
+}
+impl<SessionKey, SessionHandle> OnEvict<SessionKey, SessionHandle> {
+    fn run(self, key: SessionKey, session: SessionHandle) {
+        let hook = self.hook.into_inner().into_inner();
+        match self.queue {
+            Some((queue, enqueue)) => enqueue(&queue, hook, key, session),
+            None => hook(key, session),
+        }
+    }
+}
+type EnqueueEviction<SessionKey, SessionHandle> =
+    fn(&EvictionQueue, Box<EvictHook<SessionKey, SessionHandle>>, SessionKey, SessionHandle);
+fn enqueue_eviction<SessionKey, SessionHandle>(
+    queue: &EvictionQueue,
+    hook: Box<EvictHook<SessionKey, SessionHandle>>,
+    key: SessionKey,
+    session: SessionHandle,
+) where
+    SessionKey: Send + 'static,
+    SessionHandle: Send + 'static,
+{
+    queue.run(Box::new(move || hook(key, session)));
+}
+
+/// A session along with its bookkeeping
+#[derive(Debug)]
+struct Entry<SessionKey, SessionHandle, Metadata> {
+    session: SessionHandle,
+    /// Used to detect idle sessions
+    last_access: Mutex<Timestamp>,
+    /// Bumped on every access from a layer-wide counter
+    ///
+    /// The authoritative recency order: unlike [`Self::last_access`], no two entries share a value even within one clock tick.
+    seq: AtomicU64,
+    /// Changed whenever the handle is, for optimistic concurrency
+    ///
+    /// Drawn from the same counter as [`Self::seq`], so a session removed and inserted again never reuses a version.
+    version: u64,
+    /// Whether the `on_expiring` hook has run since the last access
+    expiring_notified: AtomicBool,
+    created_at: Timestamp,
+    /// Cheap data to query without the handle
+    metadata: Metadata,
+    /// Counted against the weight budget if any
+    weight: usize,
+    /// See [`TimeoutClass`]
+    class: AtomicU8,
+    /// The lifetime declared by the handle, counted from [`Self::created_at`], see [`SessionLayerBuilder::respect_handle_expiry`]
+    lifetime: Option<Duration>,
+    /// The tick of the wheel that the key was last scheduled at, see [`SessionLayerBuilder::expiry_wheel`]
+    ///
+    /// Keys found in other buckets are stale.
+    wheel_tick: AtomicU64,
+    /// Same as [`Self::wheel_tick`] but for the `on_expiring` hook
+    expiring_tick: AtomicU64,
+    /// Other keys of the session, see [`SessionLayer::insert_aliases`]
+    aliases: Vec<SessionKey>,
+    on_evict: Option<OnEvict<SessionKey, SessionHandle>>,
+}
+impl<SessionKey, SessionHandle, Metadata> Entry<SessionKey, SessionHandle, Metadata> {
+    fn new(session: SessionHandle, now: Timestamp, seq: u64) -> Self
+    where
+        Metadata: Default,
+    {
+        Self {
+            session,
+            last_access: Mutex::new(now),
+            seq: AtomicU64::new(seq),
+            version: seq,
+            expiring_notified: AtomicBool::new(false),
+            created_at: now,
+            metadata: Metadata::default(),
+            weight: 0,
+            class: AtomicU8::new(TimeoutClass::DEFAULT.0),
+            lifetime: None,
+            wheel_tick: AtomicU64::new(u64::MAX),
+            expiring_tick: AtomicU64::new(u64::MAX),
+            aliases: vec![],
+            on_evict: None,
+        }
+    }
+
+    /// Run the eviction callback if any
+    fn evict(self, key: SessionKey) {
+        if let Some(on_evict) = self.on_evict {
+            on_evict.run(key, self.session);
+        }
+    }
+
+    /// Run the eviction callback if any on clones and return the handle
+    fn into_session(self, key: &SessionKey) -> SessionHandle
+    where
+        SessionKey: Clone,
+    {
+        if let Some(on_evict) = self.on_evict {
+            let session = (on_evict.clone_session)(&self.session);
+            on_evict.run(key.clone(), session);
+        }
+        self.session
+    }
+
+    /// Return the idle time up to now
+    fn touch(&self, now: Timestamp, seq: u64) -> Duration {
+        let last_access = std::mem::replace(&mut *self.last_access.lock(), now);
+        self.seq.store(seq, Ordering::Relaxed);
+        self.expiring_notified.store(false, Ordering::Relaxed);
+        now.saturating_duration_since(last_access)
+    }
+
+    fn idle(&self, now: Timestamp) -> Duration {
+        now.saturating_duration_since(*self.last_access.lock())
+    }
+
+    fn age(&self, now: Timestamp) -> Duration {
+        now.saturating_duration_since(self.created_at)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    /// Build a layer whose sweeper sits on a runtime that is never driven
+    fn build<K, H, M>(
+        builder: SessionLayerBuilder<K, H, M>,
+    ) -> (tokio::runtime::Runtime, Arc<SessionLayer<K, H, M>>)
+    where
+        K: Eq + std::hash::Hash + Clone + Sync + Send + 'static,
+        H: Sync + Send + 'static,
+        M: Sync + Send + 'static,
+    {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+        let layer = runtime.block_on(async { builder.build() }).unwrap();
+        (runtime, layer)
+    }
+
+    pub(crate) fn layer<K, H>() -> (tokio::runtime::Runtime, Arc<SessionLayer<K, H>>)
+    where
+        K: Eq + std::hash::Hash + Clone + Sync + Send + 'static,
+        H: Sync + Send + 'static,
+    {
+        build(SessionLayer::builder(TIMEOUT))
+    }
+
+    /// A builder of layers that only sweep when told to and follow the returned clock
+    fn manual<K, H>() -> (SessionLayerBuilder<K, H>, ManualClock) {
+        let clock = ManualClock::new();
+        let builder = SessionLayerBuilder::new(TIMEOUT)
+            .manual_sweep()
+            .time_source(TimeSource::Manual(clock.clone()));
+        (builder, clock)
+    }
+
+    /// Pretend the session under `key` has been idle for `by` longer
+    pub(crate) fn idle<K: Eq + std::hash::Hash, H>(
+        layer: &SessionLayer<K, H>,
+        key: &K,
+        by: Duration,
+    ) {
+        let key_to_session = layer.key_to_session.read();
+        let mut last_access = key_to_session.get(key).unwrap().last_access.lock();
+        *last_access = last_access.checked_sub(by).unwrap();
+    }
+
+    /// How long the session under `key` has been idle
+    fn idle_for<K: Eq + std::hash::Hash, H, M>(layer: &SessionLayer<K, H, M>, key: &K) -> Duration {
+        let last_access = *layer
+            .key_to_session
+            .read()
+            .get(key)
+            .unwrap()
+            .last_access
+            .lock();
+        layer
+            .time_source
+            .now()
+            .saturating_duration_since(last_access)
+    }
+
+    /// Pretend the session under `key` was created `by` earlier
+    fn age_by<K: Eq + std::hash::Hash, H>(layer: &SessionLayer<K, H>, key: &K, by: Duration) {
+        let mut key_to_session = layer.key_to_session.write();
+        let created_at = &mut key_to_session.get_mut(key).unwrap().created_at;
+        *created_at = created_at.checked_sub(by).unwrap();
+    }
+
+    fn drain_events<K: Clone>(
+        events: &mut broadcast::Receiver<SessionEvent<K>>,
+    ) -> Vec<SessionEvent<K>> {
+        std::iter::from_fn(|| events.try_recv().ok()).collect()
+    }
+
+    #[test]
+    fn get_or_insert_with_status_tells_new_from_existing() {
+        let (_runtime, layer) = layer::<u32, u32>();
+        assert_eq!(layer.get_or_insert_with_status(1, || 10), (10, true));
+        assert_eq!(layer.get_or_insert_with_status(1, || 20), (10, false));
+        assert_eq!(layer.get_or_insert_with(2, || 30), 30);
+        assert_eq!(layer.get(&2), Some(30));
+    }
+
+    #[test]
+    fn get_or_insert_with_skips_make_on_hit() {
+        let (_runtime, layer) = layer::<u32, u32>();
+        layer.insert(1, 10).unwrap();
+        let session = layer.get_or_insert_with(1, || unreachable!("session already exists"));
+        assert_eq!(session, 10);
+    }
+
+    #[test]
+    fn lifecycle_events_are_broadcast() {
+        let (_runtime, layer) = layer::<u32, u32>();
+        let mut events = layer.subscribe();
+        layer.insert(1, 10).unwrap();
+        layer.insert_or_replace(1, 11).unwrap();
+        layer.insert(2, 20).unwrap();
+        layer.get(&1);
+        layer.remove(&1);
+        // Let the session reach its timeout without waiting for it
+        idle(&layer, &2, TIMEOUT);
+        layer.sweep();
+        let events = drain_events(&mut events);
+        assert!(matches!(
+            events.as_slice(),
+            [
+                SessionEvent::Inserted { key: 1, .. },
+                SessionEvent::Replaced { key: 1, .. },
+                SessionEvent::Inserted { key: 2, .. },
+                SessionEvent::Removed { key: 1, .. },
+                SessionEvent::Expired { key: 2, .. },
+            ]
+        ));
+    }
+
+    #[test]
+    fn access_events_are_opt_in() {
+        let (_runtime, layer) =
+            build(SessionLayer::<u32, u32>::builder(TIMEOUT).access_events(true));
+        layer.insert(1, 10).unwrap();
+        let mut events = layer.subscribe();
+        layer.get(&1);
+        layer.get(&2);
+        let events = drain_events(&mut events);
+        assert!(matches!(
+            events.as_slice(),
+            [SessionEvent::Accessed { key: 1, .. }]
+        ));
+    }
+
+    #[test]
+    fn panicking_callback_leaves_the_layer_usable() {
+        let (_runtime, layer) = layer::<u32, u32>();
+        layer.insert(1, 10).unwrap();
+        let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            layer.get_or_insert_with(2, || panic!("make panics under the write lock"));
+        }));
+        assert!(panicked.is_err());
+        layer.insert(2, 20).unwrap();
+        assert_eq!(layer.get(&1), Some(10));
+        assert_eq!(layer.get(&2), Some(20));
+    }
+
+    #[test]
+    fn stats_snapshot_does_not_refresh() {
+        let (_runtime, layer) = layer::<u32, u32>();
+        layer.insert(1, 10).unwrap();
+        idle(&layer, &1, Duration::from_secs(4));
+        layer.insert(2, 20).unwrap();
+        let stats = layer.stats_snapshot();
+        assert_eq!(stats.len, 2);
+        assert!(stats.oldest_idle >= Duration::from_secs(4));
+        assert!(stats.newest_idle < Duration::from_secs(4));
+        assert!(layer.stats_snapshot().oldest_idle >= Duration::from_secs(4));
+    }
+
+    #[test]
+    fn age_counts_from_creation_regardless_of_access() {
+        let (_runtime, layer) = layer::<u32, u32>();
+        layer.insert(1, 10).unwrap();
+        age_by(&layer, &1, Duration::from_secs(5));
+        layer.get(&1);
+        assert!(layer.age(&1).unwrap() >= Duration::from_secs(5));
+        assert_eq!(layer.age(&2), None);
+    }
+
+    #[test]
+    fn rekey_keeps_the_session_and_its_age() {
+        let (_runtime, layer) = layer::<u32, u32>();
+        layer.insert(1, 10).unwrap();
+        layer.insert(2, 20).unwrap();
+        age_by(&layer, &1, Duration::from_secs(3));
+        assert_eq!(layer.rekey(&1, 2), Err(RekeyError::Collision));
+        assert_eq!(layer.rekey(&3, 4), Err(RekeyError::NotFound));
+        layer.rekey(&1, 3).unwrap();
+        assert_eq!(layer.get(&1), None);
+        assert!(layer.age(&3).unwrap() >= Duration::from_secs(3));
+        assert_eq!(layer.get(&3), Some(10));
+    }
+
+    #[test]
+    fn get_or_try_insert_with_inserts_nothing_on_error() {
+        let (_runtime, layer) = layer::<u32, u32>();
+        assert_eq!(
+            layer.get_or_try_insert_with(1, || Err("unavailable")),
+            Err("unavailable")
+        );
+        assert_eq!(layer.stats_snapshot().len, 0);
+        assert_eq!(layer.get_or_try_insert_with(1, || Ok::<_, ()>(10)), Ok(10));
+        assert_eq!(layer.get_or_try_insert_with(1, || Err(())), Ok(10));
+    }
+
+    #[test]
+    fn extreme_timeouts_never_expire() {
+        // Built outside a runtime since no sweeper is needed
+        let layer = SessionLayerBuilder::<u32, u32>::new(Duration::MAX)
+            .build()
+            .expect("no runtime needed without expiry");
+        layer.insert(1, 10).unwrap();
+        idle(&layer, &1, Duration::from_secs(1));
+        layer.sweep();
+        assert_eq!(layer.get(&1), Some(10));
+        assert_eq!(layer.stats_snapshot().len, 1);
+    }
+
+    #[test]
+    fn unbounded_layers_need_no_runtime() {
+        let layer = SessionLayer::<u32, u32>::new_unbounded();
+        layer.insert(1, 10).unwrap();
+        layer.sweep();
+        assert_eq!(layer.get(&1), Some(10));
+    }
+
+    #[test]
+    fn try_new_rejects_invalid_configuration() {
+        assert_eq!(
+            SessionLayer::<u32, u32>::try_new(Duration::ZERO).err(),
+            Some(NewSessionLayerError::ZeroTimeout)
+        );
+        assert_eq!(
+            SessionLayerBuilder::<u32, u32>::new(TIMEOUT)
+                .event_capacity(0)
+                .build()
+                .err(),
+            Some(NewSessionLayerError::ZeroEventCapacity)
+        );
+    }
+
+    #[test]
+    fn try_new_needs_a_runtime_to_sweep() {
+        assert_eq!(
+            SessionLayer::<u32, u32>::try_new(TIMEOUT).err(),
+            Some(NewSessionLayerError::NoRuntime)
+        );
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let _entered = runtime.enter();
+        assert!(SessionLayer::<u32, u32>::try_new(TIMEOUT).is_ok());
+    }
+
+    #[test]
+    fn insert_many_lenient_reports_each_key() {
+        let (_runtime, layer) = layer::<u32, u32>();
+        layer.insert(2, 20).unwrap();
+        let outcomes = layer.insert_many_lenient([(1, 10), (2, 21), (3, 30), (3, 31)]);
+        assert!(matches!(
+            outcomes.as_slice(),
+            [
+                (1, Ok(())),
+                (2, Err(InsertError::Collision(21))),
+                (3, Ok(())),
+                (3, Err(InsertError::Collision(31))),
+            ]
+        ));
+        assert_eq!(layer.get(&2), Some(20));
+        assert_eq!(layer.get(&3), Some(30));
+    }
+
+    #[test]
+    fn sweeper_parks_while_the_map_is_empty() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .start_paused(true)
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let layer = SessionLayer::<u32, u32>::new(TIMEOUT);
+            tokio::time::sleep(TIMEOUT * 10).await;
+            // Only a sweeper woken by the insertion can remove the session
+            layer.insert(1, 10).unwrap();
+            idle(&layer, &1, TIMEOUT);
+            tokio::time::sleep(TIMEOUT).await;
+            assert_eq!(layer.stats_snapshot().len, 0);
+        });
+    }
+
+    #[test]
+    fn absorb_keeps_the_idle_budget_left() {
+        let (_other_runtime, other) = layer::<u32, u32>();
+        let (_runtime, layer) = build(SessionLayer::<u32, u32>::builder(TIMEOUT * 2));
+        other.insert(1, 10).unwrap();
+        idle(&other, &1, Duration::from_secs(4));
+        layer.absorb(&other, MergePolicy::KeepExisting).unwrap();
+        assert_eq!(other.stats_snapshot().len, 0);
+        // 6 seconds were left in `other`, so 14 seconds of the doubled timeout are spent
+        let idle = idle_for(&layer, &1);
+        assert!(Duration::from_secs(14) <= idle && idle < Duration::from_secs(15));
+    }
+
+    #[test]
+    fn absorb_resolves_conflicts_by_policy() {
+        let ((_runtime, layer), (_other_runtime, other)) = (layer(), layer::<u32, u32>());
+        layer.insert(1, 10).unwrap();
+        other.insert(1, 11).unwrap();
+        other.insert(2, 20).unwrap();
+        let conflict = layer.absorb(&other, MergePolicy::Error).unwrap_err();
+        assert_eq!(conflict.0, [1]);
+        assert_eq!(other.get(&1), Some(11));
+        assert_eq!(layer.get(&2), Some(20));
+
+        layer.absorb(&other, MergePolicy::KeepExisting).unwrap();
+        assert_eq!(layer.get(&1), Some(10));
+
+        other.insert(1, 12).unwrap();
+        layer.absorb(&other, MergePolicy::TakeIncoming).unwrap();
+        assert_eq!(layer.get(&1), Some(12));
+    }
+
+    #[test]
+    fn absorb_leaves_conflicts_untouched() {
+        let ((_runtime, layer), (_other_runtime, other)) = (layer(), layer::<u32, u32>());
+        layer.insert(1, 10).unwrap();
+        other.insert(1, 11).unwrap();
+        other.insert(2, 20).unwrap();
+        idle(&other, &1, Duration::from_secs(4));
+        let mut events = other.subscribe();
+        layer.absorb(&other, MergePolicy::Error).unwrap_err();
+        assert!(matches!(
+            drain_events(&mut events).as_slice(),
+            [SessionEvent::Removed { key: 2, .. }]
+        ));
+        let idle = idle_for(&other, &1);
+        assert!(idle >= Duration::from_secs(4));
+    }
+
+    #[test]
+    fn concurrent_absorbs_in_both_directions_do_not_deadlock() {
+        let ((_runtime, layer), (_other_runtime, other)) = (layer(), layer::<u32, u32>());
+        std::thread::scope(|scope| {
+            for (from, to) in [(&layer, &other), (&other, &layer)] {
+                scope.spawn(move || {
+                    for i in 0..1000 {
+                        from.insert(i, i).ok();
+                        to.absorb(from, MergePolicy::TakeIncoming).unwrap();
+                    }
+                });
+            }
+        });
+        assert_eq!(
+            layer.stats_snapshot().len + other.stats_snapshot().len,
+            1000
+        );
+    }
+
+    fn scan_all<H: std::fmt::Debug + Clone + Sync + Send + 'static>(
+        layer: &SessionLayer<u32, H>,
+        limit: usize,
+        mut between_pages: impl FnMut(),
+    ) -> Vec<u32> {
+        let mut keys = vec![];
+        let mut cursor = Some(ScanCursor::start());
+        while let Some(at) = cursor {
+            let (page, next) = layer.scan(at, limit);
+            keys.extend(page.into_iter().map(|(key, _)| key));
+            cursor = next;
+            between_pages();
+        }
+        keys
+    }
+
+    #[test]
+    fn scan_visits_every_session_once() {
+        let (_runtime, layer) = layer::<u32, u32>();
+        for key in 0..100 {
+            layer.insert(key, key).unwrap();
+        }
+        let mut keys = scan_all(&layer, 7, || ());
+        keys.sort_unstable();
+        assert_eq!(keys, (0..100).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn scan_survives_mutations_between_pages() {
+        let (_runtime, layer) = layer::<u32, u32>();
+        for key in 0..100 {
+            layer.insert(key, key).unwrap();
+        }
+        let mut next_key = 100;
+        let keys = scan_all(&layer, 10, || {
+            layer.insert(next_key, next_key).unwrap();
+            layer.remove(&(next_key - 100));
+            next_key += 1;
+        });
+        let removed = next_key - 100;
+        let unique = keys.iter().collect::<std::collections::HashSet<_>>();
+        assert_eq!(unique.len(), keys.len());
+        // Every session present for the whole scan is returned
+        assert!((removed..100).all(|key| unique.contains(&key)));
+    }
+
+    fn case_insensitive() -> (tokio::runtime::Runtime, Arc<SessionLayer<String, u32>>) {
+        build(SessionLayer::builder(TIMEOUT).normalize_key(|key: &String| key.to_lowercase()))
+    }
+
+    #[test]
+    fn owned_keys_are_normalized_on_the_way_in() {
+        let (_runtime, layer) = case_insensitive();
+        layer.insert("Alice".to_owned(), 10).unwrap();
+        assert!(layer.insert("ALICE".to_owned(), 11).is_err());
+        let (page, _) = layer.scan(ScanCursor::default(), 10);
+        assert_eq!(page, [("alice".to_owned(), 10)]);
+        assert_eq!(layer.normalize_key(&"ALICE".to_owned()), "alice");
+        // Borrowed keys are taken as is
+        assert_eq!(layer.get("alice"), Some(10));
+        assert_eq!(layer.get("Alice"), None);
+    }
+
+    #[test]
+    fn dead_handles_are_pruned_on_access_and_sweep() {
+        let (_runtime, layer) = build(
+            SessionLayer::builder(TIMEOUT)
+                .liveness_check(|alive: &Arc<AtomicBool>| alive.load(Ordering::Relaxed)),
+        );
+        let (first, second) = (
+            Arc::new(AtomicBool::new(true)),
+            Arc::new(AtomicBool::new(true)),
+        );
+        layer.insert(1, Arc::clone(&first)).unwrap();
+        layer.insert(2, Arc::clone(&second)).unwrap();
+        first.store(false, Ordering::Relaxed);
+        assert!(layer.get(&1).is_none());
+        assert_eq!(layer.stats_snapshot().len, 1);
+        second.store(false, Ordering::Relaxed);
+        layer.sweep();
+        assert_eq!(layer.stats_snapshot().len, 0);
+    }
+
+    #[test]
+    fn approx_len_follows_every_mutation() {
+        let (_runtime, layer) = layer::<u32, u32>();
+        for key in 0..4 {
+            layer.insert(key, key).unwrap();
+        }
+        assert_eq!(layer.approx_len(), 4);
+        layer.remove(&0);
+        assert_eq!(layer.approx_len(), 3);
+        for key in 1..4 {
+            idle(&layer, &key, TIMEOUT);
+        }
+        layer.sweep();
+        assert_eq!(layer.approx_len(), 0);
+        layer.insert(4, 4).unwrap();
+        assert_eq!(layer.approx_len(), layer.len());
+    }
+
+    #[test]
+    fn swap_contents_returns_the_old_set_and_starts_the_new_one_fresh() {
+        let (_runtime, layer) = layer::<u32, u32>();
+        layer.insert(1, 10).unwrap();
+        layer.insert(2, 20).unwrap();
+        idle(&layer, &2, TIMEOUT);
+        let old = layer.swap_contents(HashMap::from([(2, 200), (3, 300)]));
+        assert_eq!(old, HashMap::from([(1, 10), (2, 20)]));
+        assert_eq!(layer.get(&1), None);
+        assert!(idle_for(&layer, &3) < TIMEOUT / 2);
+
+        layer.sweep();
+        assert_eq!(layer.get(&2), Some(200));
+        assert_eq!(layer.get(&3), Some(300));
+    }
+
+    #[test]
+    fn on_sweep_sees_the_report_of_each_sweep() {
+        let reports = Arc::new(Mutex::new(vec![]));
+        let (_runtime, layer) = build(SessionLayer::<u32, u32>::builder(TIMEOUT).on_sweep({
+            let reports = Arc::clone(&reports);
+            move |report| reports.lock().push(report)
+        }));
+        assert_eq!(layer.last_sweep(), None);
+        layer.insert(1, 10).unwrap();
+        idle(&layer, &1, TIMEOUT);
+        layer.insert(2, 20).unwrap();
+
+        let report = layer.sweep();
+        assert_eq!((report.removed, report.remaining), (1, 1));
+        assert_eq!(layer.last_sweep(), Some(report));
+        assert_eq!(*reports.lock(), [report]);
+    }
+
+    #[test]
+    fn contains_and_touch_keeps_the_session_alive() {
+        let (_runtime, layer) = layer::<u32, u32>();
+        layer.insert(1, 10).unwrap();
+        for _ in 0..3 {
+            idle(&layer, &1, TIMEOUT / 2);
+            assert!(layer.contains_and_touch(&1));
+            assert!(idle_for(&layer, &1) < TIMEOUT / 2);
+            layer.sweep();
+        }
+        assert!(!layer.contains_and_touch(&2));
+        idle(&layer, &1, TIMEOUT);
+        layer.sweep();
+        assert!(!layer.contains_and_touch(&1));
+    }
+
+    #[test]
+    fn on_evict_runs_once_per_session() {
+        let (_runtime, layer) = layer::<u32, u32>();
+        let evicted = Arc::new(Mutex::new(vec![]));
+        let on_evict = |evicted: &Arc<Mutex<Vec<(u32, u32)>>>| {
+            let evicted = Arc::clone(evicted);
+            Box::new(move |key, session| evicted.lock().push((key, session))) as Box<_>
+        };
+        layer
+            .insert_with_on_evict(1, 10, on_evict(&evicted))
+            .unwrap();
+        layer
+            .insert_with_on_evict(2, 20, on_evict(&evicted))
+            .unwrap();
+        layer
+            .insert_with_on_evict(3, 30, on_evict(&evicted))
+            .unwrap();
+
+        layer.remove(&1);
+        layer.remove(&1);
+        idle(&layer, &2, TIMEOUT);
+        idle(&layer, &3, TIMEOUT);
+        layer.insert(2, 21).unwrap_err();
+        layer.sweep();
+        layer.sweep();
+        let mut evicted = evicted.lock().clone();
+        evicted.sort();
+        assert_eq!(evicted, [(1, 10), (2, 20), (3, 30)]);
+    }
+
+    #[test]
+    fn get_or_insert_with_hands_out_the_inserted_session_after_downgrading() {
+        let layer = SessionLayer::<u32, Arc<u32>>::new_unbounded();
+        let made = layer.get_or_insert_with(1, || Arc::new(10));
+        assert_eq!(layer.len(), 1);
+        assert_eq!(layer.approx_len(), 1);
+        // One handle held by the layer and one handed out
+        assert_eq!(Arc::strong_count(&made), 2);
+        // The write lock is released, so the next writer gets through
+        let again = layer.get_or_insert_with(1, || unreachable!());
+        assert!(Arc::ptr_eq(&made, &again));
+        layer.insert(2, Arc::new(20)).unwrap();
+    }
+
+    #[test]
+    fn shutdown_with_closes_each_session_once_within_the_concurrency() {
+        let layer = SessionLayer::<u32, u32>::new_unbounded();
+        let evicted = Arc::new(AtomicUsize::new(0));
+        for key in 0..8 {
+            let evicted = Arc::clone(&evicted);
+            let on_evict = Box::new(move |_, _| {
+                evicted.fetch_add(1, Ordering::Relaxed);
+            });
+            layer.insert_with_on_evict(key, key * 10, on_evict).unwrap();
+        }
+        let closed = Mutex::new(vec![]);
+        let running = AtomicUsize::new(0);
+        let peak = AtomicUsize::new(0);
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(layer.shutdown_with(3, |key, session| {
+            let (closed, running, peak) = (&closed, &running, &peak);
+            async move {
+                let now = running.fetch_add(1, Ordering::Relaxed) + 1;
+                peak.fetch_max(now, Ordering::Relaxed);
+                tokio::task::yield_now().await;
+                closed.lock().push((key, session));
+                running.fetch_sub(1, Ordering::Relaxed);
+            }
+        }));
+        let mut closed = closed.into_inner();
+        closed.sort();
+        assert_eq!(
+            closed,
+            (0..8).map(|key| (key, key * 10)).collect::<Vec<_>>()
+        );
+        assert_eq!(peak.load(Ordering::Relaxed), 3);
+        assert_eq!(evicted.load(Ordering::Relaxed), 8);
+        assert!(layer.is_empty());
+    }
+
+    #[test]
+    fn sweep_expires_the_least_recently_used_first_within_a_tick() {
+        let (_runtime, layer) = layer::<u32, u32>();
+        for key in 0..4 {
+            layer.insert(key, key).unwrap();
+        }
+        layer.get(&0);
+        layer.contains_and_touch(&2);
+        let mut events = layer.subscribe();
+        // All in the same tick, so only the access order tells them apart
+        let expired_at = layer.time_source.now().checked_sub(TIMEOUT).unwrap();
+        for (_, entry) in layer.key_to_session.read().iter() {
+            *entry.last_access.lock() = expired_at;
+        }
+        layer.sweep();
+        let expired = drain_events(&mut events)
+            .into_iter()
+            .map(|event| *event.key())
+            .collect::<Vec<_>>();
+        assert_eq!(expired, [1, 3, 0, 2]);
+    }
+
+    #[test]
+    fn on_expiring_fires_once_per_idle_episode() {
+        let warned = Arc::new(Mutex::new(vec![]));
+        let (_runtime, layer) = build(SessionLayer::builder(TIMEOUT).on_expiring(
+            Duration::from_secs(4),
+            {
+                let warned = Arc::clone(&warned);
+                move |key, _| warned.lock().push(*key)
+            },
+        ));
+        layer.insert(1, 10).unwrap();
+        layer.insert(2, 20).unwrap();
+        for key in [1, 2] {
+            idle(&layer, &key, Duration::from_secs(7));
+        }
+        let mut expiring = layer.expiring_within(Duration::from_secs(4));
+        expiring.sort();
+        assert_eq!(expiring, [(1, 10), (2, 20)]);
+        assert!(layer.expiring_within(Duration::from_secs(2)).is_empty());
+
+        layer.sweep();
+        layer.sweep();
+        warned.lock().sort();
+        assert_eq!(*warned.lock(), [1, 2]);
+
+        layer.get(&1);
+        idle(&layer, &1, Duration::from_secs(7));
+        layer.sweep();
+        assert_eq!(*warned.lock(), [1, 2, 1]);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn purge_loop_sweeps_until_the_layer_is_dropped() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .start_paused(true)
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let layer = SessionLayer::<u32, u32>::builder(TIMEOUT)
+                .manual_sweep()
+                .build()
+                .unwrap();
+            let purging = tokio::spawn(Arc::clone(&layer).purge_loop(Duration::from_secs(1)));
+            layer.insert(1, 10).unwrap();
+            idle(&layer, &1, TIMEOUT);
+            tokio::time::sleep(Duration::from_secs(2)).await;
+            assert!(layer.is_empty());
+
+            drop(layer);
+            tokio::time::sleep(Duration::from_secs(2)).await;
+            assert!(purging.is_finished());
+        });
+    }
+
+    #[test]
+    fn versions_reject_stale_writers() {
+        let (_runtime, layer) = layer::<u32, u32>();
+        layer.insert(1, 10).unwrap();
+        let (session, version) = layer.get_versioned(&1).unwrap();
+        assert_eq!(session, 10);
+
+        let newer = layer.replace_if_version(&1, version, 11).unwrap();
+        assert_ne!(newer, version);
+        assert_eq!(
+            layer.replace_if_version(&1, version, 12),
+            Err(VersionConflict {
+                current: Some(newer)
+            })
+        );
+        assert_eq!(
+            layer.remove_if_version(&1, version).map_err(|e| e.current),
+            Err(Some(newer))
+        );
+        assert_eq!(layer.remove_if_version(&1, newer), Ok(11));
+        assert_eq!(
+            layer.remove_if_version(&1, newer),
+            Err(VersionConflict { current: None })
+        );
+    }
+
+    #[test]
+    fn remove_many_skips_missing_keys() {
+        let (_runtime, layer) = layer::<u32, u32>();
+        for key in 0..4 {
+            layer.insert(key, key * 10).unwrap();
+        }
+        let mut events = layer.subscribe();
+        let mut removed = layer.remove_many(&[0, 2, 2, 9, 3]);
+        removed.sort();
+        assert_eq!(removed, [(0, 0), (2, 20), (3, 30)]);
+        assert_eq!(layer.keys(), [1]);
+        assert_eq!(drain_events(&mut events).len(), 3);
+    }
+
+    #[test]
+    fn outstanding_clones_counts_handles_held_outside() {
+        let (_runtime, layer) = layer::<u32, Arc<u32>>();
+        layer.insert(1, Arc::new(10)).unwrap();
+        assert_eq!(layer.outstanding_clones(&1), Some(0));
+        let first = layer.get(&1).unwrap();
+        let second = layer.peek(&1).unwrap();
+        assert_eq!(layer.outstanding_clones(&1), Some(2));
+        drop((first, second));
+        assert_eq!(layer.outstanding_clones(&1), Some(0));
+        assert_eq!(layer.outstanding_clones(&2), None);
+    }
+
+    #[test]
+    fn insert_at_backdates_the_session() {
+        let (_runtime, layer) = layer::<u32, u32>();
+        let eight = Duration::from_secs(8);
+        layer.insert_at(1, 10, Instant::now() - eight).unwrap();
+        layer.insert(2, 20).unwrap();
+        assert!(idle_for(&layer, &1) >= eight);
+        assert!(layer.age(&1).unwrap() >= eight);
+        idle(&layer, &1, Duration::from_secs(2));
+        layer.sweep();
+        assert_eq!(layer.keys(), [2]);
+    }
+
+    #[test]
+    fn metadata_defaults_and_can_be_queried() {
+        let (_runtime, layer) = build(SessionLayer::builder(TIMEOUT).metadata::<&str>());
+        layer.insert_with_metadata(1, 10, "admin").unwrap();
+        layer.insert(2, 20).unwrap();
+        assert_eq!(layer.metadata(&1), Some("admin"));
+        assert_eq!(layer.metadata(&2), Some(""));
+        assert_eq!(layer.metadata(&3), None);
+
+        assert_eq!(layer.find_by_metadata(|role| *role == "admin"), [1]);
+    }
+
+    #[test]
+    fn collision_policy_decides_which_write_wins() {
+        let outcome = |policy| {
+            let (_runtime, layer) = build(SessionLayer::builder(TIMEOUT).collision_policy(policy));
+            layer.insert(1, 10).unwrap();
+            let inserted = layer.insert(1, 11).is_ok();
+            (inserted, layer.get(&1), layer.len())
+        };
+        assert_eq!(outcome(CollisionPolicy::Reject), (false, Some(10), 1));
+        assert_eq!(outcome(CollisionPolicy::KeepExisting), (true, Some(10), 1));
+        assert_eq!(outcome(CollisionPolicy::Overwrite), (true, Some(11), 1));
+    }
+
+    #[test]
+    fn len_watch_only_sees_actual_changes() {
+        let (_runtime, layer) = layer::<u32, u32>();
+        let mut len = layer.len_watch();
+        assert_eq!(*len.borrow_and_update(), 0);
+        layer.insert_many_lenient([(1, 10), (2, 20)]);
+        assert!(len.has_changed().unwrap());
+        assert_eq!(*len.borrow_and_update(), 2);
+
+        layer.insert(1, 11).unwrap_err();
+        layer.get(&1);
+        assert!(!len.has_changed().unwrap());
+
+        for key in [1, 2] {
+            idle(&layer, &key, TIMEOUT);
+        }
+        layer.sweep();
+        assert_eq!(*len.borrow_and_update(), 0);
+    }
+
+    #[test]
+    fn extract_if_returns_matches_and_keeps_the_rest_as_is() {
+        let (_runtime, layer) = layer::<u32, u32>();
+        for key in 0..6 {
+            layer.insert(key, key * 10).unwrap();
+            idle(&layer, &key, TIMEOUT / 2);
+        }
+        let mut events = layer.subscribe();
+        let mut extracted = layer.extract_if(|key, session| key % 2 == 0 && *session != 40);
+        extracted.sort();
+        assert_eq!(extracted, [(0, 0), (2, 20)]);
+        assert_eq!(drain_events(&mut events).len(), 2);
+
+        for key in [1, 3, 4, 5] {
+            idle(&layer, &key, TIMEOUT / 2);
+        }
+        layer.sweep();
+        assert!(layer.is_empty());
+    }
+
+    #[test]
+    fn background_sweeps_longer_than_their_interval_are_overruns() {
+        let overruns = Arc::new(AtomicUsize::new(0));
+        let builder = SessionLayer::<u32, u32>::builder(TIMEOUT)
+            .liveness_check(|_| {
+                std::thread::sleep(Duration::from_millis(5));
+                true
+            })
+            .on_overrun({
+                let overruns = Arc::clone(&overruns);
+                move |report| {
+                    assert!(Duration::from_millis(5) <= report.duration);
+                    overruns.fetch_add(1, Ordering::Relaxed);
+                }
+            });
+        let (_runtime, layer) = build(builder);
+        layer.insert(1, 10).unwrap();
+        layer.background_sweep(Duration::from_secs(60));
+        assert_eq!(layer.sweep_overruns(), 0);
+        layer.background_sweep(Duration::from_millis(1));
+        assert_eq!(layer.sweep_overruns(), 1);
+        assert_eq!(overruns.load(Ordering::Relaxed), 1);
+        // Manual sweeps have no interval to overrun
+        layer.sweep();
+        assert_eq!(layer.sweep_overruns(), 1);
+    }
+
+    #[test]
+    fn insert_and_get_hands_out_the_session_under_the_key() {
+        let (_runtime, layer) = layer::<u32, Arc<u32>>();
+        let inserted = layer.insert_and_get(1, Arc::new(10)).unwrap();
+        assert!(Arc::ptr_eq(&inserted, &layer.peek(&1).unwrap()));
+        let rejected = layer.insert_and_get(1, Arc::new(11)).unwrap_err();
+        assert_eq!(*rejected.into_session(), 11);
+
+        let (_runtime, layer) = build(
+            SessionLayer::<u32, Arc<u32>>::builder(TIMEOUT)
+                .collision_policy(CollisionPolicy::KeepExisting),
+        );
+        layer.insert(1, Arc::new(10)).unwrap();
+        assert_eq!(*layer.insert_and_get(1, Arc::new(11)).unwrap(), 10);
+    }
+
+    #[test]
+    fn handles_that_cannot_be_cloned_are_read_in_place() {
+        #[derive(Debug, PartialEq)]
+        struct Connection(u32);
+
+        let (_runtime, layer) = layer::<u32, Connection>();
+        layer.insert(1, Connection(10)).unwrap();
+        layer.insert(2, Connection(20)).unwrap();
+        idle(&layer, &1, TIMEOUT / 2);
+        assert_eq!(layer.get_ref(&1, |conn| conn.0), Some(10));
+        assert_eq!(layer.get_ref(&3, |conn| conn.0), None);
+        assert_eq!(layer.remove(&2), Some(Connection(20)));
+
+        // get_ref refreshes like get
+        idle(&layer, &1, TIMEOUT / 2);
+        layer.sweep();
+        assert_eq!(layer.len(), 1);
+    }
+
+    #[test]
+    fn test_layers_only_expire_on_the_manual_clock() {
+        // No runtime is needed since nothing sweeps in the background
+        let (layer, clock) = SessionLayer::<u32, u32>::new_test(Duration::from_millis(1));
+        layer.insert(1, 10).unwrap();
+        std::thread::sleep(Duration::from_millis(5));
+        layer.sweep();
+        assert_eq!(idle_for(&layer, &1), Duration::ZERO);
+
+        clock.clone().advance(Duration::from_millis(1));
+        layer.sweep();
+        assert!(layer.is_empty());
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_layers_reject_a_zero_timeout() {
+        SessionLayer::<u32, u32>::new_test(Duration::ZERO);
+    }
+
+    #[test]
+    fn sparse_maps_are_shrunk_after_background_sweeps() {
+        let (layer, _) = SessionLayer::<u32, u32>::new_test(TIMEOUT);
+        layer.insert_many_lenient((0..1_000).map(|key| (key, key)));
+        let grown = layer.capacity();
+        layer.extract_if(|key, _| 10 <= *key);
+        // Manual sweeps leave the memory alone
+        layer.sweep();
+        assert!(grown / 2 < layer.capacity());
+        layer.background_sweep(TIMEOUT);
+        assert!(layer.capacity() < grown / 10);
+        assert_eq!(layer.len(), 10);
+    }
+
+    #[test]
+    fn shrink_to_fit_keeps_every_session() {
+        let (layer, _) = SessionLayer::<u32, u32>::new_test(TIMEOUT);
+        layer.insert_many_lenient((0..1_000).map(|key| (key, key)));
+        layer.extract_if(|key, _| 500 <= *key);
+        let grown = layer.capacity();
+        layer.shrink_to_fit();
+        assert!(layer.capacity() < grown);
+        assert!((0..500).all(|key| layer.contains_key(&key)));
+    }
+
+    #[test]
+    fn audit_log_keeps_the_latest_operations() {
+        let (builder, clock) = manual::<u32, u32>();
+        let layer = builder
+            .audit_capacity(3)
+            .key_label(|key: &u32| format!("k{key}"))
+            .build()
+            .unwrap();
+        layer.insert(1, 10).unwrap();
+        layer.insert(1, 11).unwrap_err();
+        layer.get(&1);
+        layer.insert(2, 20).unwrap();
+        layer.remove(&2);
+        clock.advance(TIMEOUT);
+        layer.sweep();
+        let log = layer
+            .audit_log()
+            .into_iter()
+            .map(|record| (record.op, record.key_label))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            log,
+            [
+                (AuditOp::Insert, "k2".to_owned()),
+                (AuditOp::Remove, "k2".to_owned()),
+                (AuditOp::Expire, "k1".to_owned()),
+            ]
+        );
+
+        let (builder, _) = manual::<u32, u32>();
+        let layer = builder.audit_capacity(3).audit_capacity(0).build().unwrap();
+        layer.insert(1, 10).unwrap();
+        assert!(layer.audit_log().is_empty());
+    }
+
+    #[test]
+    fn lookup_remembers_recently_removed_keys() {
+        let (builder, clock) = manual::<u32, u32>();
+        let layer = builder.tombstones(16, TIMEOUT * 3 / 2).build().unwrap();
+        layer.insert(1, 10).unwrap();
+        layer.insert(2, 20).unwrap();
+        assert_eq!(layer.lookup(&1), Lookup::Found(10));
+        layer.remove(&2);
+        clock.advance(TIMEOUT);
+        layer.sweep();
+        assert_eq!(
+            layer.lookup(&1),
+            Lookup::Expired {
+                since: Duration::ZERO
+            }
+        );
+        assert_eq!(layer.lookup(&2), Lookup::Expired { since: TIMEOUT });
+        assert_eq!(layer.lookup(&3), Lookup::Unknown);
+
+        // The next sweep forgets the removals past the retention
+        clock.advance(TIMEOUT);
+        layer.sweep();
+        assert_eq!(layer.lookup(&1), Lookup::Expired { since: TIMEOUT });
+        assert_eq!(layer.lookup(&2), Lookup::Unknown);
+    }
+
+    #[test]
+    fn background_sweeps_are_skipped_while_paused() {
+        let (layer, clock) = SessionLayer::<u32, u32>::new_test(TIMEOUT);
+        layer.insert(1, 10).unwrap();
+        clock.advance(TIMEOUT);
+        let len = layer.with_sweep_paused(|layer| {
+            layer.background_sweep(TIMEOUT);
+            layer.len()
+        });
+        assert_eq!(len, 1);
+        layer.background_sweep(TIMEOUT);
+        assert!(layer.is_empty());
+
+        layer.insert(2, 20).unwrap();
+        clock.advance(TIMEOUT);
+        // Explicit sweeps still go through
+        layer.with_sweep_paused(|layer| layer.sweep());
+        assert!(layer.is_empty());
+    }
+
+    #[test]
+    fn normalized_lookups_accept_any_spelling() {
+        let (_runtime, layer) = case_insensitive();
+        layer.insert("Alice".to_owned(), 10).unwrap();
+        assert_eq!(layer.get_normalized(&"ALICE".to_owned()), Some(10));
+        assert!(layer.contains_key_normalized(&"aLiCe".to_owned()));
+        assert!(!layer.contains_key_normalized(&"Bob".to_owned()));
+        assert_eq!(layer.remove_normalized(&"ALICE".to_owned()), Some(10));
+        assert!(layer.is_empty());
+    }
+
+    #[test]
+    fn weight_budget_evicts_the_least_recently_used() {
+        let (builder, _) = manual::<u32, Vec<u8>>();
+        let layer = builder.weight_budget(10, Vec::len).build().unwrap();
+        let mut events = layer.subscribe();
+        layer.insert(1, vec![0; 4]).unwrap();
+        layer.insert(2, vec![0; 4]).unwrap();
+        layer.get(&1);
+        layer.insert(3, vec![0; 4]).unwrap();
+        assert!(layer.contains_key(&1) && !layer.contains_key(&2) && layer.contains_key(&3));
+        assert_eq!(layer.total_weight(), 8);
+
+        // A session heavier than the whole budget stays alone
+        layer.insert(4, vec![0; 20]).unwrap();
+        assert_eq!(layer.keys(), [4]);
+        let removed = drain_events(&mut events)
+            .into_iter()
+            .filter(|event| !matches!(event, SessionEvent::Inserted { .. }))
+            .map(|event| *event.key())
+            .collect::<Vec<_>>();
+        assert_eq!(removed, [2, 1, 3]);
+    }
+
+    #[test]
+    fn integer_keyed_layers_behave_like_the_default_ones() {
+        let (builder, clock) = manual::<u64, u64>();
+        let layer = builder.integer_keys().build().unwrap();
+        layer.insert_many_lenient((0..100).map(|key| (key << 32, key)));
+        assert_eq!(layer.len(), 100);
+        assert!((0..100).all(|key| layer.get(&(key << 32)) == Some(key)));
+        assert_eq!(layer.get(&1), None);
+        layer.remove_many(&[0, 1 << 32]);
+        clock.advance(TIMEOUT);
+        assert_eq!(layer.sweep().removed, 98);
+
+        let (builder, _) = manual::<u128, u32>();
+        let layer = builder.integer_keys().build().unwrap();
+        layer.insert(u128::MAX, 1).unwrap();
+        assert!(layer.contains_key(&u128::MAX));
+    }
+
+    #[test]
+    fn key_label_defaults_to_a_short_hash() {
+        let (layer, _) = SessionLayer::<String, u32>::new_test(TIMEOUT);
+        let key = "secret-token".to_owned();
+        assert_eq!(layer.key_label(&key), crate::label::short_hash(&key));
+
+        let (builder, _) = manual::<String, u32>();
+        let layer = builder
+            .key_label(|key: &String| key.clone())
+            .build()
+            .unwrap();
+        assert_eq!(layer.key_label(&key), key);
+    }
+
+    #[test]
+    fn strict_layers_never_hand_out_stale_sessions() {
+        let (builder, clock) = manual::<u32, u32>();
+        let layer = builder
+            .resurrect_policy(ResurrectPolicy::Strict)
+            .build()
+            .unwrap();
+        layer.insert(1, 10).unwrap();
+        layer.insert(2, 20).unwrap();
+        clock.advance(TIMEOUT - Duration::from_millis(1));
+        assert_eq!(layer.get(&1), Some(10));
+        clock.advance(TIMEOUT - Duration::from_millis(1));
+        assert_eq!(layer.peek(&2), None);
+        assert_eq!(layer.len(), 2);
+        // Refreshing lookups remove the session inline instead of waiting for the sweep
+        assert_eq!(layer.get(&2), None);
+        assert_eq!(layer.len(), 1);
+    }
+
+    #[test]
+    fn sweeper_status_tracks_sweeps_and_the_task() {
+        let (layer, _) = SessionLayer::<u32, u32>::new_test(TIMEOUT);
+        let status = layer.sweeper_status();
+        assert_eq!(status.last_sweep_started, None);
+        assert_eq!(status.sweeps_completed, 0);
+        assert!(!status.task_alive);
+
+        layer.sweep();
+        layer.sweep();
+        let status = layer.sweeper_status();
+        assert_eq!(status.sweeps_completed, 2);
+        assert!(status.last_sweep_started <= status.last_sweep_finished);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn sweeper_status_notices_the_task_ending() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let (builder, _) = manual::<u32, u32>();
+        let layer = builder
+            .runtime(crate::TokioRuntime::new(runtime.handle().clone()))
+            .build()
+            .unwrap();
+        let purging = runtime.spawn(Arc::clone(&layer).purge_loop(TIMEOUT));
+        assert!(layer.sweeper_status().task_alive);
+        purging.abort();
+        runtime.block_on(async {
+            assert!(purging.await.unwrap_err().is_cancelled());
+        });
+        assert!(!layer.sweeper_status().task_alive);
+    }
+
+    #[test]
+    fn take_all_orders_by_the_time_left() {
+        let (layer, clock) = SessionLayer::<u32, u32>::new_test(TIMEOUT);
+        layer.insert(1, 10).unwrap();
+        clock.advance(Duration::from_secs(3));
+        layer.insert(2, 20).unwrap();
+        clock.advance(Duration::from_secs(4));
+        layer.insert(3, 30).unwrap();
+        assert_eq!(
+            layer.take_all(),
+            [
+                (1, 10, Duration::from_secs(3)),
+                (2, 20, Duration::from_secs(6)),
+                (3, 30, TIMEOUT),
+            ]
+        );
+        assert!(layer.is_empty());
+
+        let layer = SessionLayer::<u32, u32>::new_unbounded();
+        layer.insert(1, 10).unwrap();
+        assert_eq!(layer.take_all(), [(1, 10, Duration::MAX)]);
+    }
+
+    #[test]
+    fn reservations_hold_the_key_until_fulfilled_dropped_or_expired() {
+        let (builder, clock) = manual::<u32, u32>();
+        let layer = builder
+            .reservation_timeout(Duration::from_secs(2))
+            .build()
+            .unwrap();
+        let reservation = layer.reserve(1).unwrap();
+        assert_eq!(*reservation.key(), 1);
+        assert!(layer.reserve(1).is_err());
+        assert!(layer.insert(1, 9).is_err());
+        assert_eq!(layer.get(&1), None);
+        reservation.fulfill(10).unwrap();
+        assert_eq!(layer.get(&1), Some(10));
+        assert!(layer.reserve(1).is_err());
+
+        drop(layer.reserve(2).unwrap());
+        layer.insert(2, 20).unwrap();
+
+        let abandoned = layer.reserve(3).unwrap();
+        clock.advance(Duration::from_secs(2));
+        layer.insert(3, 30).unwrap();
+        assert!(abandoned.fulfill(31).is_err());
+        assert_eq!(layer.get(&3), Some(30));
+    }
+
+    #[test]
+    fn get_and_idle_reports_and_resets_the_idle_time() {
+        let (builder, clock) = manual::<u32, u32>();
+        let layer = builder.build().unwrap();
+        layer.insert(1, 10).unwrap();
+        clock.advance(Duration::from_secs(3));
+        assert_eq!(layer.get_and_idle(&1), Some((10, Duration::from_secs(3))));
+        assert_eq!(layer.get_and_idle(&1), Some((10, Duration::ZERO)));
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(idle_for(&layer, &1), Duration::from_secs(1));
+        assert_eq!(layer.get_and_idle(&2), None);
+    }
+
+    #[test]
+    fn lenient_layers_save_sessions_refreshed_before_the_sweep() {
+        let (builder, clock) = manual::<u32, u32>();
+        let layer = builder
+            .resurrect_policy(ResurrectPolicy::Lenient)
+            .build()
+            .unwrap();
+        layer.insert(1, 10).unwrap();
+        layer.insert(2, 20).unwrap();
+        clock.advance(TIMEOUT + Duration::from_millis(1));
+        assert_eq!(layer.peek(&2), Some(20));
+        assert_eq!(layer.get(&1), Some(10));
+        layer.sweep();
+        assert_eq!(layer.get(&1), Some(10));
+        assert_eq!(layer.get(&2), None);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn writes_wake_the_sweep_early() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .start_paused(true)
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let clock = ManualClock::new();
+            let layer = SessionLayerBuilder::<u32, u32>::new(TIMEOUT)
+                .time_source(TimeSource::Manual(clock.clone()))
+                .sweep_after_mutations(2)
+                .build()
+                .unwrap();
+            layer.insert(1, 10).unwrap();
+            tokio::time::sleep(Duration::from_millis(1)).await;
+            clock.advance(TIMEOUT);
+            layer.insert(2, 20).unwrap();
+            // Well before the timed sweep half a timeout later
+            tokio::time::sleep(Duration::from_millis(1)).await;
+            assert_eq!(layer.len(), 1);
+            assert_eq!(layer.peek(&2), Some(20));
+        });
+    }
+
+    #[test]
+    fn sweeps_are_requested_every_count_writes() {
+        let (builder, _) = manual::<u32, u32>();
+        let layer = builder.sweep_after_mutations(3).build().unwrap();
+        let requested = || {
+            let notified = std::pin::pin!(layer.sweep_now.notified());
+            notified
+                .poll(&mut std::task::Context::from_waker(std::task::Waker::noop()))
+                .is_ready()
+        };
+        layer.insert(1, 10).unwrap();
+        layer.insert(2, 20).unwrap();
+        assert!(!requested());
+        layer.remove(&1);
+        assert!(requested());
+    }
+
+    #[test]
+    fn admission_hands_denied_sessions_back() {
+        let (builder, _) = manual::<u32, u32>();
+        let layer = builder
+            .admission(|_, session| match session % 2 {
+                0 => Ok(()),
+                _ => Err(AdmissionDenied::new("odd")),
+            })
+            .build()
+            .unwrap();
+        assert!(matches!(
+            layer.insert(1, 11),
+            Err(InsertError::Denied { session: 11, ref denied }) if denied.reason == "odd"
+        ));
+        assert!(matches!(
+            layer.insert_or_replace(1, 15),
+            Err(InsertError::Denied { session: 15, .. })
+        ));
+        assert!(matches!(
+            layer.try_get_or_insert_with(1, || 17),
+            Err(InsertError::Denied { session: 17, .. })
+        ));
+        assert!(layer.is_empty());
+        layer.insert(1, 10).unwrap();
+        assert_eq!(layer.get(&1), Some(10));
+        layer.insert(1, 12).unwrap_err();
+        // Nothing vets the infallible variant
+        assert_eq!(layer.get_or_insert_with(3, || 13), 13);
+    }
+
+    #[test]
+    fn try_get_or_insert_with_returns_a_session_inserted_during_admission() {
+        let (builder, _) = manual::<u32, u32>();
+        let racing: Arc<std::sync::OnceLock<Weak<SessionLayer<u32, u32>>>> = Default::default();
+        let raced = AtomicBool::new(false);
+        let layer = builder
+            .admission({
+                let racing = Arc::clone(&racing);
+                move |key, _| {
+                    if !raced.swap(true, Ordering::Relaxed) {
+                        let layer = racing.get().unwrap().upgrade().unwrap();
+                        layer.insert(*key, 99).unwrap();
+                    }
+                    Ok(())
+                }
+            })
+            .build()
+            .unwrap();
+        racing.set(Arc::downgrade(&layer)).unwrap();
+        assert!(matches!(
+            layer.try_get_or_insert_with_status(1, || 10),
+            Ok((99, false))
+        ));
+        assert_eq!(layer.get(&1), Some(99));
+    }
+
+    #[test]
+    fn handles_that_cannot_be_cloned_are_moved_in_and_out() {
+        #[derive(Debug, PartialEq)]
+        struct Connection(u32);
+
+        let (builder, clock) = manual::<u32, Connection>();
+        let layer = builder.build().unwrap();
+        layer.insert(1, Connection(10)).unwrap();
+        assert_eq!(
+            layer.insert_or_replace(1, Connection(11)).unwrap(),
+            Some(Connection(10))
+        );
+        layer.rekey(&1, 2).unwrap();
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(layer.age(&2), Some(Duration::from_secs(1)));
+        layer.insert(3, Connection(30)).unwrap();
+        layer.insert(4, Connection(40)).unwrap();
+        assert_eq!(layer.remove_many([&3]), [(3, Connection(30))]);
+        assert_eq!(
+            layer.extract_if(|_, conn| conn.0 == 40),
+            [(4, Connection(40))]
+        );
+        assert_eq!(layer.drain(), [(2, Connection(11))]);
+    }
+
+    #[test]
+    fn replace_all_keeps_the_last_session_of_each_key() {
+        let (builder, clock) = manual::<u32, u32>();
+        let layer = builder.build().unwrap();
+        layer.insert(1, 10).unwrap();
+        layer.insert(2, 20).unwrap();
+        clock.advance(TIMEOUT / 2);
+        let mut old = layer.replace_all([(2, 21), (3, 30), (3, 31)]);
+        old.sort();
+        assert_eq!(old, [(1, 10), (2, 20)]);
+        assert_eq!(layer.len(), 2);
+        assert_eq!(layer.peek(&1), None);
+        assert_eq!(layer.peek(&3), Some(31));
+        // The new sessions start with a full timeout
+        assert_eq!(layer.age(&2), Some(Duration::ZERO));
+        clock.advance(TIMEOUT / 2);
+        layer.sweep();
+        assert_eq!(layer.len(), 2);
+    }
+
+    #[test]
+    fn insert_rate_limit_throttles_inserts_and_replacements() {
+        let (builder, clock) = manual::<u32, u32>();
+        let layer = builder
+            .insert_rate_limit(2, Duration::from_secs(1))
+            .build()
+            .unwrap();
+        layer.insert(1, 10).unwrap();
+        // A failed attempt still takes a token
+        assert!(matches!(
+            layer.insert(1, 11),
+            Err(InsertError::Collision(11))
+        ));
+        assert!(matches!(
+            layer.insert_or_replace(1, 12),
+            Err(InsertError::RateLimited(12))
+        ));
+        assert!(matches!(
+            layer.insert(2, 20),
+            Err(InsertError::RateLimited(20))
+        ));
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(layer.insert_or_replace(1, 13).unwrap(), Some(10));
+        assert_eq!(layer.len(), 1);
+    }
+
+    #[test]
+    fn timeout_classes_expire_sessions_on_their_own_timeouts() {
+        const HANDSHAKE: TimeoutClass = TimeoutClass::new(1);
+        let (builder, clock) = manual::<u32, u32>();
+        let layer = builder
+            .timeout_class(HANDSHAKE, Duration::from_secs(1))
+            .build()
+            .unwrap();
+        layer.insert_in_class(1, 10, HANDSHAKE).unwrap();
+        layer.insert_in_class(2, 20, HANDSHAKE).unwrap();
+        layer.insert(3, 30).unwrap();
+        // A class that was never defined follows the default timeout
+        layer.insert_in_class(4, 40, TimeoutClass::new(9)).unwrap();
+        assert!(layer.promote(&2, TimeoutClass::DEFAULT));
+        assert!(!layer.promote(&5, TimeoutClass::DEFAULT));
+        clock.advance(Duration::from_secs(1));
+        layer.sweep();
+        assert_eq!(layer.peek(&1), None);
+        assert_eq!(layer.len(), 3);
+        clock.advance(TIMEOUT);
+        layer.sweep();
+        assert!(layer.is_empty());
+    }
+
+    #[test]
+    fn timeout_classes_reject_zero_timeouts() {
+        let (builder, _) = manual::<u32, u32>();
+        assert!(matches!(
+            builder
+                .timeout_class(TimeoutClass::new(1), Duration::ZERO)
+                .build(),
+            Err(NewSessionLayerError::ZeroTimeout)
+        ));
+    }
+
+    #[test]
+    fn lookups_of_unknown_keys_are_counted_as_misses() {
+        let (builder, clock) = manual::<u32, u32>();
+        let layer = builder.track_misses(64, TIMEOUT).build().unwrap();
+        layer.insert(1, 10).unwrap();
+        assert_eq!(layer.get(&1), Some(10));
+        for _ in 0..3 {
+            assert_eq!(layer.get(&2), None);
+        }
+        assert_eq!(layer.get(&3), None);
+        assert_eq!(layer.misses_for(&1), 0);
+        assert_eq!(layer.misses_for(&2), 3);
+        let top = layer.top_missed(1);
+        assert_eq!(top, [(layer.scan_hasher.hash_one(2), 3)]);
+        clock.advance(TIMEOUT * 2);
+        assert_eq!(layer.misses_for(&2), 0);
+        assert!(layer.top_missed(10).is_empty());
+    }
+
+    #[test]
+    fn misses_are_not_counted_unless_tracked() {
+        let (layer, _) = SessionLayer::<u32, u32>::new_test(TIMEOUT);
+        assert_eq!(layer.get(&1), None);
+        assert_eq!(layer.misses_for(&1), 0);
+        assert!(layer.top_missed(1).is_empty());
+    }
+
+    #[test]
+    fn eviction_worker_runs_callbacks_off_the_sweeping_thread() {
+        let (builder, clock) = manual::<u32, u32>();
+        let layer = builder
+            .eviction_worker(4, crate::FullQueuePolicy::Block)
+            .build()
+            .unwrap();
+        let (evicted, wait_evicted) = std::sync::mpsc::channel();
+        layer
+            .insert_with_on_evict(
+                1,
+                10,
+                Box::new(move |key, session| {
+                    let thread = std::thread::current().name().map(str::to_owned);
+                    evicted.send((key, session, thread)).unwrap();
+                }),
+            )
+            .unwrap();
+        clock.advance(TIMEOUT);
+        layer.sweep();
+        let (key, session, thread) = wait_evicted.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!((key, session), (1, 10));
+        assert_eq!(thread.as_deref(), Some("session-eviction"));
+        assert_eq!(layer.dropped_evictions(), 0);
+    }
+
+    #[test]
+    fn negative_lookup_filter_never_hides_present_keys() {
+        let (builder, clock) = manual::<u32, u32>();
+        let layer = builder.negative_lookup_filter(1 << 12).build().unwrap();
+        layer.insert(1, 10).unwrap();
+        layer.insert_aliases(2, vec![3], 20).unwrap();
+        layer.rekey(&1, 4).unwrap();
+        assert_eq!(layer.get(&1), None);
+        assert_eq!(layer.get(&3), Some(20));
+        assert_eq!(layer.get(&4), Some(10));
+        layer.replace_all([(5, 50)]);
+        assert_eq!(layer.get(&5), Some(50));
+        assert_eq!(layer.get(&4), None);
+        layer.insert(6, 60).unwrap();
+        clock.advance(TIMEOUT / 2);
+        // The sweep rebuilds the filter from the remaining keys
+        layer.sweep();
+        assert_eq!(layer.get(&5), Some(50));
+        assert_eq!(layer.get(&6), Some(60));
+        assert_eq!(layer.get(&2), None);
+    }
+
+    #[test]
+    fn thresholds_fire_once_per_excursion() {
+        let fired = Arc::new(crate::lock::Mutex::new(vec![]));
+        let (builder, _) = manual::<u32, u32>();
+        let layer = builder
+            .on_threshold(10, {
+                let fired = Arc::clone(&fired);
+                move |len| fired.lock().push(len)
+            })
+            .build()
+            .unwrap();
+        for key in 0..11 {
+            layer.insert(key, key).unwrap();
+        }
+        assert_eq!(*fired.lock(), [10]);
+        assert_eq!(layer.high_water_mark(), 11);
+        // Hovering just below the level does not rearm it
+        layer.remove(&10);
+        layer.remove(&9);
+        layer.insert(9, 9).unwrap();
+        assert_eq!(*fired.lock(), [10]);
+        layer.remove(&9);
+        layer.remove(&8);
+        layer.insert_or_replace(8, 8).unwrap();
+        layer.insert(9, 9).unwrap();
+        assert_eq!(*fired.lock(), [10, 10]);
+
+        layer.reset_high_water_mark();
+        assert_eq!(layer.high_water_mark(), 10);
+        layer.remove(&9);
+        assert_eq!(layer.high_water_mark(), 10);
+    }
+
+    #[test]
+    fn metadata_survives_version_replacements_but_not_new_entries() {
+        let (builder, _) = manual::<u32, u32>();
+        let layer = builder.metadata::<&str>().build().unwrap();
+        layer.insert_with_metadata(1, 10, "admin").unwrap();
+        assert_eq!(layer.get_with_metadata(&1), Some((10, "admin")));
+        assert_eq!(layer.get_with_metadata(&2), None);
+
+        let (_, version) = layer.get_versioned(&1).unwrap();
+        layer.replace_if_version(&1, version, 11).unwrap();
+        assert_eq!(layer.get_with_metadata(&1), Some((11, "admin")));
+        layer.insert_or_replace(1, 12).unwrap();
+        assert_eq!(layer.get_with_metadata(&1), Some((12, "")));
+    }
+
+    #[test]
+    fn expiry_wheel_expires_idle_sessions_and_reschedules_accessed_ones() {
+        let (builder, clock) = manual::<u32, u32>();
+        let layer = builder
+            .expiry_wheel(Duration::from_secs(1))
+            .build()
+            .unwrap();
+        layer.insert(1, 10).unwrap();
+        layer.insert(2, 20).unwrap();
+        clock.advance(TIMEOUT / 2);
+        assert_eq!(layer.get(&2), Some(20));
+        clock.advance(TIMEOUT / 2);
+        layer.sweep();
+        assert_eq!(layer.peek(&1), None);
+        assert_eq!(layer.peek(&2), Some(20));
+        clock.advance(TIMEOUT / 2);
+        layer.sweep();
+        assert!(layer.is_empty());
+    }
+
+    #[test]
+    fn expiry_wheel_drives_on_expiring() {
+        let warned = Arc::new(AtomicUsize::new(0));
+        let (builder, clock) = manual::<u32, u32>();
+        let layer = builder
+            .expiry_wheel(Duration::from_secs(1))
+            .on_expiring(Duration::from_secs(3), {
+                let warned = Arc::clone(&warned);
+                move |_, _| {
+                    warned.fetch_add(1, Ordering::Relaxed);
+                }
+            })
+            .build()
+            .unwrap();
+        layer.insert(1, 10).unwrap();
+        clock.advance(Duration::from_secs(6));
+        layer.sweep();
+        assert_eq!(warned.load(Ordering::Relaxed), 0);
+        clock.advance(Duration::from_secs(1));
+        layer.sweep();
+        layer.sweep();
+        assert_eq!(warned.load(Ordering::Relaxed), 1);
+        assert_eq!(layer.len(), 1);
+    }
+
+    #[test]
+    fn expiry_wheel_counts_ticks_on_the_wall_clock() {
+        let layer = SessionLayerBuilder::<u32, u32>::new(Duration::from_millis(20))
+            .manual_sweep()
+            .time_source(TimeSource::Wall)
+            .expiry_wheel(Duration::from_millis(5))
+            .build()
+            .unwrap();
+        layer.insert(1, 10).unwrap();
+        layer.sweep();
+        assert_eq!(layer.len(), 1);
+        std::thread::sleep(Duration::from_millis(40));
+        layer.sweep();
+        assert!(layer.is_empty());
+    }
+
+    #[test]
+    fn refresh_reports_the_full_timeout_of_the_class() {
+        const SHORT: TimeoutClass = TimeoutClass::new(1);
+        let (builder, clock) = manual::<u32, u32>();
+        let layer = builder
+            .timeout_class(SHORT, Duration::from_secs(2))
+            .build()
+            .unwrap();
+        layer.insert(1, 10).unwrap();
+        layer.insert_in_class(2, 20, SHORT).unwrap();
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(layer.refresh(&1), Some((10, TIMEOUT)));
+        assert_eq!(layer.refresh(&2), Some((20, Duration::from_secs(2))));
+        assert_eq!(idle_for(&layer, &1), Duration::ZERO);
+        assert_eq!(layer.refresh(&3), None);
+
+        let unbounded = SessionLayer::<u32, u32>::new_unbounded();
+        unbounded.insert(1, 10).unwrap();
+        assert_eq!(unbounded.refresh(&1), Some((10, Duration::MAX)));
+    }
+
+    #[test]
+    fn aliases_share_one_session() {
+        let (builder, clock) = manual::<u32, u32>();
+        let layer = builder.metadata::<&str>().build().unwrap();
+        layer.insert_aliases(1, vec![2, 3], 10).unwrap();
+        layer.add_alias(&3, 4).unwrap();
+        assert_eq!(layer.add_alias(&5, 6), Err(AliasError::NotFound));
+        assert_eq!(layer.add_alias(&1, 2), Err(AliasError::Collision));
+        assert!(layer.insert(2, 20).is_err());
+        assert!(layer.insert_aliases(5, vec![6, 6], 50).is_err());
+        assert_eq!(layer.len(), 1);
+
+        clock.advance(TIMEOUT / 2);
+        assert_eq!(layer.get(&4), Some(10));
+        assert_eq!(idle_for(&layer, &1), Duration::ZERO);
+        assert_eq!(layer.age(&2), Some(TIMEOUT / 2));
+        assert_eq!(layer.peek(&3), Some(10));
+        assert_eq!(layer.metadata(&4), Some(""));
+        assert!(layer.contains_key(&2));
+
+        // Removing through an alias drops every key of the session
+        assert_eq!(layer.remove_many([&3]), [(1, 10)]);
+        assert!((1..=4).all(|key| layer.peek(&key).is_none()));
+        layer.insert(2, 20).unwrap();
+        assert_eq!(layer.get(&2), Some(20));
+    }
+
+    #[test]
+    fn aliases_expire_with_their_session() {
+        let (builder, clock) = manual::<u32, u32>();
+        let layer = builder.build().unwrap();
+        layer.insert_aliases(1, vec![2], 10).unwrap();
+        clock.advance(TIMEOUT);
+        layer.sweep();
+        assert_eq!(layer.get(&2), None);
+        layer.insert(2, 20).unwrap();
+        assert_eq!(layer.get(&1), None);
+    }
+
+    #[test]
+    fn inserting_paths_follow_aliases_and_reservations() {
+        let (builder, _) = manual::<u32, u32>();
+        let layer = builder.metadata::<&str>().build().unwrap();
+        layer.insert_aliases(1, vec![2], 10).unwrap();
+        assert_eq!(layer.get_or_insert_with(2, || 20), 10);
+        assert!(layer.set_metadata(&2, "through alias"));
+        assert_eq!(layer.metadata(&1), Some("through alias"));
+        assert_eq!(layer.len(), 1);
+
+        let reservation = layer.reserve(3).unwrap();
+        let outcomes = layer.insert_many_lenient([(2, 21), (3, 31), (4, 41)]);
+        assert!(matches!(outcomes[0], (2, Err(InsertError::Collision(21)))));
+        assert!(matches!(outcomes[1], (3, Err(InsertError::Collision(31)))));
+        assert!(matches!(outcomes[2], (4, Ok(()))));
+        assert!(matches!(
+            layer.try_get_or_insert_with(3, || 32),
+            Err(InsertError::Collision(32))
+        ));
+        drop(reservation);
+        assert_eq!(layer.try_get_or_insert_with(3, || 33).unwrap(), 33);
+
+        // Replacing the primary key forgets its aliases
+        assert_eq!(layer.insert_or_replace(1, 11).unwrap(), Some(10));
+        assert_eq!(layer.get(&2), None);
+        layer.insert(2, 22).unwrap();
+    }
+
+    #[cfg(feature = "live-bytes")]
+    #[test]
+    fn live_bytes_follows_the_weight_of_the_layer() {
+        // Far heavier than the sessions of any other test, which may run concurrently and move the gauge too
+        const HEAVY: usize = 1 << 40;
+        let (builder, clock) = manual::<u32, usize>();
+        let layer = builder
+            .weight_budget(usize::MAX, |weight| *weight)
+            .build()
+            .unwrap();
+        layer.insert(1, HEAVY).unwrap();
+        layer.insert(2, HEAVY).unwrap();
+        assert!(HEAVY * 2 <= crate::live_bytes());
+        layer.remove(&1);
+        assert!(crate::live_bytes() < HEAVY * 2);
+        clock.advance(TIMEOUT);
+        layer.sweep();
+        assert!(crate::live_bytes() < HEAVY);
+        layer.insert(3, HEAVY).unwrap();
+        drop(layer);
+        assert!(crate::live_bytes() < HEAVY);
+    }
+
+    #[test]
+    fn insert_derived_takes_the_key_from_the_handle() {
+        #[derive(Debug, Clone, PartialEq)]
+        struct Connection {
+            id: u32,
+        }
+
+        let (builder, _) = manual::<u32, Connection>();
+        let layer = builder.build().unwrap();
+        let key_of = |conn: &Connection| conn.id;
+        assert_eq!(
+            layer.insert_derived(Connection { id: 1 }, key_of).unwrap(),
+            1
+        );
+        assert_eq!(layer.get(&1), Some(Connection { id: 1 }));
+        assert!(matches!(
+            layer.insert_derived(Connection { id: 1 }, key_of),
+            Err(InsertError::Collision(Connection { id: 1 }))
+        ));
+    }
+
+    #[test]
+    fn collect_filtered_clones_only_fresh_matches() {
+        let (builder, clock) = manual::<u32, Arc<u32>>();
+        let layer = builder
+            .resurrect_policy(ResurrectPolicy::Strict)
+            .build()
+            .unwrap();
+        let odd = Arc::new(1);
+        let even = Arc::new(2);
+        let stale = Arc::new(3);
+        layer.insert(3, Arc::clone(&stale)).unwrap();
+        clock.advance(TIMEOUT);
+        layer.insert(1, Arc::clone(&odd)).unwrap();
+        layer.insert(2, Arc::clone(&even)).unwrap();
+        clock.advance(Duration::from_secs(1));
+
+        let is_odd = |_: &u32, session: &Arc<u32>| **session % 2 == 1;
+        let matches = layer.collect_filtered(is_odd);
+        assert_eq!(matches, [Arc::clone(&odd)]);
+        assert_eq!(Arc::strong_count(&even), 2);
+        assert_eq!(layer.collect_filtered_key_values(is_odd), [(1, odd)]);
+        // Nothing was refreshed
+        assert_eq!(layer.idle_time(&1), Some(Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn debug_hides_sessions_and_never_blocks() {
+        let (builder, _) = manual::<String, String>();
+        let layer = builder.build().unwrap();
+        layer
+            .insert("secret-key".to_owned(), "secret-handle".to_owned())
+            .unwrap();
+        let debug = format!("{layer:?}");
+        assert!(debug.contains("len: 1"), "{debug}");
+        assert!(!debug.contains("secret"), "{debug}");
+
+        let key_to_session = layer.key_to_session.write();
+        assert!(format!("{layer:?}").contains("len: locked"));
+        drop(key_to_session);
+    }
+
+    #[test]
+    fn remove_prefix_compares_keys_as_bytes() {
+        let (builder, _) = manual::<String, u32>();
+        let layer = builder.build().unwrap();
+        for (key, session) in [
+            ("tenant:1:a", 1),
+            ("tenant:1:b", 2),
+            ("tenant:12:a", 3),
+            ("é", 4),
+        ] {
+            layer.insert(key.to_owned(), session).unwrap();
+        }
+        assert_eq!(layer.remove_prefix(b"tenant:1:"), 2);
+        assert_eq!(layer.remove_prefix(b"tenant:1:"), 0);
+        assert_eq!(layer.peek("tenant:12:a"), Some(3));
+        // Half of the two bytes of the character
+        assert_eq!(layer.remove_prefix(&"é".as_bytes()[..1]), 1);
+        assert_eq!(layer.len(), 1);
+    }
+
+    #[test]
+    fn debug_dump_formats_up_to_max_entries() {
+        let (builder, _) = manual::<u32, &str>();
+        let layer = builder.build().unwrap();
+        assert_eq!(layer.debug_dump(2), "{ }");
+        layer.insert(1, "a").unwrap();
+        assert_eq!(layer.debug_dump(2), r#"{ 1: "a" }"#);
+        layer.insert(2, "b").unwrap();
+        layer.insert(3, "c").unwrap();
+        let dump = layer.debug_dump(2);
+        assert!(dump.ends_with(", .. 1 more }"), "{dump}");
+        assert_eq!(dump.matches(": ").count(), 2);
+        assert_eq!(layer.debug_dump(0), "{ .. 3 more }");
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct Token(Option<Duration>);
+    impl Expiry for Token {
+        fn expires_in(&self) -> Option<Duration> {
+            self.0
+        }
+    }
+
+    #[test]
+    fn handles_cap_their_own_lifetime() {
+        let lease = Token(Some(Duration::from_secs(3)));
+        let (builder, clock) = manual::<u32, Token>();
+        let layer = builder.respect_handle_expiry(true).build().unwrap();
+        layer.insert(1, lease.clone()).unwrap();
+        layer.insert(2, Token(None)).unwrap();
+        clock.advance(Duration::from_secs(2));
+        // Accesses do not extend the lifetime
+        assert_eq!(
+            layer.refresh(&1),
+            Some((lease.clone(), Duration::from_secs(1)))
+        );
+        assert_eq!(layer.refresh(&2), Some((Token(None), TIMEOUT)));
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(layer.get(&1), None);
+        layer.sweep();
+        assert_eq!(layer.len(), 1);
+
+        let (builder, clock) = manual::<u32, Token>();
+        let layer = builder.respect_handle_expiry(false).build().unwrap();
+        layer.insert(1, lease.clone()).unwrap();
+        clock.advance(Duration::from_secs(3));
+        assert_eq!(layer.get(&1), Some(lease));
+    }
+
+    #[test]
+    fn handle_lifetimes_count_without_a_timeout() {
+        assert_eq!(
+            SessionLayerBuilder::<u32, Token>::unbounded()
+                .respect_handle_expiry(true)
+                .build()
+                .err(),
+            Some(NewSessionLayerError::UnsweptHandleExpiry)
+        );
+
+        let clock = ManualClock::new();
+        let layer = SessionLayerBuilder::unbounded()
+            .manual_sweep()
+            .time_source(TimeSource::Manual(clock.clone()))
+            .respect_handle_expiry(true)
+            .build()
+            .unwrap();
+        let lease = Token(Some(Duration::from_secs(3)));
+        layer.insert(1, lease.clone()).unwrap();
+        layer.insert(2, Token(None)).unwrap();
+        clock.advance(Duration::from_secs(2));
+        assert_eq!(
+            layer.expiring_within(Duration::from_secs(2)),
+            [(1, lease.clone())]
+        );
+        clock.advance(Duration::from_secs(1));
+        layer.sweep();
+        assert_eq!(layer.len(), 1);
+        assert!(layer.expiring_within(Duration::MAX).is_empty());
+    }
+}
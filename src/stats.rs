@@ -0,0 +1,197 @@
+use std::{
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
+
+use crate::hook::Hook;
+
+pub const IDLE_HISTOGRAM_BUCKETS: usize = 8;
+
+/// The shape of the session population at one point in time
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PopulationStats {
+    pub len: usize,
+    /// [`Duration::ZERO`] if there are no sessions
+    pub oldest_idle: Duration,
+    /// [`Duration::ZERO`] if there are no sessions
+    pub newest_idle: Duration,
+    /// [`Duration::ZERO`] if there are no sessions
+    pub mean_idle: Duration,
+    /// Bucket `i` counts sessions idle for `[i * bucket_width, (i + 1) * bucket_width)`
+    ///
+    /// The last bucket also counts sessions that are idle past the timeout but not yet swept.
+    pub idle_histogram: [usize; IDLE_HISTOGRAM_BUCKETS],
+    pub bucket_width: Duration,
+}
+impl PopulationStats {
+    pub(crate) fn from_idle_times(
+        timeout: Duration,
+        idle_times: impl Iterator<Item = Duration>,
+    ) -> Self {
+        let bucket_width = timeout / IDLE_HISTOGRAM_BUCKETS as u32;
+        let mut stats = Self {
+            len: 0,
+            oldest_idle: Duration::ZERO,
+            newest_idle: Duration::MAX,
+            mean_idle: Duration::ZERO,
+            idle_histogram: [0; IDLE_HISTOGRAM_BUCKETS],
+            bucket_width,
+        };
+        let mut total_idle = Duration::ZERO;
+        for idle in idle_times {
+            stats.len += 1;
+            stats.oldest_idle = stats.oldest_idle.max(idle);
+            stats.newest_idle = stats.newest_idle.min(idle);
+            total_idle = total_idle.saturating_add(idle);
+            let bucket = match bucket_width.is_zero() {
+                true => IDLE_HISTOGRAM_BUCKETS - 1,
+                false => (idle.as_nanos() / bucket_width.as_nanos()) as usize,
+            };
+            stats.idle_histogram[bucket.min(IDLE_HISTOGRAM_BUCKETS - 1)] += 1;
+        }
+        if stats.len == 0 {
+            stats.newest_idle = Duration::ZERO;
+        } else {
+            stats.mean_idle = total_idle / stats.len as u32;
+        }
+        stats
+    }
+}
+
+/// The health of the sweeping, e.g. to alert on a background task that is starved or gone
+///
+/// Times are measured by the real clock even under [`crate::TimeSource::Manual`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SweeperStatus {
+    /// [`None`] before the first sweep
+    pub last_sweep_started: Option<Instant>,
+    /// Earlier than [`Self::last_sweep_started`] while a sweep is in progress
+    pub last_sweep_finished: Option<Instant>,
+    /// By the background task and by manual calls alike
+    pub sweeps_completed: u64,
+    /// Whether a background task or a [`crate::SessionLayer::purge_loop`] future is still around to sweep
+    pub task_alive: bool,
+}
+
+/// What a sweep did
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SweepReport {
+    pub removed: usize,
+    pub remaining: usize,
+    pub duration: Duration,
+    /// When the sweep started
+    pub at: Instant,
+}
+
+/// The summed weight of the sessions in all layers, see [`live_bytes`]
+#[cfg(feature = "live-bytes")]
+static LIVE_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+/// The summed weight of the sessions in all layers of the process, e.g. for a dashboard of the memory held by sessions
+///
+/// Only layers with a [`crate::SessionLayerBuilder::weight_budget`] weigh their sessions, so the others count as nothing.
+#[cfg(feature = "live-bytes")]
+pub fn live_bytes() -> usize {
+    LIVE_BYTES.load(Ordering::Relaxed)
+}
+
+/// The peak number of sessions and the alerts on crossing levels of it
+#[derive(Debug, Default)]
+pub(crate) struct Occupancy {
+    high_water: AtomicUsize,
+    thresholds: Vec<Threshold>,
+    /// The share of this layer in [`LIVE_BYTES`]
+    #[cfg(feature = "live-bytes")]
+    weight: AtomicUsize,
+}
+#[derive(Debug)]
+pub(crate) struct Threshold {
+    level: usize,
+    /// Whether the next crossing fires
+    armed: AtomicBool,
+    hook: Hook<ThresholdHook>,
+}
+pub(crate) type ThresholdHook = dyn Fn(usize) + Send + Sync;
+impl Threshold {
+    pub fn new(level: usize, hook: Hook<ThresholdHook>) -> Self {
+        Self {
+            level,
+            armed: AtomicBool::new(true),
+            hook,
+        }
+    }
+}
+impl Occupancy {
+    pub fn new(thresholds: Vec<Threshold>) -> Self {
+        Self {
+            high_water: AtomicUsize::new(0),
+            thresholds,
+            #[cfg(feature = "live-bytes")]
+            weight: AtomicUsize::new(0),
+        }
+    }
+
+    /// Called with the number and the summed weight of the sessions after every write
+    #[cfg_attr(not(feature = "live-bytes"), allow(unused_variables))]
+    pub fn observe(&self, len: usize, weight: usize) {
+        #[cfg(feature = "live-bytes")]
+        {
+            // Writes to one layer are serialized by its lock, and the difference may be negative
+            let old = self.weight.swap(weight, Ordering::Relaxed);
+            LIVE_BYTES.fetch_add(weight.wrapping_sub(old), Ordering::Relaxed);
+        }
+        self.high_water.fetch_max(len, Ordering::Relaxed);
+        for threshold in &self.thresholds {
+            if threshold.level <= len {
+                if threshold.armed.swap(false, Ordering::Relaxed) {
+                    (threshold.hook)(len);
+                }
+            } else if len * 10 < threshold.level * 9 {
+                // Below 90% of the level, so hovering around it does not fire repeatedly
+                threshold.armed.store(true, Ordering::Relaxed);
+            }
+        }
+    }
+
+    pub fn high_water(&self) -> usize {
+        self.high_water.load(Ordering::Relaxed)
+    }
+
+    pub fn reset_high_water(&self, len: usize) {
+        self.high_water.store(len, Ordering::Relaxed);
+    }
+}
+#[cfg(feature = "live-bytes")]
+impl Drop for Occupancy {
+    fn drop(&mut self) {
+        LIVE_BYTES.fetch_sub(*self.weight.get_mut(), Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn idle_times_are_bucketed_by_an_eighth_of_the_timeout() {
+        let secs = |secs| Duration::from_secs(secs);
+        let stats = PopulationStats::from_idle_times(
+            secs(8),
+            [secs(0), secs(1), secs(3), secs(20)].into_iter(),
+        );
+        assert_eq!(stats.len, 4);
+        assert_eq!(stats.oldest_idle, secs(20));
+        assert_eq!(stats.newest_idle, secs(0));
+        assert_eq!(stats.mean_idle, secs(6));
+        assert_eq!(stats.bucket_width, secs(1));
+        assert_eq!(stats.idle_histogram, [1, 1, 0, 1, 0, 0, 0, 1]);
+    }
+
+    #[test]
+    fn empty_population_reports_zeros() {
+        let stats = PopulationStats::from_idle_times(Duration::from_secs(8), std::iter::empty());
+        assert_eq!(stats.len, 0);
+        assert_eq!(stats.newest_idle, Duration::ZERO);
+        assert_eq!(stats.mean_idle, Duration::ZERO);
+    }
+}
@@ -0,0 +1,112 @@
+use std::{
+    collections::VecDeque,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures_core::Stream;
+
+use crate::session::{ScanCursor, SessionLayer};
+
+/// How many sessions a [`SessionStream`] clones out per read lock
+const CHUNK_SIZE: usize = 256;
+
+/// Sessions yielded chunk by chunk via [`SessionLayer::scan`]
+///
+/// The read lock is only held while a chunk is fetched, and the next chunk is not fetched until the consumer drains the current one.
+/// Sessions inserted or removed during the stream may or may not be yielded.
+#[derive(Debug)]
+pub struct SessionStream<'a, SessionKey, SessionHandle, Metadata, Item> {
+    layer: &'a SessionLayer<SessionKey, SessionHandle, Metadata>,
+    /// [`None`] once the last chunk is fetched
+    cursor: Option<ScanCursor>,
+    chunk: VecDeque<(SessionKey, SessionHandle)>,
+    project: fn(SessionKey, SessionHandle) -> Item,
+}
+impl<'a, SessionKey, SessionHandle, Metadata, Item>
+    SessionStream<'a, SessionKey, SessionHandle, Metadata, Item>
+{
+    pub(crate) fn new(
+        layer: &'a SessionLayer<SessionKey, SessionHandle, Metadata>,
+        project: fn(SessionKey, SessionHandle) -> Item,
+    ) -> Self {
+        Self {
+            layer,
+            cursor: Some(ScanCursor::start()),
+            chunk: VecDeque::new(),
+            project,
+        }
+    }
+}
+impl<SessionKey, SessionHandle, Metadata, Item> Unpin
+    for SessionStream<'_, SessionKey, SessionHandle, Metadata, Item>
+{
+}
+impl<SessionKey, SessionHandle, Metadata, Item> Stream
+    for SessionStream<'_, SessionKey, SessionHandle, Metadata, Item>
+where
+    SessionKey: std::fmt::Debug + std::hash::Hash + Eq + Clone + Sync + Send + 'static,
+    SessionHandle: std::fmt::Debug + Clone + Sync + Send + 'static,
+    Metadata: Default + Sync + Send + 'static,
+{
+    type Item = Item;
+
+    fn poll_next(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            if let Some((key, session)) = this.chunk.pop_front() {
+                return Poll::Ready(Some((this.project)(key, session)));
+            }
+            let Some(cursor) = this.cursor.take() else {
+                return Poll::Ready(None);
+            };
+            let (chunk, next) = this.layer.scan(cursor, CHUNK_SIZE);
+            this.chunk.extend(chunk);
+            this.cursor = next;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::task::Waker;
+
+    use super::*;
+    use crate::MutSessionLayer;
+
+    /// Every item of a stream that never pends
+    fn collect<S: Stream + Unpin>(mut stream: S) -> Vec<S::Item> {
+        let mut cx = Context::from_waker(Waker::noop());
+        std::iter::from_fn(|| match Pin::new(&mut stream).poll_next(&mut cx) {
+            Poll::Ready(item) => item,
+            Poll::Pending => unreachable!("sessions are fetched synchronously"),
+        })
+        .collect()
+    }
+
+    #[test]
+    fn stream_yields_every_session_across_chunks() {
+        let layer = SessionLayer::<u32, u32>::new_unbounded();
+        let count = CHUNK_SIZE as u32 * 2 + 1;
+        for key in 0..count {
+            layer.insert(key, key * 10).unwrap();
+        }
+        let mut sessions = collect(layer.stream());
+        sessions.sort();
+        assert_eq!(
+            sessions,
+            (0..count).map(|key| (key, key * 10)).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn stream_keys_of_mutable_sessions() {
+        let layer = MutSessionLayer::<u32, u32>::new_unbounded();
+        assert!(collect(layer.stream_keys()).is_empty());
+        layer.insert(1, 10).unwrap();
+        layer.insert(2, 20).unwrap();
+        let mut keys = collect(layer.stream_keys());
+        keys.sort();
+        assert_eq!(keys, [1, 2]);
+    }
+}
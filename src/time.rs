@@ -0,0 +1,173 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::lock::Mutex;
+
+/// The clock that idle times and ages are measured against
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum TimeSource {
+    /// [`Instant`]
+    ///
+    /// Immune to clock adjustments, but pauses while the machine is suspended on some platforms, so idle sessions outlive their timeout by the suspended duration.
+    #[default]
+    Monotonic,
+    /// [`SystemTime`]
+    ///
+    /// Keeps counting during suspension and matches timestamps persisted outside the process.
+    /// A session last accessed in the future of a clock that went backwards is treated as fresh rather than expired.
+    Wall,
+    /// [`ManualClock`]
+    ///
+    /// Only moves when advanced, for deterministic tests.
+    Manual(ManualClock),
+}
+impl TimeSource {
+    pub(crate) fn now(&self) -> Timestamp {
+        match self {
+            TimeSource::Monotonic => Timestamp {
+                instant: Instant::now(),
+                wall: None,
+            },
+            TimeSource::Wall => Timestamp {
+                instant: Instant::now(),
+                wall: Some(SystemTime::now()),
+            },
+            TimeSource::Manual(clock) => Timestamp {
+                instant: clock.now(),
+                wall: None,
+            },
+        }
+    }
+
+    /// The timestamp of a past or future `instant`
+    ///
+    /// In [`TimeSource::Wall`], the wall-clock time is extrapolated from the current one.
+    pub(crate) fn at(&self, instant: Instant) -> Timestamp {
+        let now = self.now();
+        let wall = now
+            .wall
+            .map(|wall| match now.instant.checked_duration_since(instant) {
+                Some(ago) => wall.checked_sub(ago).unwrap_or(SystemTime::UNIX_EPOCH),
+                None => wall + instant.duration_since(now.instant),
+            });
+        Timestamp { instant, wall }
+    }
+}
+
+/// A clock for tests that stands still until [`Self::advance`] is called
+///
+/// Clones share the same time.
+#[derive(Debug, Clone)]
+pub struct ManualClock(Arc<ManualClockState>);
+#[derive(Debug)]
+struct ManualClockState {
+    origin: Instant,
+    elapsed: Mutex<Duration>,
+}
+impl ManualClock {
+    pub fn new() -> Self {
+        Self(Arc::new(ManualClockState {
+            origin: Instant::now(),
+            elapsed: Mutex::new(Duration::ZERO),
+        }))
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        *self.0.elapsed.lock() += duration;
+    }
+
+    /// The creation time of the clock plus all advances so far
+    ///
+    /// Unrelated to [`Instant::now`] except for the starting point.
+    pub fn now(&self) -> Instant {
+        self.0.origin + *self.0.elapsed.lock()
+    }
+}
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl PartialEq for ManualClock {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+impl Eq for ManualClock {}
+
+/// A point in time read from a [`TimeSource`]
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Timestamp {
+    /// Always read so that events can carry an [`Instant`] in either mode
+    instant: Instant,
+    /// Only read in [`TimeSource::Wall`]
+    wall: Option<SystemTime>,
+}
+impl Timestamp {
+    pub fn instant(&self) -> Instant {
+        self.instant
+    }
+
+    /// Zero if `earlier` is actually later
+    pub fn saturating_duration_since(&self, earlier: Timestamp) -> Duration {
+        match (self.wall, earlier.wall) {
+            (Some(now), Some(earlier)) => now.duration_since(earlier).unwrap_or_default(),
+            _ => self.instant.saturating_duration_since(earlier.instant),
+        }
+    }
+
+    pub fn checked_sub(&self, duration: Duration) -> Option<Timestamp> {
+        let wall = match self.wall {
+            Some(wall) => Some(wall.checked_sub(duration)?),
+            None => None,
+        };
+        Some(Timestamp {
+            instant: self.instant.checked_sub(duration)?,
+            wall,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wall_clock_going_backwards_reads_as_fresh() {
+        let now = TimeSource::Wall.now();
+        let later = Timestamp {
+            wall: now.wall.map(|wall| wall + Duration::from_secs(60)),
+            ..now
+        };
+        assert_eq!(now.saturating_duration_since(later), Duration::ZERO);
+        assert_eq!(
+            later.saturating_duration_since(now),
+            Duration::from_secs(60)
+        );
+    }
+
+    #[test]
+    fn wall_clock_is_extrapolated_for_other_instants() {
+        let now = TimeSource::Wall.now();
+        let ago = Duration::from_secs(5);
+        let past = TimeSource::Wall.at(now.instant() - ago);
+        let elapsed = now.saturating_duration_since(past);
+        assert!(elapsed.abs_diff(ago) < Duration::from_secs(1));
+        assert!(TimeSource::Monotonic.at(now.instant()).wall.is_none());
+    }
+
+    #[test]
+    fn manual_clock_only_moves_when_advanced() {
+        let clock = ManualClock::new();
+        let source = TimeSource::Manual(clock.clone());
+        let start = source.now();
+        assert_eq!(source.now().instant(), start.instant());
+        clock.advance(Duration::from_secs(3));
+        assert_eq!(
+            source.now().saturating_duration_since(start),
+            Duration::from_secs(3)
+        );
+    }
+}
@@ -0,0 +1,87 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    time::Duration,
+};
+
+use crate::{lock::Mutex, time::Timestamp};
+
+/// Keys removed recently, remembered only by their hashes to stay small
+///
+/// Bounded both in number, dropping the oldest first, and in age.
+#[derive(Debug)]
+pub(crate) struct Tombstones {
+    capacity: usize,
+    retention: Duration,
+    graves: Mutex<Graves>,
+}
+#[derive(Debug, Default)]
+struct Graves {
+    /// The latest removal of each key hash along with its generation
+    removed_at: HashMap<u64, (Timestamp, u64)>,
+    /// Oldest first, including removals superseded by a later one of the same key hash
+    order: VecDeque<(u64, u64)>,
+    next_generation: u64,
+}
+impl Graves {
+    fn pop_oldest(&mut self) {
+        let Some((hash, generation)) = self.order.pop_front() else {
+            return;
+        };
+        if self
+            .removed_at
+            .get(&hash)
+            .is_some_and(|&(_, latest)| latest == generation)
+        {
+            self.removed_at.remove(&hash);
+        }
+    }
+}
+impl Tombstones {
+    /// `capacity` must be positive
+    pub fn new(capacity: usize, retention: Duration) -> Self {
+        Self {
+            capacity,
+            retention,
+            graves: Mutex::new(Graves::default()),
+        }
+    }
+
+    pub fn bury(&self, hashes: impl IntoIterator<Item = u64>, now: Timestamp) {
+        let mut graves = self.graves.lock();
+        for hash in hashes {
+            while self.capacity <= graves.removed_at.len() || self.capacity <= graves.order.len() {
+                graves.pop_oldest();
+            }
+            let generation = graves.next_generation;
+            graves.next_generation += 1;
+            graves.removed_at.insert(hash, (now, generation));
+            graves.order.push_back((hash, generation));
+        }
+    }
+
+    /// How long ago the key was removed, if within the retention
+    pub fn since(&self, hash: u64, now: Timestamp) -> Option<Duration> {
+        let graves = self.graves.lock();
+        let &(removed_at, _) = graves.removed_at.get(&hash)?;
+        let since = now.saturating_duration_since(removed_at);
+        (since < self.retention).then_some(since)
+    }
+
+    /// Forget removals past the retention
+    pub fn sweep(&self, now: Timestamp) {
+        let mut graves = self.graves.lock();
+        while let Some(&(hash, generation)) = graves.order.front() {
+            let expired = match graves.removed_at.get(&hash) {
+                Some(&(removed_at, latest)) if latest == generation => {
+                    self.retention <= now.saturating_duration_since(removed_at)
+                }
+                // Superseded by a later removal
+                _ => true,
+            };
+            if !expired {
+                break;
+            }
+            graves.pop_oldest();
+        }
+    }
+}
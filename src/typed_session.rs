@@ -0,0 +1,218 @@
+use std::{
+    any::{Any, TypeId},
+    borrow::Borrow,
+    collections::HashMap,
+    sync::Arc,
+    time::Duration,
+};
+
+use crate::{builder::NewSessionLayerError, lock::RwLock, session::SessionLayer};
+
+use tokio::sync::{Mutex as TokioMutex, OwnedMutexGuard};
+
+/// Store components of different types per key under one shared idle timer
+///
+/// Replaces one [`crate::MutSessionLayer`] per component type with a single layer and a single background task.
+/// Accessing any component refreshes the key, and expiry drops all of its components together.
+/// Components inserted by [`Self::insert`] are shared read-only through [`Self::get`], while those inserted by [`Self::insert_mut`] are locked through [`Self::get_mut`].
+#[derive(Debug)]
+pub struct TypedSessionLayer<SessionKey> {
+    session: Arc<SessionLayer<SessionKey, Components>>,
+}
+impl<SessionKey> TypedSessionLayer<SessionKey>
+where
+    SessionKey: Eq + std::hash::Hash + Clone + Sync + Send + 'static,
+{
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            session: SessionLayer::new(timeout),
+        }
+    }
+
+    pub fn try_new(timeout: Duration) -> Result<Self, NewSessionLayerError> {
+        Ok(Self {
+            session: SessionLayer::try_new(timeout)?,
+        })
+    }
+
+    /// Sessions never expire and no background task is spawned
+    pub fn new_unbounded() -> Self {
+        Self {
+            session: SessionLayer::new_unbounded(),
+        }
+    }
+
+    /// The number of keys, regardless of how many components each has
+    pub fn len(&self) -> usize {
+        self.session.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.session.is_empty()
+    }
+}
+impl<SessionKey> TypedSessionLayer<SessionKey>
+where
+    SessionKey: std::fmt::Debug + Clone + Eq + std::hash::Hash + Sync + Send + 'static,
+{
+    /// Return [`None`] if the key is not found or has no read-only component of type `T`
+    pub fn get<T, Q>(&self, key: &Q) -> Option<Arc<T>>
+    where
+        T: Any + Sync + Send,
+        SessionKey: Borrow<Q>,
+        Q: ?Sized + Eq + std::hash::Hash,
+    {
+        self.session.get(key)?.get::<T>()
+    }
+
+    /// Return [`None`] if the key is not found or has no mutable component of type `T`
+    pub async fn get_mut<T, Q>(&self, key: &Q) -> Option<OwnedMutexGuard<T>>
+    where
+        T: Any + Sync + Send,
+        SessionKey: Borrow<Q>,
+        Q: ?Sized + Eq + std::hash::Hash,
+    {
+        let component = self.session.get(key)?.get::<TokioMutex<T>>()?;
+        Some(component.lock_owned().await)
+    }
+
+    /// Add a read-only component to the key, creating the key if it is missing
+    ///
+    /// Return the replaced component of the same type if any.
+    /// The key is refreshed.
+    pub fn insert<T>(&self, key: SessionKey, component: T) -> Option<Arc<T>>
+    where
+        T: Any + Sync + Send,
+    {
+        self.components(key).insert(Arc::new(component))
+    }
+
+    /// Add a component for [`Self::get_mut`] to the key, creating the key if it is missing
+    ///
+    /// Return the replaced component of the same type if any.
+    /// Guards already handed out for it remain valid.
+    pub fn insert_mut<T>(&self, key: SessionKey, component: T) -> Option<Arc<TokioMutex<T>>>
+    where
+        T: Any + Sync + Send,
+    {
+        self.components(key)
+            .insert(Arc::new(TokioMutex::new(component)))
+    }
+
+    /// Drop only the component of type `T` and keep the others
+    ///
+    /// The key stays even if it has no components left.
+    pub fn remove_component<T, Q>(&self, key: &Q) -> Option<Arc<T>>
+    where
+        T: Any + Sync + Send,
+        SessionKey: Borrow<Q>,
+        Q: ?Sized + Eq + std::hash::Hash,
+    {
+        self.session.peek(key)?.remove::<T>()
+    }
+
+    /// Drop all components of the key
+    ///
+    /// Return whether the key was found.
+    pub fn remove<Q>(&self, key: &Q) -> bool
+    where
+        SessionKey: Borrow<Q>,
+        Q: ?Sized + Eq + std::hash::Hash,
+    {
+        self.session.remove(key).is_some()
+    }
+
+    fn components(&self, key: SessionKey) -> Components {
+        self.session.get_or_insert_with(key, Components::default)
+    }
+}
+
+/// Satisfy any bounds that [`SessionLayer`] requires
+#[derive(Default)]
+struct Components(Arc<RwLock<HashMap<TypeId, Arc<dyn Any + Sync + Send>>>>);
+impl Components {
+    fn get<T: Any + Sync + Send>(&self) -> Option<Arc<T>> {
+        let component = Arc::clone(self.0.read().get(&TypeId::of::<T>())?);
+        Some(downcast(component))
+    }
+
+    fn insert<T: Any + Sync + Send>(&self, component: Arc<T>) -> Option<Arc<T>> {
+        let replaced = self.0.write().insert(TypeId::of::<T>(), component)?;
+        Some(downcast(replaced))
+    }
+
+    fn remove<T: Any + Sync + Send>(&self) -> Option<Arc<T>> {
+        let removed = self.0.write().remove(&TypeId::of::<T>())?;
+        Some(downcast(removed))
+    }
+}
+impl Clone for Components {
+    fn clone(&self) -> Self {
+        Self(Arc::clone(&self.0))
+    }
+}
+impl std::fmt::Debug for Components {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Components")
+            .field("len", &self.0.read().len())
+            .finish()
+    }
+}
+
+/// Components are only ever stored under their own [`TypeId`]
+fn downcast<T: Any + Sync + Send>(component: Arc<dyn Any + Sync + Send>) -> Arc<T> {
+    match component.downcast() {
+        Ok(component) => component,
+        Err(_) => unreachable!("component stored under the type ID of another type"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    struct Name(&'static str);
+
+    #[test]
+    fn components_of_each_type_live_side_by_side() {
+        let layer = TypedSessionLayer::new_unbounded();
+        assert!(layer.insert(1, Name("a")).is_none());
+        layer.insert(1, 7_u32);
+        assert_eq!(layer.len(), 1);
+        assert_eq!(layer.get::<Name, _>(&1).as_deref(), Some(&Name("a")));
+        assert_eq!(layer.get::<u32, _>(&1).as_deref(), Some(&7));
+        assert!(layer.get::<u64, _>(&1).is_none());
+        assert!(layer.get::<u32, _>(&2).is_none());
+
+        let replaced = layer.insert(1, Name("b"));
+        assert_eq!(replaced.as_deref(), Some(&Name("a")));
+        assert_eq!(layer.remove_component::<u32, _>(&1).as_deref(), Some(&7));
+        assert!(layer.get::<u32, _>(&1).is_none());
+        assert_eq!(layer.get::<Name, _>(&1).as_deref(), Some(&Name("b")));
+        assert!(layer.remove(&1));
+        assert!(layer.is_empty());
+    }
+
+    #[test]
+    fn mutable_components_are_locked_apart_from_read_only_ones() {
+        let layer = TypedSessionLayer::new_unbounded();
+        layer.insert(1, 10_u32);
+        layer.insert_mut(1, vec!["first"]);
+        tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+            .block_on(async {
+                layer
+                    .get_mut::<Vec<&str>, _>(&1)
+                    .await
+                    .unwrap()
+                    .push("second");
+                // Read-only and mutable components of the same type are distinct
+                assert!(layer.get_mut::<u32, _>(&1).await.is_none());
+                assert!(layer.get::<Vec<&str>, _>(&1).is_none());
+                let guard = layer.get_mut::<Vec<&str>, _>(&1).await.unwrap();
+                assert_eq!(*guard, ["first", "second"]);
+            });
+    }
+}
@@ -0,0 +1,124 @@
+use std::{borrow::Borrow, sync::Arc};
+
+use crate::session::SessionLayer;
+
+/// A [`SessionLayer`] that can only be read, for code that must never insert or evict sessions
+///
+/// Obtained via [`SessionLayer::read_only`].
+///
+/// Writes do not compile:
+///
+/// ```compile_fail
+/// let layer = session::SessionLayer::<u32, u32>::new_unbounded();
+/// layer.read_only().insert(1, 10);
+/// ```
+#[derive(Debug)]
+pub struct ReadOnlyView<SessionKey, SessionHandle, Metadata = ()> {
+    session: Arc<SessionLayer<SessionKey, SessionHandle, Metadata>>,
+}
+impl<SessionKey, SessionHandle, Metadata> ReadOnlyView<SessionKey, SessionHandle, Metadata> {
+    pub(crate) fn new(session: Arc<SessionLayer<SessionKey, SessionHandle, Metadata>>) -> Self {
+        Self { session }
+    }
+}
+impl<SessionKey, SessionHandle, Metadata> Clone
+    for ReadOnlyView<SessionKey, SessionHandle, Metadata>
+{
+    fn clone(&self) -> Self {
+        Self::new(Arc::clone(&self.session))
+    }
+}
+impl<SessionKey, SessionHandle, Metadata> ReadOnlyView<SessionKey, SessionHandle, Metadata>
+where
+    SessionKey: Eq + std::hash::Hash + Clone + Sync + Send + 'static,
+    SessionHandle: Sync + Send + 'static,
+    Metadata: Sync + Send + 'static,
+{
+    pub fn len(&self) -> usize {
+        self.session.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.session.is_empty()
+    }
+}
+impl<SessionKey, SessionHandle, Metadata> ReadOnlyView<SessionKey, SessionHandle, Metadata>
+where
+    SessionKey: std::fmt::Debug + std::hash::Hash + Eq + Clone + Sync + Send + 'static,
+    SessionHandle: std::fmt::Debug + Clone + Sync + Send + 'static,
+    Metadata: Default + Sync + Send + 'static,
+{
+    /// Refresh and clone out the session handle
+    pub fn get<Q>(&self, key: &Q) -> Option<SessionHandle>
+    where
+        SessionKey: Borrow<Q>,
+        Q: ?Sized + Eq + std::hash::Hash,
+    {
+        self.session.get(key)
+    }
+
+    /// Clone out the session handle without refreshing it
+    pub fn peek<Q>(&self, key: &Q) -> Option<SessionHandle>
+    where
+        SessionKey: Borrow<Q>,
+        Q: ?Sized + Eq + std::hash::Hash,
+    {
+        self.session.peek(key)
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        SessionKey: Borrow<Q>,
+        Q: ?Sized + Eq + std::hash::Hash,
+    {
+        self.session.contains_key(key)
+    }
+
+    pub fn keys(&self) -> Vec<SessionKey> {
+        self.session.keys()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{
+        session::tests::{idle, layer},
+        SessionLayer,
+    };
+
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    #[test]
+    fn view_sees_writes_through_the_layer() {
+        let layer = SessionLayer::<u32, u32>::new_unbounded();
+        let view = layer.read_only();
+        assert!(view.is_empty());
+        layer.insert(1, 10).unwrap();
+        assert_eq!(view.len(), 1);
+        assert!(view.contains_key(&1));
+        assert_eq!(view.keys(), [1]);
+        layer.remove(&1);
+        assert_eq!(view.clone().get(&1), None);
+    }
+
+    #[test]
+    fn only_get_refreshes() {
+        let (_runtime, layer) = layer::<u32, u32>();
+        let view = layer.read_only();
+        layer.insert(1, 10).unwrap();
+        layer.insert(2, 20).unwrap();
+        for key in [1, 2] {
+            idle(&layer, &key, TIMEOUT / 2);
+        }
+        assert_eq!(view.get(&1), Some(10));
+        assert_eq!(view.peek(&2), Some(20));
+        for key in [1, 2] {
+            idle(&layer, &key, TIMEOUT / 2 + Duration::from_millis(1));
+        }
+        layer.sweep();
+        assert_eq!(view.peek(&1), Some(10));
+        assert_eq!(view.peek(&2), None);
+    }
+}
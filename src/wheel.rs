@@ -0,0 +1,70 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use crate::{lock::Mutex, time::Timestamp};
+
+/// Keys bucketed by the tick their sessions are due to expire at, so that a sweep visits only the due ones
+///
+/// Accesses do not move keys: a sweep reschedules a due key whose session was accessed since, so a session busy for a long time is visited once per timeout rather than once per access.
+/// Keys of removed sessions stay until due and are then skipped.
+/// Ticks are counted on the configured [`crate::TimeSource`], the same clock that deadlines are measured against.
+#[derive(Debug)]
+pub(crate) struct ExpiryWheel<K> {
+    origin: Timestamp,
+    granularity: Duration,
+    buckets: Mutex<BTreeMap<u64, Vec<K>>>,
+}
+impl<K> ExpiryWheel<K> {
+    /// `granularity` must be positive
+    pub fn new(origin: Timestamp, granularity: Duration) -> Self {
+        Self {
+            origin,
+            granularity,
+            buckets: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// The first tick at or after `after` from `now`, so a key is never due before its session
+    pub fn tick_of(&self, now: Timestamp, after: Duration) -> u64 {
+        let since = now
+            .saturating_duration_since(self.origin)
+            .saturating_add(after);
+        since.as_nanos().div_ceil(self.granularity.as_nanos()) as u64
+    }
+
+    pub fn schedule(&self, key: K, tick: u64) {
+        self.buckets.lock().entry(tick).or_default().push(key);
+    }
+
+    /// Take the buckets of the ticks passed by `now`
+    pub fn take_due(&self, now: Timestamp) -> BTreeMap<u64, Vec<K>> {
+        let since = now.saturating_duration_since(self.origin);
+        let due = (since.as_nanos() / self.granularity.as_nanos()) as u64;
+        let mut buckets = self.buckets.lock();
+        let later = buckets.split_off(&(due + 1));
+        std::mem::replace(&mut *buckets, later)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::time::{ManualClock, TimeSource};
+
+    #[test]
+    fn keys_are_due_no_earlier_than_their_deadline() {
+        let clock = ManualClock::new();
+        let time = TimeSource::Manual(clock.clone());
+        let wheel = ExpiryWheel::new(time.now(), Duration::from_secs(1));
+        let tick = wheel.tick_of(time.now(), Duration::from_millis(1500));
+        assert_eq!(tick, 2);
+        wheel.schedule(1, tick);
+        wheel.schedule(2, wheel.tick_of(time.now(), Duration::from_secs(3)));
+        clock.advance(Duration::from_millis(1999));
+        assert!(wheel.take_due(time.now()).is_empty());
+        clock.advance(Duration::from_millis(1));
+        assert_eq!(wheel.take_due(time.now()), BTreeMap::from([(2, vec![1])]));
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(wheel.take_due(time.now()), BTreeMap::from([(3, vec![2])]));
+        assert!(wheel.take_due(time.now()).is_empty());
+    }
+}